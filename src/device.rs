@@ -0,0 +1,346 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Filesystems whose contents are generated by the kernel instead of being stored anywhere.
+#[cfg(target_os = "linux")]
+const PSEUDO_FILESYSTEMS: &[&str] = &[
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "proc",
+    "pstore",
+    "securityfs",
+    "selinuxfs",
+    "sysfs",
+    "tracefs",
+];
+
+#[cfg(unix)]
+pub fn device_of(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+pub fn device_of(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+pub fn is_block_device(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    metadata.file_type().is_block_device()
+}
+
+#[cfg(not(unix))]
+pub fn is_block_device(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// The capacity of the block device at the given path, which has to be opened for this.
+#[cfg(target_os = "linux")]
+pub fn device_capacity(path: &Path) -> io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    // _IOR(0x12, 114, size_t) from <linux/fs.h>, the direction bits are different on a few
+    // architectures
+    #[cfg(not(any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc64"
+    )))]
+    const IOC_READ: usize = 2 << 30;
+    #[cfg(any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc64"
+    ))]
+    const IOC_READ: usize = 2 << 29;
+    const BLKGETSIZE64: usize = IOC_READ | (std::mem::size_of::<usize>() << 16) | (0x12 << 8) | 114;
+
+    let file = std::fs::File::open(path)?;
+    let mut capacity: u64 = 0;
+    // SAFETY: BLKGETSIZE64 writes a u64 to the given pointer
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), BLKGETSIZE64 as _, &mut capacity) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(capacity)
+}
+
+/// The capacity of the block device at the given path, as far as seeking to its end tells.
+#[cfg(not(target_os = "linux"))]
+pub fn device_capacity(path: &Path) -> io::Result<u64> {
+    use std::io::{Seek, SeekFrom};
+    std::fs::File::open(path)?.seek(SeekFrom::End(0))
+}
+
+/// Describes every device by its mount point and filesystem type, like `/home (ext4)`. Devices
+/// that are not found in the mount table are described by their number.
+pub fn device_names(devices: impl Iterator<Item = u64>) -> HashMap<u64, String> {
+    let mounts = mounts();
+    devices
+        .map(|device| {
+            let name = match mounts.get(&device) {
+                Some((mount_point, fs_type)) => format!("{} ({})", mount_point, fs_type),
+                None => format!("device {}", device),
+            };
+            (device, name)
+        })
+        .collect()
+}
+
+/// The devices of all pseudo-filesystems like `/proc` and `/sys`, and of the tmpfs mounts below
+/// `/run` that only hold runtime data of the system.
+#[cfg(target_os = "linux")]
+pub fn special_devices() -> HashSet<u64> {
+    mounts()
+        .into_iter()
+        .filter(|(_, (mount_point, fs_type))| {
+            PSEUDO_FILESYSTEMS.contains(&fs_type.as_str())
+                || (fs_type == "tmpfs"
+                    && (mount_point == "/run" || mount_point.starts_with("/run/")))
+        })
+        .map(|(device, _)| device)
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn special_devices() -> HashSet<u64> {
+    HashSet::new()
+}
+
+/// Finds the bind mounts below the given directories that show a directory again which is also
+/// contained in one of the directories (or in another such mount), so that it does not have to be
+/// walked twice. Returns the path of every such mount point, spelled as a path below the given
+/// directory, together with the other place its contents can be found at.
+#[cfg(target_os = "linux")]
+pub fn bind_mounts(dirs: &[PathBuf]) -> HashMap<PathBuf, PathBuf> {
+    let dirs: Vec<(PathBuf, PathBuf)> = dirs
+        .iter()
+        .filter_map(|dir| Some((dir.clone(), std::fs::canonicalize(dir).ok()?)))
+        .collect();
+    if dirs.is_empty() {
+        return HashMap::new();
+    }
+    duplicate_mounts(&mount_table(), &dirs)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_mounts(_dirs: &[PathBuf]) -> HashMap<PathBuf, PathBuf> {
+    HashMap::new()
+}
+
+/// An entry of `/proc/self/mountinfo`.
+#[cfg(target_os = "linux")]
+struct Mount {
+    id: u64,
+    device: u64,
+    /// The directory of the filesystem that is mounted, which is not `/` for bind mounts
+    root: PathBuf,
+    mount_point: PathBuf,
+    fs_type: String,
+}
+
+/// See `bind_mounts`, the directories are paired with their canonical paths.
+///
+/// Two bind mounts of the same directory would be duplicates of each other, so a mount only
+/// counts as a duplicate of a mount that shows a larger part of the filesystem, or the same part
+/// but was mounted earlier.
+#[cfg(target_os = "linux")]
+fn duplicate_mounts(mounts: &[Mount], dirs: &[(PathBuf, PathBuf)]) -> HashMap<PathBuf, PathBuf> {
+    let order = |mount: &Mount| (mount.root.components().count(), mount.id);
+    let walked = |path: &Path| {
+        dirs.iter()
+            .any(|(_, canonical)| path.starts_with(canonical))
+    };
+
+    let mut duplicates = HashMap::new();
+    for mount in mounts {
+        let below = dirs.iter().find(|(_, canonical)| {
+            mount.mount_point.starts_with(canonical) && mount.mount_point != *canonical
+        });
+        let (dir, canonical) = match below {
+            Some(dir) => dir,
+            None => continue,
+        };
+        let original = mounts
+            .iter()
+            .filter(|other| other.device == mount.device && order(other) < order(mount))
+            .filter_map(|other| {
+                let relative = mount.root.strip_prefix(&other.root).ok()?;
+                Some(other.mount_point.join(relative))
+            })
+            .find(|original| *original != mount.mount_point && walked(original));
+        if let Some(original) = original {
+            let relative = mount.mount_point.strip_prefix(canonical).unwrap();
+            duplicates.insert(dir.join(relative), original);
+        }
+    }
+    duplicates
+}
+
+/// Reads every mount from `/proc/self/mountinfo`.
+#[cfg(target_os = "linux")]
+fn mount_table() -> Vec<Mount> {
+    let mountinfo = match std::fs::read_to_string("/proc/self/mountinfo") {
+        Ok(mountinfo) => mountinfo,
+        Err(_) => return vec![],
+    };
+
+    let mut mounts = vec![];
+    for line in mountinfo.lines() {
+        // '36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue'
+        let (fields, fs_fields) = match line.split_once(" - ") {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let fields: Vec<&str> = fields.split(' ').collect();
+        let (id, device, root, mount_point) =
+            match (fields.first(), fields.get(2), fields.get(3), fields.get(4)) {
+                (Some(id), Some(device), Some(root), Some(mount_point)) => {
+                    (id, device, unescape(root), unescape(mount_point))
+                }
+                _ => continue,
+            };
+        let id = match id.parse() {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        let device = match device.split_once(':') {
+            Some((major, minor)) => match (major.parse(), minor.parse()) {
+                (Ok(major), Ok(minor)) => libc::makedev(major, minor),
+                _ => continue,
+            },
+            None => continue,
+        };
+        let fs_type = fs_fields.split(' ').next().unwrap_or_default().to_string();
+        mounts.push(Mount {
+            id,
+            device,
+            root: PathBuf::from(root),
+            mount_point: PathBuf::from(mount_point),
+            fs_type,
+        });
+    }
+    mounts
+}
+
+/// The mount point and filesystem type of every device. If a device is mounted more than once,
+/// the shortest mount point is used.
+#[cfg(target_os = "linux")]
+fn mounts() -> HashMap<u64, (String, String)> {
+    let mut mounts: HashMap<u64, (String, String)> = HashMap::new();
+    for mount in mount_table() {
+        let mount_point = mount.mount_point.to_string_lossy().into_owned();
+        let is_shorter = mounts
+            .get(&mount.device)
+            .map_or(true, |(known, _)| mount_point.len() < known.len());
+        if is_shorter {
+            mounts.insert(mount.device, (mount_point, mount.fs_type));
+        }
+    }
+    mounts
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mounts() -> HashMap<u64, (String, String)> {
+    HashMap::new()
+}
+
+/// Mount points in `/proc/self/mountinfo` encode spaces and a few other characters as octal
+/// escapes like `\040`.
+#[cfg(target_os = "linux")]
+fn unescape(field: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let octal = tail
+            .get(..3)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match octal {
+            Some(escaped) if byte == b'\\' => {
+                bytes.push(escaped);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_points_are_unescaped() {
+        assert_eq!(unescape("/mnt/my\\040disk"), "/mnt/my disk");
+        assert_eq!(unescape("/"), "/");
+        assert_eq!(unescape("/a\\b"), "/a\\b");
+    }
+
+    fn mount(id: u64, device: u64, root: &str, mount_point: &str) -> Mount {
+        Mount {
+            id,
+            device,
+            root: PathBuf::from(root),
+            mount_point: PathBuf::from(mount_point),
+            fs_type: "ext4".to_string(),
+        }
+    }
+
+    #[test]
+    fn bind_mounts_of_walked_directories_are_duplicates() {
+        let mounts = [
+            mount(1, 1, "/", "/"),
+            mount(2, 2, "/", "/home"),
+            // The same directory of /home, mounted twice more
+            mount(3, 2, "/alice/data", "/home/bob/data"),
+            mount(4, 2, "/alice/data", "/srv/data"),
+            // Another filesystem that happens to have the same directory
+            mount(5, 3, "/alice/data", "/home/carol/data"),
+        ];
+        let dirs = |dirs: &[&str]| -> Vec<(PathBuf, PathBuf)> {
+            dirs.iter()
+                .map(|dir| (PathBuf::from("."), PathBuf::from(dir)))
+                .collect()
+        };
+
+        let duplicates = duplicate_mounts(&mounts, &dirs(&["/home"]));
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(
+            duplicates.get(Path::new("./bob/data")),
+            Some(&PathBuf::from("/home/alice/data"))
+        );
+
+        // Without /home/alice, the first of the other two mounts is walked
+        let duplicates = duplicate_mounts(&mounts, &dirs(&["/home/bob", "/srv"]));
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(
+            duplicates.get(Path::new("./data")),
+            Some(&PathBuf::from("/home/bob/data"))
+        );
+
+        assert!(duplicate_mounts(&mounts, &dirs(&["/srv"])).is_empty());
+    }
+}
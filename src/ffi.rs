@@ -0,0 +1,221 @@
+//! A C interface to the walk, for use from other languages (e.g. with Python's `ctypes`). It
+//! needs the `ffi` feature, with which the shared library (like `target/release/libdiskus.so`)
+//! is built by
+//!
+//! ```sh
+//! cargo build --release --lib --no-default-features --features ffi
+//! ```
+//!
+//! The functions correspond to these declarations:
+//!
+//! ```c
+//! #include <stdbool.h>
+//! #include <stddef.h>
+//! #include <stdint.h>
+//!
+//! typedef struct DiskusWalk DiskusWalk;
+//!
+//! typedef struct {
+//!     size_t threads;        // 0 for three times the number of CPUs, like the binary
+//!     int size_type;         // DISKUS_DISK_USAGE, DISKUS_APPARENT_SIZE, ...
+//!     bool count_hardlinks;  // count hardlinked files for every path they are found at
+//! } DiskusOptions;
+//!
+//! // The path is not null-terminated, and invalid UTF-8 is replaced by U+FFFD
+//! typedef void (*DiskusCallback)(const uint8_t *path, size_t path_len, uint64_t size,
+//!                                bool is_error, void *userdata);
+//!
+//! #define DISKUS_DISK_USAGE 0
+//! #define DISKUS_APPARENT_SIZE 1
+//! #define DISKUS_INODES 2
+//! #define DISKUS_BLOCKS 3
+//!
+//! #define DISKUS_OK 0
+//! #define DISKUS_ERRORS 1
+//! #define DISKUS_INVALID_ARGUMENT -1
+//! #define DISKUS_WALK_FAILED -2
+//! #define DISKUS_PANICKED -3
+//!
+//! // Returns NULL if the arguments are invalid. `options` may be NULL for the defaults.
+//! DiskusWalk *diskus_walk_new(const char *const *paths, size_t n, const DiskusOptions *options);
+//! // Calls `callback` for every path with its total size, then for every error with size 0
+//! int diskus_walk_run(const DiskusWalk *walk, DiskusCallback callback, void *userdata);
+//! void diskus_walk_free(DiskusWalk *walk);
+//! ```
+//!
+//! Panics never cross the interface, they are returned as `DISKUS_PANICKED` (or `NULL`).
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use crate::filesize::FilesizeType;
+use crate::walk::Walk;
+
+/// `DiskusOptions::size_type` for `FilesizeType::DiskUsage`
+pub const DISKUS_DISK_USAGE: c_int = 0;
+/// `DiskusOptions::size_type` for `FilesizeType::ApparentSize`
+pub const DISKUS_APPARENT_SIZE: c_int = 1;
+/// `DiskusOptions::size_type` for `FilesizeType::Inodes`
+pub const DISKUS_INODES: c_int = 2;
+/// `DiskusOptions::size_type` for `FilesizeType::Blocks`
+pub const DISKUS_BLOCKS: c_int = 3;
+
+/// The walk finished, and every entry could be read
+pub const DISKUS_OK: c_int = 0;
+/// The walk finished, but some entries could not be read. They were passed to the callback.
+pub const DISKUS_ERRORS: c_int = 1;
+/// The walk or the callback is null
+pub const DISKUS_INVALID_ARGUMENT: c_int = -1;
+/// The walk could not be run, see `WalkError`
+pub const DISKUS_WALK_FAILED: c_int = -2;
+/// The walk panicked
+pub const DISKUS_PANICKED: c_int = -3;
+
+/// A walk that was created with `diskus_walk_new`.
+pub struct DiskusWalk {
+    walk: Walk,
+}
+
+/// The options of `diskus_walk_new`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DiskusOptions {
+    /// The number of threads, or 0 for three times the number of CPUs
+    pub threads: usize,
+    /// One of the `DISKUS_*` size types
+    pub size_type: c_int,
+    /// See `Walk::count_hardlinks`
+    pub count_hardlinks: bool,
+}
+
+impl Default for DiskusOptions {
+    fn default() -> DiskusOptions {
+        DiskusOptions {
+            threads: 0,
+            size_type: DISKUS_DISK_USAGE,
+            count_hardlinks: false,
+        }
+    }
+}
+
+/// Called by `diskus_walk_run` with a path (which is not null-terminated), its size, whether it
+/// is an error, and the `userdata` of the call.
+pub type DiskusCallback = extern "C" fn(
+    path: *const u8,
+    path_len: usize,
+    size: u64,
+    is_error: bool,
+    userdata: *mut c_void,
+);
+
+/// Creates a walk of the `n` given paths, or returns null if there are no paths, one of them is
+/// null (or not valid UTF-8 on Windows) or the size type is unknown.
+///
+/// # Safety
+///
+/// `paths` has to point to `n` null-terminated strings, and `options` has to be null or point to
+/// a `DiskusOptions`.
+#[no_mangle]
+pub unsafe extern "C" fn diskus_walk_new(
+    paths: *const *const c_char,
+    n: usize,
+    options: *const DiskusOptions,
+) -> *mut DiskusWalk {
+    let created = panic::catch_unwind(|| {
+        if paths.is_null() || n == 0 {
+            return None;
+        }
+        let roots = slice::from_raw_parts(paths, n)
+            .iter()
+            .map(|&path| {
+                if path.is_null() {
+                    return None;
+                }
+                path_of(CStr::from_ptr(path))
+            })
+            .collect::<Option<Vec<PathBuf>>>()?;
+        let options = options.as_ref().copied().unwrap_or_default();
+        let filesize_type = match options.size_type {
+            DISKUS_DISK_USAGE => FilesizeType::DiskUsage,
+            DISKUS_APPARENT_SIZE => FilesizeType::ApparentSize,
+            DISKUS_INODES => FilesizeType::Inodes,
+            DISKUS_BLOCKS => FilesizeType::Blocks,
+            _ => return None,
+        };
+        let threads = match options.threads {
+            0 => 3 * num_cpus::get(),
+            threads => threads,
+        };
+        let walk =
+            Walk::new(roots, threads, filesize_type).count_hardlinks(options.count_hardlinks);
+        Some(Box::into_raw(Box::new(DiskusWalk { walk })))
+    });
+    created.ok().flatten().unwrap_or(std::ptr::null_mut())
+}
+
+/// Runs the walk, and calls `callback` for every path with its total size first, then for every
+/// error with the path it occurred at and a size of 0. Returns `DISKUS_OK`, `DISKUS_ERRORS` or
+/// one of the negative error codes.
+///
+/// # Safety
+///
+/// `walk` has to be null or come from `diskus_walk_new`, and must not have been freed. The path
+/// passed to the callback is only valid during the call.
+#[no_mangle]
+pub unsafe extern "C" fn diskus_walk_run(
+    walk: *const DiskusWalk,
+    callback: Option<DiskusCallback>,
+    userdata: *mut c_void,
+) -> c_int {
+    let (Some(walk), Some(callback)) = (walk.as_ref(), callback) else {
+        return DISKUS_INVALID_ARGUMENT;
+    };
+    let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+        let Ok(result) = walk.walk.run() else {
+            return DISKUS_WALK_FAILED;
+        };
+        let report = |path: &Path, size, is_error| {
+            let path = path.to_string_lossy();
+            callback(path.as_ptr(), path.len(), size, is_error, userdata);
+        };
+        for entry in &result.entries {
+            report(&entry.path, entry.size, false);
+        }
+        for error in &result.errors {
+            report(error.path(), 0, true);
+        }
+        if result.errors.is_empty() {
+            DISKUS_OK
+        } else {
+            DISKUS_ERRORS
+        }
+    }));
+    ran.unwrap_or(DISKUS_PANICKED)
+}
+
+/// Frees a walk. Null is ignored.
+///
+/// # Safety
+///
+/// `walk` has to be null or come from `diskus_walk_new`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn diskus_walk_free(walk: *mut DiskusWalk) {
+    if !walk.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(walk))));
+    }
+}
+
+#[cfg(unix)]
+fn path_of(path: &CStr) -> Option<PathBuf> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    Some(PathBuf::from(OsStr::from_bytes(path.to_bytes())))
+}
+
+#[cfg(not(unix))]
+fn path_of(path: &CStr) -> Option<PathBuf> {
+    path.to_str().ok().map(PathBuf::from)
+}
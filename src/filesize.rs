@@ -1,23 +1,279 @@
-#[derive(Debug, Clone, Copy)]
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::fs::File;
+use std::fs::Metadata;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FilesizeType {
+    /// The space that is allocated for every entry. Only available on Unix, elsewhere this is the
+    /// same as `ApparentSize`.
     DiskUsage,
+    /// The length of every entry
     ApparentSize,
+    /// The size of the data that is actually stored in every regular file, without the holes of
+    /// sparse files. Every file is opened to find its holes, if that fails this is the same as
+    /// `DiskUsage`.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    DataSize,
+    /// The space of every regular file that is not shared with its APFS clones (copies made with
+    /// `cp -c`), so that shared space is not counted once for every clone. Where the filesystem
+    /// does not report this (before macOS 10.15, or on other filesystems than APFS), this is the
+    /// same as `DiskUsage`.
+    #[cfg(target_os = "macos")]
+    Physical,
+    /// Count every entry as 1, i.e. compute the number of inodes
+    Inodes,
+    /// The number of 512-byte blocks that are allocated for every entry (`st_blocks`), e.g. for
+    /// quotas that are measured in blocks. Where this is not available, it is the apparent size
+    /// rounded up to whole blocks.
+    Blocks,
 }
 
 impl FilesizeType {
-    #[cfg(not(windows))]
-    pub fn size(self, metadata: &std::fs::Metadata) -> u64 {
-        use std::os::unix::fs::MetadataExt;
-
+    /// The size of an entry, as far as it can be computed from its metadata alone (see
+    /// `FilesizeType::size_of`).
+    pub fn size(self, metadata: &Metadata) -> u64 {
         match self {
             FilesizeType::ApparentSize => metadata.len(),
-            // block size is always 512 byte, see stat(2) manpage
-            FilesizeType::DiskUsage => metadata.blocks() * 512,
+            FilesizeType::DiskUsage => disk_usage(metadata),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            FilesizeType::DataSize => disk_usage(metadata),
+            #[cfg(target_os = "macos")]
+            FilesizeType::Physical => disk_usage(metadata),
+            FilesizeType::Inodes => 1,
+            FilesizeType::Blocks => blocks(metadata),
+        }
+    }
+
+    /// The size of the entry at the given path. Only `DataSize` and `Physical` need to access the
+    /// entry itself.
+    pub fn size_of(self, path: &Path, metadata: &Metadata) -> u64 {
+        self.size_and_shared(path, metadata).0
+    }
+
+    /// Like `size_of`, but also returns how much of the disk usage of the entry is shared with
+    /// APFS clones and therefore left out of its size. This is only ever non-zero for `Physical`,
+    /// and `None` if the filesystem does not report it.
+    #[cfg_attr(
+        not(any(target_os = "linux", target_os = "macos")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn size_and_shared(self, path: &Path, metadata: &Metadata) -> (u64, Option<u64>) {
+        match self {
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            FilesizeType::DataSize if metadata.is_file() => (
+                data_size(path).unwrap_or_else(|| disk_usage(metadata)),
+                Some(0),
+            ),
+            #[cfg(target_os = "macos")]
+            FilesizeType::Physical if metadata.is_file() => {
+                let disk_usage = disk_usage(metadata);
+                match private_size(path) {
+                    Some(private_size) => (
+                        private_size.min(disk_usage),
+                        Some(disk_usage.saturating_sub(private_size)),
+                    ),
+                    None => (disk_usage, None),
+                }
+            }
+            _ => (self.size(metadata), Some(0)),
+        }
+    }
+}
+
+/// Sums the data regions of a file with `lseek(SEEK_DATA)` and `lseek(SEEK_HOLE)`. Returns
+/// `None` if the file cannot be opened or the filesystem does not support seeking for holes.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn data_size(path: &Path) -> Option<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = File::open(path).ok()?;
+    let fd = file.as_raw_fd();
+    let mut size = 0;
+    let mut offset = 0;
+    loop {
+        // SAFETY: the file descriptor stays open while `file` is alive
+        let data = unsafe { libc::lseek(fd, offset, libc::SEEK_DATA) };
+        if data < 0 {
+            // ENXIO means that there is no more data after the offset
+            return match io::Error::last_os_error().raw_os_error() {
+                Some(libc::ENXIO) => Some(size),
+                _ => None,
+            };
+        }
+        // SAFETY: as above
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < data {
+            return None;
+        }
+        size += (hole - data) as u64;
+        offset = hole;
+    }
+}
+
+/// The number of bytes of a file that are not shared with other files, from
+/// `getattrlist(ATTR_CMNEXT_PRIVATESIZE)`. Returns `None` if the filesystem does not report it.
+#[cfg(target_os = "macos")]
+fn private_size(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::mem::{self, MaybeUninit};
+    use std::os::unix::ffi::OsStrExt;
+
+    /// The layout of the attributes that `getattrlist` writes
+    #[repr(C)]
+    struct Attributes {
+        length: u32,
+        returned: libc::attribute_set_t,
+        private_size: libc::off_t,
+    }
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut requested = libc::attrlist {
+        bitmapcount: libc::ATTR_BIT_MAP_COUNT,
+        reserved: 0,
+        commonattr: libc::ATTR_CMN_RETURNED_ATTRS,
+        volattr: 0,
+        dirattr: 0,
+        fileattr: 0,
+        // With FSOPT_ATTR_CMN_EXTENDED, these are the extended common attributes
+        forkattr: libc::ATTR_CMNEXT_PRIVATESIZE,
+    };
+    let mut attributes = MaybeUninit::<Attributes>::zeroed();
+    // SAFETY: `path` is NUL-terminated, and at most `size_of::<Attributes>()` bytes are written
+    // to `attributes`
+    let status = unsafe {
+        libc::getattrlist(
+            path.as_ptr(),
+            (&mut requested as *mut libc::attrlist).cast(),
+            attributes.as_mut_ptr().cast(),
+            mem::size_of::<Attributes>(),
+            libc::FSOPT_NOFOLLOW | libc::FSOPT_ATTR_CMN_EXTENDED,
+        )
+    };
+    if status != 0 {
+        return None;
+    }
+    // SAFETY: the attributes only consist of integers, for which all zeroes are valid
+    let attributes = unsafe { attributes.assume_init() };
+    let returned = attributes.returned.forkattr & libc::ATTR_CMNEXT_PRIVATESIZE != 0;
+    returned.then(|| attributes.private_size.max(0) as u64)
+}
+
+#[cfg(not(windows))]
+fn disk_usage(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    // block size is always 512 byte, see stat(2) manpage
+    metadata.blocks() * 512
+}
+
+#[cfg(windows)]
+fn disk_usage(metadata: &Metadata) -> u64 {
+    // The allocated size would need another system call for every file
+    metadata.len()
+}
+
+#[cfg(not(windows))]
+fn blocks(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.blocks()
+}
+
+#[cfg(windows)]
+fn blocks(metadata: &Metadata) -> u64 {
+    metadata.len().div_ceil(512)
+}
+
+/// Parses a size like `512`, `4k`, `1M` or `2GiB` into a number of bytes.
+///
+/// Units ending in `i` or `iB`, as well as bare uppercase units (`K`, `M`, ..., like `du`), are
+/// binary multiples. Units ending in `B` (`kB`, `MB`, ...) and the bare lowercase `k` are
+/// decimal multiples.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}': expected a number", s))?;
+
+    let (prefix, binary) = match unit {
+        "" | "B" | "b" => return Ok(number),
+        "k" => ("k", false),
+        "K" | "M" | "G" | "T" | "P" | "E" => (unit, true),
+        _ => {
+            let lower = unit.to_ascii_lowercase();
+            if let Some(prefix) = lower.strip_suffix("ib").or(lower.strip_suffix('i')) {
+                (&unit[..prefix.len()], true)
+            } else if let Some(prefix) = lower.strip_suffix('b') {
+                (&unit[..prefix.len()], false)
+            } else {
+                return Err(format!("invalid size '{}': unknown unit '{}'", s, unit));
+            }
         }
+    };
+
+    let exponent = match prefix.to_ascii_lowercase().as_str() {
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        "e" => 6,
+        _ => return Err(format!("invalid size '{}': unknown unit '{}'", s, unit)),
+    };
+    let base: u64 = if binary { 1024 } else { 1000 };
+
+    number
+        .checked_mul(base.pow(exponent))
+        .ok_or_else(|| format!("invalid size '{}': value is too large", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_of_a_file() {
+        let tmp_dir = tempdir::TempDir::new("diskus-tests").unwrap();
+        let path = tmp_dir.path().join("file");
+        std::fs::write(&path, vec![1u8; 10_000]).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+
+        let blocks = FilesizeType::Blocks.size(&metadata);
+        assert!(blocks >= 20);
+        #[cfg(not(windows))]
+        assert_eq!(blocks * 512, FilesizeType::DiskUsage.size(&metadata));
+        #[cfg(windows)]
+        assert_eq!(blocks, 20);
+    }
+
+    #[test]
+    fn decimal_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512B"), Ok(512));
+        assert_eq!(parse_size("4k"), Ok(4_000));
+        assert_eq!(parse_size("100MB"), Ok(100_000_000));
+        assert_eq!(parse_size("2gb"), Ok(2_000_000_000));
+    }
+
+    #[test]
+    fn binary_sizes() {
+        assert_eq!(parse_size("4K"), Ok(4 << 10));
+        assert_eq!(parse_size("100M"), Ok(100 << 20));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_size("1Ti"), Ok(1 << 40));
     }
 
-    #[cfg(windows)]
-    pub fn size(self, metadata: &std::fs::Metadata) -> u64 {
-        metadata.len()
+    #[test]
+    fn invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10 parsecs").is_err());
+        assert!(parse_size("16E").is_err());
     }
 }
@@ -0,0 +1,159 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::glob::Glob;
+
+/// A single pattern of a `.gitignore` file.
+#[derive(Debug)]
+struct Rule {
+    glob: Glob,
+    /// `!pattern`, which re-includes entries that an earlier pattern ignored
+    negated: bool,
+    /// `pattern/`, which only matches directories
+    dir_only: bool,
+    /// Whether the pattern contains a `/` (other than a trailing one). Such patterns are matched
+    /// against the path relative to the `.gitignore` file, all others against the name.
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        // Like git, invalid patterns are ignored
+        let glob = Glob::gitignore(line).ok()?;
+        Some(Rule {
+            glob,
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.glob.matches(&relative.to_string_lossy())
+        } else {
+            relative
+                .file_name()
+                .is_some_and(|name| self.glob.matches(&name.to_string_lossy()))
+        }
+    }
+}
+
+/// The patterns of one ignore file, and those of the ignore files in the directories above.
+#[derive(Debug)]
+struct IgnoreFile {
+    /// The directory that the patterns are relative to
+    dir: PathBuf,
+    rules: Vec<Rule>,
+    parent: Option<Arc<IgnoreFile>>,
+}
+
+/// The `.gitignore` (and `.git/info/exclude`) patterns that apply to the entries of a directory.
+/// It is cheap to clone, so that every directory of a walk can keep its own.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Ignore {
+    innermost: Option<Arc<IgnoreFile>>,
+}
+
+impl Ignore {
+    /// The patterns that apply to the entries of the given directory, which is contained in the
+    /// directory of `self`.
+    pub(crate) fn enter(&self, dir: &Path) -> Ignore {
+        let mut ignore = self.clone();
+        // The patterns of .gitignore files take precedence, so they are added last
+        for file in [
+            dir.join(".git").join("info").join("exclude"),
+            dir.join(".gitignore"),
+        ] {
+            if let Ok(text) = fs::read_to_string(file) {
+                let rules: Vec<Rule> = text.lines().filter_map(Rule::parse).collect();
+                if !rules.is_empty() {
+                    ignore.innermost = Some(Arc::new(IgnoreFile {
+                        dir: dir.to_path_buf(),
+                        rules,
+                        parent: ignore.innermost.take(),
+                    }));
+                }
+            }
+        }
+        ignore
+    }
+
+    /// Whether the given entry is ignored. The last matching pattern of the innermost ignore file
+    /// with a matching pattern decides.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut file = self.innermost.as_deref();
+        while let Some(ignore_file) = file {
+            if let Ok(relative) = path.strip_prefix(&ignore_file.dir) {
+                let rule = ignore_file
+                    .rules
+                    .iter()
+                    .rev()
+                    .find(|rule| rule.matches(relative, is_dir));
+                if let Some(rule) = rule {
+                    return !rule.negated;
+                }
+            }
+            file = ignore_file.parent.as_deref();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore(dir: &str, text: &str, parent: Ignore) -> Ignore {
+        Ignore {
+            innermost: Some(Arc::new(IgnoreFile {
+                dir: PathBuf::from(dir),
+                rules: text.lines().filter_map(Rule::parse).collect(),
+                parent: parent.innermost,
+            })),
+        }
+    }
+
+    #[test]
+    fn last_matching_pattern_decides() {
+        let ignore = ignore(
+            "/repo",
+            "# logs\n*.log\n!keep.log\n/target/\n",
+            Ignore::default(),
+        );
+        assert!(ignore.is_ignored(Path::new("/repo/debug.log"), false));
+        assert!(ignore.is_ignored(Path::new("/repo/src/debug.log"), false));
+        assert!(!ignore.is_ignored(Path::new("/repo/keep.log"), false));
+        assert!(ignore.is_ignored(Path::new("/repo/target"), true));
+        // Directory patterns do not match files, anchored ones only match at the top
+        assert!(!ignore.is_ignored(Path::new("/repo/target"), false));
+        assert!(!ignore.is_ignored(Path::new("/repo/src/target"), true));
+    }
+
+    #[test]
+    fn nested_files_take_precedence() {
+        let outer = ignore("/repo", "*.log\n", Ignore::default());
+        let inner = ignore("/repo/logs", "!*.log\ntmp\n", outer);
+        assert!(!inner.is_ignored(Path::new("/repo/logs/app.log"), false));
+        assert!(inner.is_ignored(Path::new("/repo/logs/tmp"), true));
+        assert!(inner.is_ignored(Path::new("/repo/app.log"), false));
+    }
+}
@@ -0,0 +1,300 @@
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    /// `?`, any single character
+    Any,
+    /// `*`, any sequence of characters. For patterns of `.gitignore` files, the sequence must
+    /// not contain `/`.
+    Star,
+    /// `**` at the end of a `.gitignore` pattern, any sequence of characters
+    DoubleStar,
+    /// `**/` in a `.gitignore` pattern, any number of leading directories
+    AnyDirs,
+    /// `[a-z_]` or `[!0-9]`
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    /// Whether the token matches the single character `c`. Wildcards only match `/` if
+    /// `separators` is set.
+    fn matches(&self, c: char, separators: bool) -> bool {
+        match self {
+            Token::Literal(literal) => *literal == c,
+            Token::Any | Token::Star => separators || c != '/',
+            Token::DoubleStar | Token::AnyDirs => true,
+            Token::Class { negated, ranges } => {
+                (separators || c != '/')
+                    && ranges.iter().any(|&(from, to)| from <= c && c <= to) != *negated
+            }
+        }
+    }
+
+    /// Whether the token can also match an empty sequence.
+    fn optional(&self) -> bool {
+        matches!(self, Token::Star | Token::DoubleStar | Token::AnyDirs)
+    }
+}
+
+/// A shell-like pattern like `*.iso` or `*/node_modules`. Like `du --exclude`, `*` also matches
+/// `/`. Special characters can be escaped with a backslash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+    /// Whether wildcards except `**` match `/`
+    separators: bool,
+}
+
+impl Glob {
+    /// Parses a pattern of a `.gitignore` file, in which wildcards do not match `/` and `**`
+    /// matches any number of directories.
+    pub(crate) fn gitignore(pattern: &str) -> Result<Glob, String> {
+        Glob::parse(pattern, false)
+    }
+
+    /// Whether the pattern matches all of `text`.
+    pub fn matches(&self, text: &str) -> bool {
+        let n = self.tokens.len();
+        // The positions in the pattern that the part of the text read so far can lead to. Inside
+        // of an `AnyDirs` token, the position is kept in `open_dirs` until the directory ends.
+        let mut states = vec![false; n + 1];
+        let mut open_dirs = vec![false; n];
+        states[0] = true;
+        self.skip_optional(&mut states);
+
+        for c in text.chars() {
+            let mut next = vec![false; n + 1];
+            let mut next_open_dirs = vec![false; n];
+            for (p, token) in self.tokens.iter().enumerate() {
+                if open_dirs[p] || (states[p] && *token == Token::AnyDirs) {
+                    next_open_dirs[p] = true;
+                    next[p + 1] |= c == '/';
+                } else if states[p] && token.matches(c, self.separators) {
+                    match token {
+                        Token::Star | Token::DoubleStar => next[p] = true,
+                        _ => next[p + 1] = true,
+                    }
+                }
+            }
+            self.skip_optional(&mut next);
+            if !next.contains(&true) && !next_open_dirs.contains(&true) {
+                return false;
+            }
+            states = next;
+            open_dirs = next_open_dirs;
+        }
+
+        states[n]
+    }
+
+    /// Adds the positions that can be reached by matching optional tokens with nothing.
+    fn skip_optional(&self, states: &mut [bool]) {
+        for (p, token) in self.tokens.iter().enumerate() {
+            if states[p] && token.optional() {
+                states[p + 1] = true;
+            }
+        }
+    }
+
+    fn parse(s: &str, separators: bool) -> Result<Glob, String> {
+        let mut tokens = vec![];
+        let mut chars = s.chars();
+        // Whether the next character starts a path component
+        let mut component_start = true;
+
+        while let Some(c) = chars.next() {
+            let token = match c {
+                // In .gitignore files, `**` is only special as a whole path component
+                '*' if !separators && component_start && chars.as_str().starts_with("*/") => {
+                    chars.nth(1);
+                    Token::AnyDirs
+                }
+                '*' if !separators && component_start && chars.as_str() == "*" => {
+                    chars.next();
+                    Token::DoubleStar
+                }
+                '*' => Token::Star,
+                '?' => Token::Any,
+                '\\' => Token::Literal(
+                    chars
+                        .next()
+                        .ok_or_else(|| format!("trailing '\\' in pattern '{}'", s))?,
+                ),
+                '[' => {
+                    let mut negated = false;
+                    let mut ranges = vec![];
+                    let mut first = true;
+                    loop {
+                        let c = chars
+                            .next()
+                            .ok_or_else(|| format!("unterminated '[' in pattern '{}'", s))?;
+                        match c {
+                            '!' | '^' if first && !negated => {
+                                negated = true;
+                                continue;
+                            }
+                            // A ']' right after the opening bracket is a literal
+                            ']' if !first => break,
+                            _ => {}
+                        }
+                        first = false;
+                        let rest = chars.as_str();
+                        match rest.strip_prefix('-').and_then(|rest| rest.chars().next()) {
+                            Some(to) if to != ']' => {
+                                chars.next();
+                                chars.next();
+                                ranges.push((c, to));
+                            }
+                            _ => ranges.push((c, c)),
+                        }
+                    }
+                    Token::Class { negated, ranges }
+                }
+                c => Token::Literal(c),
+            };
+            component_start = token == Token::Literal('/') || token == Token::AnyDirs;
+            // Consecutive stars have the same meaning as a single one
+            if !(token == Token::Star && tokens.last() == Some(&Token::Star)) {
+                tokens.push(token);
+            }
+        }
+
+        Ok(Glob { tokens, separators })
+    }
+}
+
+impl FromStr for Glob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Glob, String> {
+        Glob::parse(s, true)
+    }
+}
+
+/// A set of `Glob`s that is matched against the entries of a walk (see `Walk::exclude`).
+#[derive(Debug, Clone, Default)]
+pub struct GlobSet {
+    globs: Vec<Glob>,
+}
+
+impl GlobSet {
+    pub fn new(globs: Vec<Glob>) -> GlobSet {
+        GlobSet { globs }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.globs.is_empty()
+    }
+
+    /// Parses a list of patterns like an exclude file of a backup tool: one pattern per line.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn parse_lines(text: &str) -> Result<Vec<Glob>, String> {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| {
+                line.parse()
+                    .map_err(|err| format!("line {}: {}", i + 1, err))
+            })
+            .collect()
+    }
+
+    /// Whether any of the patterns matches the name of an entry, its path relative to the root of
+    /// the walk, or its whole path.
+    pub(crate) fn matches(&self, path: &Path, root: &Path) -> bool {
+        if self.globs.is_empty() {
+            return false;
+        }
+        let mut candidates = vec![path.to_string_lossy()];
+        if let Some(name) = path.file_name() {
+            candidates.push(name.to_string_lossy());
+        }
+        if let Ok(relative) = path.strip_prefix(root) {
+            if !relative.as_os_str().is_empty() {
+                candidates.push(relative.to_string_lossy());
+            }
+        }
+        self.globs
+            .iter()
+            .any(|glob| candidates.iter().any(|candidate| glob.matches(candidate)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        pattern.parse::<Glob>().unwrap().matches(text)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.iso", "debian.iso"));
+        assert!(!matches("*.iso", "debian.iso.part"));
+        assert!(matches("*/node_modules", "code/app/node_modules"));
+        assert!(matches("file-?", "file-1"));
+        assert!(!matches("file-?", "file-10"));
+        assert!(matches("a**b*c", "abxc"));
+        assert!(matches("*", ""));
+        assert!(!matches("?", ""));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(matches("[ab]x", "bx"));
+        assert!(matches("file-[0-9]", "file-7"));
+        assert!(!matches("file-[!0-9]", "file-7"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "x"));
+        assert!("[ab".parse::<Glob>().is_err());
+        assert!("ab\\".parse::<Glob>().is_err());
+    }
+
+    #[test]
+    fn gitignore_wildcards() {
+        let matches = |pattern: &str, text: &str| Glob::gitignore(pattern).unwrap().matches(text);
+        assert!(matches("*.o", "main.o"));
+        assert!(!matches("*.o", "src/main.o"));
+        assert!(!matches("doc/?", "doc//"));
+        assert!(matches("**/target", "target"));
+        assert!(matches("**/target", "a/b/target"));
+        assert!(!matches("**/target", "a/btarget"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(!matches("a/**/b", "a/xb"));
+        assert!(matches("build/**", "build/x/y"));
+        assert!(matches("a**b", "axxb"));
+        assert!(!matches("a**b", "a/b"));
+    }
+
+    #[test]
+    fn sets_match_names_and_relative_paths() {
+        let set = GlobSet::new(vec![
+            "node_modules".parse().unwrap(),
+            "src/*.rs".parse().unwrap(),
+        ]);
+        let root = Path::new("/code");
+        assert!(set.matches(Path::new("/code/app/node_modules"), root));
+        assert!(set.matches(Path::new("/code/src/main.rs"), root));
+        assert!(!set.matches(Path::new("/code/app/src"), root));
+        assert!(!GlobSet::default().matches(Path::new("/code"), root));
+    }
+
+    #[test]
+    fn pattern_lists() {
+        let globs = GlobSet::parse_lines("# caches\n*.tmp\n\n  \nnode_modules\n").unwrap();
+        assert_eq!(globs.len(), 2);
+        assert!(globs[0].matches("foo.tmp"));
+
+        let err = GlobSet::parse_lines("*.tmp\n[abc\n").unwrap_err();
+        assert!(err.starts_with("line 2: "));
+    }
+}
@@ -2,17 +2,77 @@
 //!
 //! ```
 //! use std::path::PathBuf;
-//! use diskus::{Walk, FilesizeType};
+//! use diskus::{FilesizeType, WalkBuilder};
 //!
-//! let num_threads = 4;
-//! let root_directories = &[PathBuf::from(".")];
-//! let walk = Walk::new(root_directories, num_threads, FilesizeType::DiskUsage);
-//! let (size_in_bytes, errors) = walk.run();
+//! let root_directories = vec![PathBuf::from(".")];
+//! let walk = WalkBuilder::new(root_directories)
+//!     .threads(4)
+//!     .filesize_type(FilesizeType::DiskUsage)
+//!     .build()
+//!     .unwrap();
+//! let result = walk.run().unwrap();
+//! let size_in_bytes = result.entries[0].size;
 //! ```
+//!
+//! # Features
+//!
+//! The default `cli` feature is needed for the binary and for printing the results
+//! (`Walk::run_and_print`). Without it, diskus only depends on what the walk itself needs:
+//!
+//! ```toml
+//! diskus = { version = "0.8", default-features = false }
+//! ```
+//!
+//! The `ffi` feature adds a C interface to the walk, see the `ffi` module.
+//!
+//! The `async` feature adds `Walk::run_async`, which reports the events of a walk as a `Stream`
+//! (from `futures-core`), without depending on a particular async runtime.
+//!
+//! The `serde` feature implements `Serialize` and `Deserialize` for the results of a walk
+//! (`WalkResult` with everything in it, `FileEntry` and `BothSizes`). Paths are serialized as
+//! strings if they are valid UTF-8. Other paths are serialized as their bytes on Unix, so that
+//! they round-trip, but lossily as strings (with U+FFFD) on other platforms. The `io::Error` of an
+//! `Error` is kept as its message and its OS error code, if any.
+
+// Some of what a walk collects is only used for printing the results
+#![cfg_attr(not(feature = "cli"), allow(dead_code))]
 
+mod device;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 mod filesize;
+mod gitignore;
+mod glob;
+#[cfg(feature = "cli")]
+mod output;
+mod owner;
+mod progress;
+mod regex;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "cli")]
+mod template;
+mod time;
+mod trace;
 mod unique_id;
 pub mod walk;
+mod xattr;
 
-pub use crate::filesize::FilesizeType;
-pub use crate::walk::{Error, Walk};
+pub use crate::filesize::{parse_size, FilesizeType};
+pub use crate::glob::{Glob, GlobSet};
+#[cfg(feature = "cli")]
+pub use crate::output::{
+    print_note, print_warning, ColorChoice, OutputFormat, PrintOptions, SizeFormat, SortOrder,
+};
+pub use crate::progress::Progress;
+pub use crate::regex::Regex;
+#[cfg(feature = "cli")]
+pub use crate::template::{Placeholder, Template};
+pub use crate::time::{parse_duration, parse_time};
+pub use crate::walk::{
+    BothSizes, CancelToken, ConfigError, DedupScope, EntryKind, Error, FileEntry, FilterDecision,
+    LockedVisitor, RootEntry, Stats, Walk, WalkBuilder, WalkError, WalkEvent, WalkHandle,
+    WalkResult, WalkVisitor,
+};
+#[cfg(feature = "async")]
+pub use crate::walk::{WalkFuture, WalkStream};
@@ -1,8 +1,36 @@
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::OnceLock;
+use std::time::SystemTime;
 
 use clap::{crate_name, crate_version, App, AppSettings, Arg};
-use diskus::{FilesizeType, Walk};
-use humansize::file_size_opts;
+use diskus::{
+    parse_duration, parse_size, parse_time, print_note, print_warning, CancelToken, ColorChoice,
+    DedupScope, FilesizeType, Glob, GlobSet, OutputFormat, Placeholder, PrintOptions, Regex,
+    SizeFormat, SortOrder, Stats, Template, WalkBuilder, WalkError,
+};
+use humansize::file_size_opts::{self, FileSizeOpts, FixedAt};
+use humansize::FileSize;
+use num_format::{Locale, ToFormattedString};
+
+/// Exit status if some entries below the given paths could not be read
+const EXIT_ERRORS: i32 = 1;
+/// Exit status if one of the given paths could not be read at all
+const EXIT_MISSING_PATH: i32 = 2;
+/// Exit status if the walk stopped before all entries were visited
+const EXIT_PARTIAL: i32 = 3;
+/// Exit status if the walk was stopped by `--timeout`, like `timeout(1)`
+const EXIT_TIMEOUT: i32 = 124;
+/// Exit status for invalid command-line arguments
+const EXIT_USAGE: i32 = 64;
+/// Exit status if the walk was interrupted with Ctrl-C, like a shell reports it
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// The token of the running walk, which is cancelled on Ctrl-C.
+static CANCEL_ON_INTERRUPT: OnceLock<CancelToken> = OnceLock::new();
 
 fn main() {
     let app = App::new(crate_name!())
@@ -12,11 +40,42 @@ fn main() {
         .setting(AppSettings::UnifiedHelpMessage)
         .version(crate_version!())
         .about("Compute disk usage for the given filesystem entries")
+        .after_help(
+            "EXIT STATUS:\n    \
+             0     All paths were read successfully\n    \
+             1     Some entries below the given paths could not be read\n    \
+             2     One of the given paths does not exist or could not be read\n    \
+             3     The walk stopped early because of --limit\n    \
+             64    Invalid command-line arguments\n    \
+             124   The walk was stopped by --timeout\n    \
+             130   The walk was interrupted with Ctrl-C",
+        )
         .arg(
             Arg::with_name("path")
                 .multiple(true)
                 .help("List of filesystem paths"),
         )
+        .arg(
+            Arg::with_name("files-from")
+                .long("files-from")
+                .takes_value(true)
+                .value_name("file")
+                .help(
+                    "Also walk the paths in the given file (or stdin for '-'), one per line. \
+                     Blank lines are skipped.",
+                ),
+        )
+        .arg(
+            Arg::with_name("files0-from")
+                .long("files0-from")
+                .takes_value(true)
+                .value_name("file")
+                .conflicts_with("files-from")
+                .help(
+                    "Like --files-from, but the paths are separated by NUL bytes, e.g. from \
+                     'find -print0'.",
+                ),
+        )
         .arg(
             Arg::with_name("threads")
                 .long("threads")
@@ -34,6 +93,135 @@ fn main() {
                 .default_value("decimal")
                 .help("Output format for file sizes (decimal: MB, binary: MiB, bytes: B)"),
         )
+        .arg(
+            Arg::with_name("precision")
+                .long("precision")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|n| match n.parse::<usize>() {
+                    Ok(n) if n <= 6 => Ok(()),
+                    _ => Err("the precision must be a number between 0 and 6".to_string()),
+                })
+                .help("Number of decimal places for humanized sizes (default: 2)"),
+        )
+        .arg(
+            Arg::with_name("unit")
+                .long("unit")
+                .takes_value(true)
+                .value_name("unit")
+                .possible_values(&["KB", "MB", "GB", "TB", "KiB", "MiB", "GiB", "TiB"])
+                .case_insensitive(true)
+                .conflicts_with_all(&["size-format", "bytes", "block-size"])
+                .help("Print all sizes in the given unit, even if they are smaller than one unit"),
+        )
+        .arg(
+            Arg::with_name("bytes")
+                .long("bytes")
+                .short("B")
+                .takes_value(false)
+                .help(
+                    "Print exact byte counts instead of humanized sizes. Every line has the \
+                     form '<bytes><TAB><path>', the total line reads '<bytes><TAB>total'",
+                ),
+        )
+        .arg(
+            Arg::with_name("block-size")
+                .long("block-size")
+                .takes_value(true)
+                .value_name("size")
+                .validator(|s| {
+                    parse_size(&s).and_then(|size| match size {
+                        0 => Err("the block size must not be zero".to_string()),
+                        _ => Ok(()),
+                    })
+                })
+                .conflicts_with_all(&["bytes", "size-format"])
+                .help(
+                    "Print sizes as the number of blocks of the given size, rounded up \
+                     (e.g. 512, 1K, 1M, 1GiB; 'k', 'kB', 'MB', .. are decimal units)",
+                ),
+        )
+        .arg(
+            Arg::with_name("group-digits")
+                .long("group-digits")
+                .takes_value(true)
+                .value_name("separator")
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true)
+                .possible_values(&[",", "_"])
+                .help(
+                    "Separate groups of thousands in byte counts with ',' (or the given \
+                     separator). Not applied to --print0, JSON and CSV output",
+                ),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .value_name("format")
+                .possible_values(&["text", "json", "csv", "ndjson"])
+                .default_value("text")
+                .help(
+                    "Output format for the results (text: aligned columns, \
+                     json: a single JSON document, csv: rows with a header line, \
+                     ndjson: one JSON object per finished root or error)",
+                ),
+        )
+        .arg(
+            Arg::with_name("print0")
+                .long("print0")
+                .short("0")
+                .takes_value(false)
+                .conflicts_with_all(&["output", "block-size"])
+                .help(
+                    "Separate results by NUL bytes instead of newlines. Every record has \
+                     the form '<bytes><TAB><path>', e.g. for use with 'xargs -0'",
+                ),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .value_name("template")
+                .validator(|template| template.parse::<Template>().map(|_| ()))
+                .conflicts_with_all(&["output", "print0"])
+                .help(
+                    "Print every line according to the given template, e.g. \
+                     '{path} => {size}'. Available placeholders: {size}, {size_bytes}, \
+                     {path}, {percent}, {files}, {dirs}. Use '{{' and '}}' for literal braces.",
+                ),
+        )
+        .arg(
+            Arg::with_name("output-file")
+                .long("output-file")
+                .takes_value(true)
+                .value_name("path")
+                .help(
+                    "Write the results to the given file instead of stdout. The file is only \
+                     replaced once all results are written.",
+                ),
+        )
+        .arg(
+            Arg::with_name("pretty")
+                .long("pretty")
+                .takes_value(false)
+                .help(
+                    "Print humanized sizes in aligned columns, even if the output is not \
+                     a terminal",
+                ),
+        )
+        .arg(
+            Arg::with_name("plain")
+                .long("plain")
+                .takes_value(false)
+                .conflicts_with("pretty")
+                .help(
+                    "Print '<bytes><TAB><path>' lines, even if the output is a terminal \
+                     (this is the default if it is not)",
+                ),
+        )
         .arg(
             Arg::with_name("total")
                 .long("total")
@@ -41,22 +229,574 @@ fn main() {
                 .takes_value(false)
                 .help("Print the total size"),
         )
+        .arg(
+            Arg::with_name("percent")
+                .long("percent")
+                .short("p")
+                .takes_value(false)
+                .help("Show the share of each entry in the total size"),
+        )
+        .arg(
+            Arg::with_name("bar")
+                .long("bar")
+                .takes_value(false)
+                .help("Show the share of each entry in the total size as a bar and a percentage"),
+        )
+        .arg(
+            Arg::with_name("count")
+                .long("count")
+                .short("c")
+                .takes_value(false)
+                .help("Show the number of files and directories below each entry"),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .takes_value(false)
+                .help("Print the names of the columns before the results (text output only)"),
+        )
+        .arg(
+            Arg::with_name("show-shared")
+                .long("show-shared")
+                .takes_value(false)
+                .help(
+                    "Show the size of the hardlinks below each entry that were not counted, \
+                     because the same file had already been counted",
+                ),
+        )
+        .arg(
+            Arg::with_name("files-only")
+                .long("files-only")
+                .takes_value(false)
+                .help(
+                    "Do not count the size of directories themselves, only of their contents \
+                     (the results differ slightly from 'du')",
+                ),
+        )
+        .arg(
+            Arg::with_name("count-hardlinks")
+                .long("count-hardlinks")
+                .short("l")
+                .takes_value(false)
+                .conflicts_with("show-shared")
+                .help(
+                    "Count every hardlink of a file instead of only the first one, like 'du -l'. \
+                     This also uses a lot less memory for huge trees.",
+                ),
+        )
+        .arg(
+            Arg::with_name("dedup")
+                .long("dedup")
+                .takes_value(true)
+                .value_name("scope")
+                .possible_values(&["global", "per-root"])
+                .default_value("global")
+                .help(
+                    "Count files that are hardlinked into several of the given paths only for the \
+                     first one (global), or for every one of them (per-root)",
+                ),
+        )
+        .arg(
+            Arg::with_name("allow-overlap")
+                .long("allow-overlap")
+                .takes_value(false)
+                .help(
+                    "Also walk the given paths that are contained in another given path, which \
+                     counts their contents twice",
+                ),
+        )
         .arg(
             Arg::with_name("sort")
                 .long("sort")
                 .short("s")
+                .takes_value(true)
+                .value_name("key")
+                .possible_values(&["size", "name", "none"])
+                .default_value("none")
+                .help(
+                    "Sort the results in ascending order by size or by name ('none' prints \
+                     every entry as soon as it is finished)",
+                ),
+        )
+        .arg(
+            Arg::with_name("reverse")
+                .long("reverse")
+                .short("r")
+                .takes_value(false)
+                .help("Reverse the sort order (e.g. print the largest entries first)"),
+        )
+        .arg(
+            Arg::with_name("top")
+                .long("top")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|n| match n.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err("N must be a positive number".to_string()),
+                })
+                .help(
+                    "Only show the N largest entries (sorted by size in descending order, \
+                     unless --sort is given)",
+                ),
+        )
+        .arg(
+            Arg::with_name("min-size")
+                .long("min-size")
+                .takes_value(true)
+                .value_name("size")
+                .validator(|s| parse_size(&s).map(|_| ()))
+                .help(
+                    "Only show entries of at least the given size, e.g. '100M' or '2GiB' \
+                     (sorted by size in descending order, unless --sort is given)",
+                ),
+        )
+        .arg(
+            Arg::with_name("max-file-size")
+                .long("max-file-size")
+                .takes_value(true)
+                .value_name("size")
+                .validator(|s| parse_size(&s).map(|_| ()))
+                .help(
+                    "Do not count files larger than the given size, e.g. '1T' (directories are \
+                     still walked)",
+                ),
+        )
+        .arg(
+            Arg::with_name("newer-than")
+                .long("newer-than")
+                .takes_value(true)
+                .value_name("time")
+                .validator(|s| parse_time(&s, SystemTime::now()).map(|_| ()))
+                .help(
+                    "Only count files modified at or after the given time, either a duration \
+                     like '30d' or '12h' ago or a date like '2024-03-01' (directories are still \
+                     walked)",
+                ),
+        )
+        .arg(
+            Arg::with_name("older-than")
+                .long("older-than")
+                .takes_value(true)
+                .value_name("time")
+                .validator(|s| parse_time(&s, SystemTime::now()).map(|_| ()))
+                .help(
+                    "Only count files modified before the given time, in the same format as for \
+                     --newer-than",
+                ),
+        )
+        .arg(
+            Arg::with_name("biggest-files")
+                .long("biggest-files")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|n| match n.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err("N must be a positive number".to_string()),
+                })
+                .help("List the N largest files below all paths after the results"),
+        )
+        .arg(
+            Arg::with_name("by-extension")
+                .long("by-extension")
+                .takes_value(true)
+                .value_name("rows")
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true)
+                .validator(|n| match n.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err("the number of rows must be a positive number".to_string()),
+                })
+                .help(
+                    "Break the size of all files down by extension after the results, in at \
+                     most the given number of rows (default: 10)",
+                ),
+        )
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|n| {
+                    n.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|_| "the depth must be a non-negative number".to_string())
+                })
+                .help(
+                    "Do not walk the contents of directories N levels below the given paths, \
+                     for a quick underestimate",
+                ),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .long("limit")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|n| match n.parse::<u64>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err("N must be a positive number".to_string()),
+                })
+                .help("Stop after visiting N entries, for a bounded underestimate"),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .value_name("duration")
+                .validator(|s| parse_duration(&s).map(|_| ()))
+                .help(
+                    "Stop walking after the given time, e.g. '30s' or '10m', and print the \
+                     sizes up to then",
+                ),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .takes_value(true)
+                .value_name("glob")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|glob| glob.parse::<Glob>().map(|_| ()))
+                .help(
+                    "Skip entries whose name or path matches the given pattern, e.g. \
+                     '*/node_modules' or '*.iso'. Can be given multiple times.",
+                ),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .takes_value(true)
+                .value_name("glob")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|glob| glob.parse::<Glob>().map(|_| ()))
+                .help(
+                    "Only count files whose name or path matches the given pattern, e.g. \
+                     '*.log'. Excluded entries are skipped even if they match. Can be given \
+                     multiple times.",
+                ),
+        )
+        .arg(
+            Arg::with_name("ext")
+                .long("ext")
+                .takes_value(true)
+                .value_name("extensions")
+                .help(
+                    "Only count files with one of the given comma-separated extensions, e.g. \
+                     'mp4,mkv' (case-insensitive). An empty entry matches files without an \
+                     extension.",
+                ),
+        )
+        .arg(
+            Arg::with_name("regex")
+                .long("regex")
+                .takes_value(true)
+                .value_name("pattern")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|regex| regex.parse::<Regex>().map(|_| ()))
+                .help(
+                    "Only count files whose name matches the given regular expression, e.g. \
+                     '^core\\.\\d+$'. Can be given multiple times.",
+                ),
+        )
+        .arg(
+            Arg::with_name("regex-exclude")
+                .long("regex-exclude")
+                .takes_value(true)
+                .value_name("pattern")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|regex| regex.parse::<Regex>().map(|_| ()))
+                .help(
+                    "Do not count files whose name matches the given regular expression. Can \
+                     be given multiple times.",
+                ),
+        )
+        .arg(
+            Arg::with_name("regex-full-path")
+                .long("regex-full-path")
+                .takes_value(false)
+                .help(
+                    "Match --regex and --regex-exclude against the path relative to the given \
+                     path instead of the name",
+                ),
+        )
+        .arg(
+            Arg::with_name("no-skip-special")
+                .long("no-skip-special")
+                .alias("no-skip-special-fs")
+                .takes_value(false)
+                .help(
+                    "Also walk pseudo-filesystems like /proc and /sys below the given paths, \
+                     which are skipped by default (see --include-special for special files)",
+                ),
+        )
+        .arg(
+            Arg::with_name("no-skip-bind-mounts")
+                .long("no-skip-bind-mounts")
+                .takes_value(false)
+                .help(
+                    "Also walk bind mounts of directories that are walked elsewhere anyway, \
+                     which are skipped by default",
+                ),
+        )
+        .arg(
+            Arg::with_name("include-special")
+                .long("include-special")
+                .takes_value(false)
+                .help(
+                    "Also count sockets, FIFOs and device nodes below the given paths, which \
+                     are skipped by default (see --no-skip-special for pseudo-filesystems)",
+                ),
+        )
+        .arg(
+            Arg::with_name("follow-symlinks")
+                .long("follow-symlinks")
+                .short("L")
+                .takes_value(false)
+                .help("Count the targets of symlinks and walk symlinked directories"),
+        )
+        .arg(
+            Arg::with_name("dereference-args")
+                .long("dereference-args")
+                .short("H")
+                .takes_value(false)
+                .help("Walk the targets of the given paths that are symlinks, like 'du -H'"),
+        )
+        .arg(
+            Arg::with_name("one-file-system")
+                .long("one-file-system")
+                .short("x")
+                .takes_value(false)
+                .help("Skip directories on other filesystems than the given paths"),
+        )
+        .arg(
+            Arg::with_name("gitignore")
+                .long("gitignore")
+                .takes_value(false)
+                .help("Skip entries that are ignored by .gitignore files, like git does"),
+        )
+        .arg(
+            Arg::with_name("no-git-dir")
+                .long("no-git-dir")
+                .takes_value(false)
+                .help("Skip .git directories"),
+        )
+        .arg(
+            Arg::with_name("prune")
+                .long("prune")
+                .takes_value(true)
+                .value_name("name")
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Skip all directories with the given name below the given paths, e.g. \
+                     'node_modules'. Can be given multiple times.",
+                ),
+        )
+        .arg(
+            Arg::with_name("skip-cache-dirs")
+                .long("skip-cache-dirs")
+                .takes_value(false)
+                .help(
+                    "Do not walk the contents of directories that are tagged as caches with a \
+                     CACHEDIR.TAG file",
+                ),
+        )
+        .arg(
+            Arg::with_name("respect-ignore-markers")
+                .long("respect-ignore-markers")
+                .takes_value(false)
+                .help("Do not walk the contents of directories with a .diskusignore file"),
+        )
+        .arg(
+            Arg::with_name("no-hidden")
+                .long("no-hidden")
+                .takes_value(false)
+                .help(
+                    "Skip hidden files and directories below the given paths (names starting \
+                     with a dot)",
+                ),
+        )
+        .arg(
+            Arg::with_name("exclude-from")
+                .long("exclude-from")
+                .takes_value(true)
+                .value_name("file")
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Skip entries that match one of the patterns in the given file, one per \
+                     line. Blank lines and lines starting with '#' are ignored.",
+                ),
+        )
+        .arg(
+            Arg::with_name("depth")
+                .long("depth")
+                .short("d")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|n| {
+                    n.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|_| "the depth must be a non-negative number".to_string())
+                })
+                .help(
+                    "Also show the size of every directory up to N levels below the \
+                     given paths (like 'du -d N')",
+                ),
+        )
+        .arg(
+            Arg::with_name("absolute")
+                .long("absolute")
+                .takes_value(false)
+                .help("Print absolute paths with all symlinks resolved"),
+        )
+        .arg(
+            Arg::with_name("classify")
+                .long("classify")
+                .short("F")
+                .takes_value(false)
+                .help("Append '/' to directories and '@' to symlinks, like 'ls -F'"),
+        )
+        .arg(
+            Arg::with_name("inodes")
+                .long("inodes")
+                .takes_value(false)
+                .conflicts_with_all(&["bytes", "block-size", "size-format", "precision", "unit"])
+                .help("Count the number of inodes instead of computing sizes"),
+        )
+        .arg(
+            Arg::with_name("blocks")
+                .long("blocks")
                 .takes_value(false)
-                .help("Sort the sizes in ascending order"),
+                .conflicts_with_all(&[
+                    "inodes",
+                    "apparent-size",
+                    "bytes",
+                    "block-size",
+                    "size-format",
+                    "precision",
+                    "unit",
+                ])
+                .help(
+                    "Report the number of 512-byte blocks that are allocated for the entries \
+                     (st_blocks) instead of sizes in bytes",
+                ),
+        )
+        .arg(
+            Arg::with_name("size-type")
+                .long("size-type")
+                .takes_value(true)
+                .value_name("type")
+                .possible_values(&["disk-usage", "apparent", "inodes", "blocks"])
+                .conflicts_with_all(&["inodes", "blocks", "apparent-size"])
+                .help(
+                    "What to count for every entry (disk-usage: the default, apparent: like \
+                     --apparent-size, inodes: like --inodes, blocks: like --blocks)",
+                ),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .takes_value(true)
+                .value_name("when")
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto")
+                .help(
+                    "When to use colors (auto: only if the output is a terminal and \
+                     NO_COLOR is not set)",
+                ),
+        )
+        .arg(
+            Arg::with_name("progress")
+                .long("progress")
+                .takes_value(false)
+                .help("Show the number of scanned entries and bytes while running"),
+        )
+        .arg(
+            Arg::with_name("live")
+                .long("live")
+                .takes_value(false)
+                .conflicts_with_all(&["progress", "sort", "top"])
+                .help(
+                    "Continuously show the size of every path while scanning (if the output \
+                     is a terminal)",
+                ),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .takes_value(false)
+                .help(
+                    "Print the number of visited entries and errors as well as the elapsed \
+                     time to stderr",
+                ),
         )
         .arg(
             Arg::with_name("verbose")
                 .long("verbose")
                 .short("v")
                 .takes_value(false)
-                .help("Do not hide filesystem errors"),
+                .multiple(true)
+                .help(
+                    "Do not hide filesystem errors. Use -vv to also log every directory as it \
+                     is entered",
+                ),
+        )
+        .arg(
+            Arg::with_name("ignore-errors")
+                .long("ignore-errors")
+                .takes_value(true)
+                .value_name("glob")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|glob| glob.parse::<Glob>().map(|_| ()))
+                .help(
+                    "Drop the filesystem errors for paths that match the given pattern, e.g. \
+                     '/var/lib/private/*'. They do not affect the exit status. Can be given \
+                     multiple times.",
+                ),
+        )
+        .arg(
+            Arg::with_name("summarize-errors")
+                .long("summarize-errors")
+                .takes_value(false)
+                .conflicts_with("verbose")
+                .help(
+                    "Print only a few filesystem errors of each kind, followed by the \
+                     number of omitted ones",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .takes_value(false)
+                .conflicts_with_all(&["verbose", "summarize-errors"])
+                .help("Do not print filesystem errors or warnings"),
+        )
+        .arg(
+            Arg::with_name("no-fail-on-error")
+                .long("no-fail-on-error")
+                .takes_value(false)
+                .help(
+                    "Exit with status 0 even if entries below the given paths could not be \
+                     read",
+                ),
+        )
+        .arg(
+            Arg::with_name("errors-json")
+                .long("errors-json")
+                .takes_value(false)
+                .conflicts_with_all(&["verbose", "summarize-errors", "quiet"])
+                .help(
+                    "Print every filesystem error on stderr as a JSON object on its own line, \
+                     e.g. {\"kind\": \"could_not_read_dir\", \"path\": \"/root\"}",
+                ),
         );
 
-    #[cfg(not(windows))]
     let app = app.arg(
         Arg::with_name("apparent-size")
             .long("apparent-size")
@@ -64,7 +804,111 @@ fn main() {
             .help("Compute apparent size instead of disk usage"),
     );
 
-    let matches = app.get_matches();
+    #[cfg(windows)]
+    let app = app.arg(
+        Arg::with_name("dedup-hardlinks")
+            .long("dedup-hardlinks")
+            .takes_value(false)
+            .conflicts_with("count-hardlinks")
+            .help(
+                "Count files with several hardlinks only once, like on other systems \
+                 (opens every file)",
+            ),
+    );
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    let app = app.arg(
+        Arg::with_name("data-size")
+            .long("data-size")
+            .takes_value(false)
+            .conflicts_with_all(&["apparent-size", "inodes", "blocks", "size-type"])
+            .help(
+                "Only count the data that is actually stored in files, without the holes of \
+                 sparse files (opens every file)",
+            ),
+    );
+
+    #[cfg(target_os = "macos")]
+    let app = app.arg(
+        Arg::with_name("physical")
+            .long("physical")
+            .takes_value(false)
+            .conflicts_with_all(&[
+                "apparent-size",
+                "inodes",
+                "blocks",
+                "data-size",
+                "size-type",
+            ])
+            .help(
+                "Leave the space that files share with their APFS clones out of their size. \
+                 The default disk usage matches 'du', --apparent-size matches the size shown by \
+                 Finder.",
+            ),
+    );
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    let app = app.arg(
+        Arg::with_name("include-xattrs")
+            .long("include-xattrs")
+            .takes_value(false)
+            .conflicts_with_all(&["inodes", "blocks"])
+            .help(
+                "Add the size of the extended attributes of files (like resource forks on \
+                 macOS) to their size",
+            ),
+    );
+
+    #[cfg(unix)]
+    let app = app.arg(
+        Arg::with_name("both")
+            .long("both")
+            .takes_value(false)
+            .conflicts_with_all(&[
+                "apparent-size",
+                "inodes",
+                "blocks",
+                "data-size",
+                "size-type",
+            ])
+            .help("Show the apparent size and the disk usage side by side"),
+    );
+
+    #[cfg(unix)]
+    let app = app.arg(
+        Arg::with_name("by-device")
+            .long("by-device")
+            .takes_value(false)
+            .help("Break the size of all entries down by the filesystem they are stored on"),
+    );
+
+    #[cfg(unix)]
+    let app = app.arg(
+        Arg::with_name("only-mine")
+            .long("only-mine")
+            .takes_value(false)
+            .help(
+                "Only count the entries owned by the current (effective) user, and do not walk \
+                 the directories of other users",
+            ),
+    );
+
+    #[cfg(unix)]
+    let app = app.arg(
+        Arg::with_name("by-owner")
+            .long("by-owner")
+            .takes_value(false)
+            .help("Break the size of all entries down by their owner after the results"),
+    );
+
+    let matches = app.get_matches_safe().unwrap_or_else(|err| {
+        // --help and --version are reported as errors, too
+        if err.use_stderr() {
+            eprintln!("{}", err.message);
+            process::exit(EXIT_USAGE);
+        }
+        err.exit()
+    });
 
     // Setting the number of threads to 3x the number of cores is a good tradeoff between
     // cold-cache and warm-cache runs. For a cold disk cache, we are limited by disk IO and
@@ -76,32 +920,686 @@ fn main() {
         .and_then(|t| t.parse().ok())
         .unwrap_or(3 * num_cpus::get());
 
-    let paths: Vec<PathBuf> = matches
-        .values_of("path")
+    let mut paths: Vec<PathBuf> = matches
+        .values_of_os("path")
         .map(|paths| paths.map(PathBuf::from).collect())
-        .unwrap_or_else(|| vec![PathBuf::from(".")]);
+        .unwrap_or_default();
+    // Unlike the shells on Unix, cmd and PowerShell leave wildcards to the programs
+    #[cfg(windows)]
+    {
+        paths = expand_wildcards(paths).unwrap_or_else(|err| {
+            eprintln!("diskus: {}", err);
+            process::exit(EXIT_MISSING_PATH);
+        });
+    }
+    let path_list = match matches.value_of_os("files0-from") {
+        Some(file) => Some((file, b'\0')),
+        None => matches.value_of_os("files-from").map(|file| (file, b'\n')),
+    };
+    if let Some((file, separator)) = path_list {
+        let listed = read_paths(file, separator).unwrap_or_else(|err| {
+            eprintln!(
+                "diskus: could not read path list '{}': {}",
+                file.to_string_lossy(),
+                err
+            );
+            process::exit(EXIT_USAGE);
+        });
+        if listed.is_empty() {
+            eprintln!(
+                "diskus: the path list '{}' does not contain any paths",
+                file.to_string_lossy()
+            );
+            process::exit(EXIT_USAGE);
+        }
+        paths.extend(listed);
+    }
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+
+    // --apparent-size, --inodes and --blocks are short for the values of --size-type
+    let size_type = match matches.value_of("size-type") {
+        Some(size_type) => size_type,
+        None if matches.is_present("inodes") => "inodes",
+        None if matches.is_present("blocks") => "blocks",
+        None if matches.is_present("apparent-size") => "apparent",
+        None => "disk-usage",
+    };
+    let counted = matches!(size_type, "inodes" | "blocks");
+    if counted && matches.is_present("size-type") {
+        // The same conflicts as those of the flags
+        let conflicts = [
+            "bytes",
+            "block-size",
+            "size-format",
+            "precision",
+            "unit",
+            "include-xattrs",
+        ];
+        if let Some(arg) = conflicts
+            .iter()
+            .find(|&&arg| matches.occurrences_of(arg) > 0)
+        {
+            eprintln!(
+                "diskus: --size-type {} cannot be used with --{}",
+                size_type, arg
+            );
+            process::exit(EXIT_USAGE);
+        }
+    }
+    let filesize_type = match size_type {
+        "inodes" => FilesizeType::Inodes,
+        "blocks" => FilesizeType::Blocks,
+        "apparent" => FilesizeType::ApparentSize,
+        _ => FilesizeType::DiskUsage,
+    };
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    let filesize_type = if matches.is_present("data-size") {
+        FilesizeType::DataSize
+    } else {
+        filesize_type
+    };
+    #[cfg(target_os = "macos")]
+    let filesize_type = if matches.is_present("physical") {
+        FilesizeType::Physical
+    } else {
+        filesize_type
+    };
+
+    let block_size = matches
+        .value_of("block-size")
+        .map(|size| parse_size(size).unwrap());
 
-    let filesize_type = if matches.is_present("apparent-size") {
-        FilesizeType::ApparentSize
+    let precision: Option<usize> = matches
+        .value_of("precision")
+        .map(|precision| precision.parse().unwrap());
+    let humanized = |opts: FileSizeOpts| {
+        SizeFormat::Humanized(FileSizeOpts {
+            decimal_places: precision.unwrap_or(opts.decimal_places),
+            ..opts
+        })
+    };
+
+    let output_file = matches.value_of_os("output-file").map(PathBuf::from);
+
+    // Padded, humanized columns are only useful if somebody reads them. Pipes and files get
+    // exact byte counts, unless another size format has been requested explicitly.
+    let pretty = if matches.is_present("plain") {
+        false
+    } else if matches.is_present("pretty") {
+        true
     } else {
-        FilesizeType::DiskUsage
+        output_file.is_none() && atty::is(atty::Stream::Stdout)
+    };
+    let humanized_by_default = matches.value_of("output") == Some("text")
+        && matches.occurrences_of("size-format") == 0
+        && !matches.is_present("precision")
+        && !matches.is_present("unit");
+
+    let size_format = match (matches.value_of("size-format"), block_size) {
+        _ if size_type == "inodes" => SizeFormat::Inodes,
+        _ if size_type == "blocks" => SizeFormat::BlockCounts,
+        _ if matches.is_present("bytes") => SizeFormat::Bytes,
+        (_, Some(block_size)) => SizeFormat::Blocks(block_size),
+        _ if !pretty && humanized_by_default => SizeFormat::Bytes,
+        _ if matches.is_present("unit") => SizeFormat::Humanized(fixed_unit(
+            matches.value_of("unit").unwrap(),
+            precision.unwrap_or(file_size_opts::DECIMAL.decimal_places),
+        )),
+        (Some("decimal"), _) => humanized(file_size_opts::DECIMAL),
+        (Some("binary"), _) => humanized(file_size_opts::BINARY),
+        _ => SizeFormat::Bytes,
+    };
+
+    let output_format = match matches.value_of("output") {
+        _ if matches.is_present("print0") => OutputFormat::Print0,
+        Some("json") => OutputFormat::Json,
+        Some("csv") => OutputFormat::Csv,
+        Some("ndjson") => OutputFormat::Ndjson,
+        _ => OutputFormat::Text,
+    };
+
+    let color = match matches.value_of("color") {
+        Some("always") => ColorChoice::Always,
+        Some("never") => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    };
+
+    let template: Option<Template> = matches
+        .value_of("format")
+        .map(|template| template.parse().unwrap());
+
+    let options = PrintOptions {
+        size_format,
+        output_format,
+        total: matches.is_present("total"),
+        verbose: matches.is_present("verbose"),
+        quiet: matches.is_present("quiet"),
+        summarize_errors: matches.is_present("summarize-errors"),
+        errors_json: matches.is_present("errors-json"),
+        percent: matches.is_present("percent")
+            || matches.is_present("bar")
+            || template
+                .as_ref()
+                .is_some_and(|template| template.uses(Placeholder::Percent)),
+        bar: matches.is_present("bar"),
+        count: matches.is_present("count"),
+        both: matches.is_present("both"),
+        header: matches.is_present("header"),
+        show_shared: matches.is_present("show-shared"),
+        color,
+        top: matches.value_of("top").map(|top| top.parse().unwrap()),
+        min_size: matches
+            .value_of("min-size")
+            .map(|size| parse_size(size).unwrap()),
+        by_owner: matches.is_present("by-owner"),
+        by_device: matches.is_present("by-device"),
+        by_extension: match matches.value_of("by-extension") {
+            Some(rows) => Some(rows.parse().unwrap()),
+            None if matches.is_present("by-extension") => Some(10),
+            None => None,
+        },
+        biggest_files: matches
+            .value_of("biggest-files")
+            .map(|n| n.parse().unwrap()),
+        live: matches.is_present("live"),
+        output_file,
+        template,
+        pretty,
+        classify: matches.is_present("classify"),
+        digit_separator: match matches.value_of("group-digits") {
+            Some("_") => Some('_'),
+            _ if matches.is_present("group-digits") => Some(','),
+            _ => None,
+        },
+    };
+
+    #[cfg(unix)]
+    // SAFETY: geteuid cannot fail
+    let owner = matches
+        .is_present("only-mine")
+        .then(|| unsafe { libc::geteuid() });
+    #[cfg(not(unix))]
+    let owner = None;
+
+    // Ctrl-C stops the walk, so that the sizes up to then are still printed
+    let cancel = CANCEL_ON_INTERRUPT.get_or_init(CancelToken::new).clone();
+    #[cfg(unix)]
+    cancel_on_interrupt();
+
+    let report_depth = matches
+        .value_of("depth")
+        .map(|depth| depth.parse().unwrap());
+
+    let mut exclude: Vec<Glob> = matches
+        .values_of("exclude")
+        .map(|globs| globs.map(|glob| glob.parse().unwrap()).collect())
+        .unwrap_or_default();
+    for file in matches.values_of_os("exclude-from").into_iter().flatten() {
+        exclude.extend(read_patterns(Path::new(file)).unwrap_or_else(|err| {
+            eprintln!(
+                "diskus: could not read exclude file '{}': {}",
+                file.to_string_lossy(),
+                err
+            );
+            process::exit(EXIT_USAGE);
+        }));
+    }
+
+    let include: Vec<Glob> = matches
+        .values_of("include")
+        .map(|globs| globs.map(|glob| glob.parse().unwrap()).collect())
+        .unwrap_or_default();
+
+    let regexes = |name| {
+        matches
+            .values_of(name)
+            .map(|regexes| regexes.map(|regex| regex.parse().unwrap()).collect())
+            .unwrap_or_default()
     };
 
-    let size_format = match matches.value_of("size-format") {
-        Some("decimal") => Some(file_size_opts::DECIMAL),
-        Some("binary") => Some(file_size_opts::BINARY),
+    let now = SystemTime::now();
+    let time = |name| {
+        matches
+            .value_of(name)
+            .map(|time| parse_time(time, now).unwrap())
+    };
+    let (newer_than, older_than) = (time("newer-than"), time("older-than"));
+    if let (Some(newer_than), Some(older_than)) = (newer_than, older_than) {
+        if newer_than >= older_than {
+            eprintln!("diskus: no file can be both --newer-than and --older-than the given times");
+            process::exit(EXIT_USAGE);
+        }
+    }
+
+    let walk = WalkBuilder::new(paths)
+        .threads(num_threads)
+        .filesize_type(filesize_type)
+        .exclude(GlobSet::new(exclude))
+        .follow_symlinks(matches.is_present("follow-symlinks"))
+        .report_depth(report_depth)
+        .dedup(match matches.value_of("dedup") {
+            Some("per-root") => DedupScope::PerRoot,
+            _ => DedupScope::Global,
+        })
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("diskus: {}", err);
+            process::exit(EXIT_USAGE);
+        })
+        .ignore_errors(GlobSet::new(
+            matches
+                .values_of("ignore-errors")
+                .map(|globs| globs.map(|glob| glob.parse().unwrap()).collect())
+                .unwrap_or_default(),
+        ))
+        .include(GlobSet::new(include))
+        .extensions(
+            matches
+                .value_of("ext")
+                .map(|extensions| extensions.split(',').map(String::from).collect()),
+        )
+        .regex(regexes("regex"))
+        .regex_exclude(regexes("regex-exclude"))
+        .regex_full_path(matches.is_present("regex-full-path"))
+        .max_file_size(
+            matches
+                .value_of("max-file-size")
+                .map(|size| parse_size(size).unwrap()),
+        )
+        .limit(matches.value_of("limit").map(|n| n.parse().unwrap()))
+        .timeout(
+            matches
+                .value_of("timeout")
+                .map(|duration| parse_duration(duration).unwrap()),
+        )
+        .newer_than(newer_than)
+        .older_than(older_than)
+        .gitignore(matches.is_present("gitignore"))
+        .skip_git_dirs(matches.is_present("no-git-dir"))
+        .skip_hidden(matches.is_present("no-hidden"))
+        .skip_cache_dirs(matches.is_present("skip-cache-dirs"))
+        .respect_ignore_markers(matches.is_present("respect-ignore-markers"))
+        .prune(
+            matches
+                .values_of_os("prune")
+                .map(|names| names.map(|name| name.to_os_string()).collect())
+                .unwrap_or_default(),
+        )
+        .one_file_system(matches.is_present("one-file-system"))
+        .skip_special(!matches.is_present("no-skip-special"))
+        .skip_bind_mounts(!matches.is_present("no-skip-bind-mounts"))
+        .skip_special_files(!matches.is_present("include-special"))
+        .owner(owner)
+        .cancel_token(cancel)
+        .count_hardlinks(matches.is_present("count-hardlinks"))
+        .dedup_hardlinks(matches.is_present("dedup-hardlinks"))
+        .files_only(matches.is_present("files-only"))
+        .include_xattrs(matches.is_present("include-xattrs"))
+        .allow_overlap(matches.is_present("allow-overlap"))
+        .dereference_roots(matches.is_present("dereference-args"))
+        .absolute(matches.is_present("absolute"))
+        .max_depth(
+            matches
+                .value_of("max-depth")
+                .map(|depth| depth.parse().unwrap()),
+        )
+        .status_line(matches.is_present("progress"))
+        .trace(matches.occurrences_of("verbose") >= 2);
+
+    let sort_order = match matches.value_of("sort") {
+        Some("size") => Some(SortOrder::Size),
+        Some("name") => Some(SortOrder::Name),
         _ => None,
     };
 
-    let print_total: bool = matches.is_present("total");
+    let quiet = options.quiet;
+    if owner == Some(0) && !quiet {
+        print_warning(
+            color,
+            format_args!(
+                "--only-mine is run as root, so only the entries owned by root (UID 0) are counted"
+            ),
+        );
+    }
 
-    let verbose = matches.is_present("verbose");
+    let filtered = options.top.is_some() || options.min_size.is_some();
+    let result = if filtered && sort_order.is_none() {
+        // Show the largest entries first
+        walk.run_and_print_sorted(options, SortOrder::Size, !matches.is_present("reverse"))
+    } else if let Some(sort_order) = sort_order {
+        walk.run_and_print_sorted(options, sort_order, matches.is_present("reverse"))
+    } else {
+        if matches.is_present("reverse") && !quiet {
+            print_warning(
+                color,
+                format_args!("--reverse has no effect without --sort size or --sort name"),
+            );
+        }
+        walk.run_and_print(options)
+    };
+    let stats = result.unwrap_or_else(|err| match err {
+        // Like other tools, diskus stops quietly if e.g. 'head' stops reading its output
+        WalkError::Output(err) if err.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        err => {
+            eprintln!("diskus: {}", err);
+            process::exit(EXIT_ERRORS);
+        }
+    });
 
-    let walk = Walk::new(paths, num_threads, filesize_type);
+    if matches.is_present("stats") {
+        print_stats(&stats);
+    }
 
-    if matches.is_present("sort") {
-        walk.run_and_print_sorted(size_format, print_total, verbose);
+    if stats.truncated_dirs > 0 && !quiet {
+        print_warning(
+            color,
+            format_args!(
+                "the contents of {} {} at --max-depth were not walked, the sizes are an \
+                 underestimate",
+                stats.truncated_dirs.to_formatted_string(&Locale::en),
+                if stats.truncated_dirs == 1 {
+                    "directory"
+                } else {
+                    "directories"
+                }
+            ),
+        );
+    }
+
+    if stats.timed_out && !quiet {
+        print_warning(
+            color,
+            format_args!(
+                "partial results (timed out after {}), the sizes are an underestimate",
+                matches.value_of("timeout").unwrap_or_default()
+            ),
+        );
+    }
+
+    let interrupted = stats.cancelled && !stats.timed_out;
+    if interrupted && !quiet {
+        print_warning(
+            color,
+            format_args!("interrupted, the sizes are an underestimate"),
+        );
+    }
+
+    if stats.limit_reached && !quiet {
+        print_warning(
+            color,
+            format_args!(
+                "scan truncated after {} entries, the sizes are an underestimate",
+                matches.value_of("limit").unwrap_or_default()
+            ),
+        );
+    }
+
+    if stats.clone_shared > 0 && !quiet {
+        print_note(
+            color,
+            format_args!(
+                "left out {} that the files (possibly) share with APFS clones",
+                stats
+                    .clone_shared
+                    .file_size(file_size_opts::DECIMAL)
+                    .unwrap()
+            ),
+        );
+    }
+
+    if stats.unknown_clone_shares > 0 && !quiet {
+        print_warning(
+            color,
+            format_args!(
+                "the space that {} {} with APFS clones is unknown (only APFS on macOS 10.15 or \
+                 later reports it), {} full disk usage is counted",
+                stats.unknown_clone_shares.to_formatted_string(&Locale::en),
+                if stats.unknown_clone_shares == 1 {
+                    "file shares"
+                } else {
+                    "files share"
+                },
+                if stats.unknown_clone_shares == 1 {
+                    "its"
+                } else {
+                    "their"
+                }
+            ),
+        );
+    }
+
+    if stats.skipped_large_files > 0 && !quiet {
+        print_note(
+            color,
+            format_args!(
+                "skipped {} {} larger than --max-file-size",
+                stats.skipped_large_files.to_formatted_string(&Locale::en),
+                if stats.skipped_large_files == 1 {
+                    "file"
+                } else {
+                    "files"
+                }
+            ),
+        );
+    }
+
+    if matches.is_present("prune") && !quiet {
+        print_note(
+            color,
+            format_args!(
+                "pruned {} {}",
+                stats.pruned_dirs.to_formatted_string(&Locale::en),
+                if stats.pruned_dirs == 1 {
+                    "directory"
+                } else {
+                    "directories"
+                }
+            ),
+        );
+    }
+
+    if matches.is_present("respect-ignore-markers") && !quiet {
+        print_note(
+            color,
+            format_args!(
+                "skipped the contents of {} {} with a .diskusignore file",
+                stats.marked_dirs.to_formatted_string(&Locale::en),
+                if stats.marked_dirs == 1 {
+                    "directory"
+                } else {
+                    "directories"
+                }
+            ),
+        );
+    }
+
+    if stats.missing_roots > 0 {
+        process::exit(EXIT_MISSING_PATH);
+    }
+    if stats.timed_out {
+        process::exit(EXIT_TIMEOUT);
+    }
+    if interrupted {
+        process::exit(EXIT_INTERRUPTED);
+    }
+    if stats.limit_reached {
+        process::exit(EXIT_PARTIAL);
+    }
+    if stats.errors > 0 && !matches.is_present("no-fail-on-error") {
+        process::exit(EXIT_ERRORS);
+    }
+}
+
+/// Cancels `CANCEL_ON_INTERRUPT` on the first SIGINT. A second one kills diskus as usual.
+#[cfg(unix)]
+fn cancel_on_interrupt() {
+    extern "C" fn handle_interrupt(_signal: libc::c_int) {
+        // Both are async-signal-safe: an atomic load and store, and signal(2) itself
+        if let Some(cancel) = CANCEL_ON_INTERRUPT.get() {
+            cancel.cancel();
+        }
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+
+    // SAFETY: the handler only does async-signal-safe work
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// Reads the patterns of an exclude file (see `GlobSet::parse_lines`).
+fn read_patterns(path: &Path) -> Result<Vec<Glob>, String> {
+    let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    GlobSet::parse_lines(&text)
+}
+
+/// Reads a list of paths from the given file (or stdin for `-`), split at the given separator.
+/// Empty entries are skipped, all others are taken exactly as they are, including their
+/// whitespace.
+fn read_paths(file: &OsStr, separator: u8) -> io::Result<Vec<PathBuf>> {
+    let bytes = if file == "-" {
+        let mut bytes = vec![];
+        io::stdin().lock().read_to_end(&mut bytes)?;
+        bytes
+    } else {
+        fs::read(file)?
+    };
+    Ok(bytes
+        .split(|&byte| byte == separator)
+        .filter(|line| !line.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+/// Replaces the path arguments with wildcards in them by the paths they match. Arguments that
+/// exist as they are, e.g. a directory with brackets in its name, are not expanded. Like the
+/// filesystem, the matching ignores case.
+#[cfg(windows)]
+fn expand_wildcards(paths: Vec<PathBuf>) -> Result<Vec<PathBuf>, String> {
+    use std::path::Component;
+
+    let has_wildcards = |s: &str| s.contains(['*', '?', '[']);
+    let mut expanded = vec![];
+    for path in paths {
+        let pattern = path.to_string_lossy().into_owned();
+        if !has_wildcards(&pattern) || fs::symlink_metadata(&path).is_ok() {
+            expanded.push(path);
+            continue;
+        }
+
+        let mut matches = vec![PathBuf::new()];
+        for component in path.components() {
+            let name = match component {
+                Component::Normal(name) if has_wildcards(&name.to_string_lossy()) => {
+                    name.to_string_lossy().to_lowercase()
+                }
+                _ => {
+                    for path in &mut matches {
+                        path.push(component);
+                    }
+                    continue;
+                }
+            };
+            let glob: Glob = name.parse()?;
+            let mut next = vec![];
+            for dir in &matches {
+                let entries = if dir.as_os_str().is_empty() {
+                    fs::read_dir(".")
+                } else {
+                    fs::read_dir(dir)
+                };
+                let Ok(entries) = entries else { continue };
+                let mut names: Vec<_> = entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.file_name())
+                    .filter(|name| glob.matches(&name.to_string_lossy().to_lowercase()))
+                    .collect();
+                names.sort();
+                next.extend(names.into_iter().map(|name| dir.join(name)));
+            }
+            matches = next;
+        }
+
+        // Literal components after a wildcard do not have to exist in every match
+        matches.retain(|path| fs::symlink_metadata(path).is_ok());
+        if matches.is_empty() {
+            return Err(format!("no paths match the pattern '{}'", pattern));
+        }
+        expanded.extend(matches);
+    }
+    Ok(expanded)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Size format options that render every size in the given unit, like `0.00 GB`.
+fn fixed_unit(unit: &str, decimal_places: usize) -> FileSizeOpts {
+    let (opts, fixed_at) = match unit.to_lowercase().as_str() {
+        "kb" => (file_size_opts::DECIMAL, FixedAt::Kilo),
+        "mb" => (file_size_opts::DECIMAL, FixedAt::Mega),
+        "gb" => (file_size_opts::DECIMAL, FixedAt::Giga),
+        "tb" => (file_size_opts::DECIMAL, FixedAt::Tera),
+        "kib" => (file_size_opts::BINARY, FixedAt::Kilo),
+        "mib" => (file_size_opts::BINARY, FixedAt::Mega),
+        "gib" => (file_size_opts::BINARY, FixedAt::Giga),
+        "tib" => (file_size_opts::BINARY, FixedAt::Tera),
+        _ => unreachable!("unit is validated by clap"),
+    };
+    FileSizeOpts {
+        fixed_at,
+        decimal_places,
+        // Keep the number of decimal places the same for all sizes, so that they line up
+        decimal_zeroes: decimal_places,
+        ..opts
+    }
+}
+
+fn print_stats(stats: &Stats) {
+    let count = |n: u64| n.to_formatted_string(&Locale::en);
+    eprintln!();
+    eprintln!("Files:          {}", count(stats.files));
+    eprintln!("Directories:    {}", count(stats.dirs));
+    eprintln!("Symlinks:       {}", count(stats.symlinks));
+    if stats.skipped_special_files > 0 {
+        eprintln!(
+            "Special files:  {} skipped",
+            count(stats.skipped_special_files)
+        );
+    }
+    if stats.skipped_other_owners > 0 {
+        eprintln!(
+            "Other owners:   {} skipped",
+            count(stats.skipped_other_owners)
+        );
+    }
+    if stats.ignored_errors > 0 {
+        eprintln!(
+            "Errors:         {} ({} ignored)",
+            count(stats.errors),
+            count(stats.ignored_errors)
+        );
     } else {
-        walk.run_and_print(size_format, print_total, verbose);
+        eprintln!("Errors:         {}", count(stats.errors));
     }
+    eprintln!("Elapsed time:   {:.3} s", stats.elapsed.as_secs_f64());
+    eprintln!(
+        "Entries/second: {}",
+        count(stats.entries_per_second().round() as u64)
+    );
 }
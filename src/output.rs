@@ -0,0 +1,1672 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Write as _};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use humansize::file_size_opts::{self, FileSizeOpts};
+use humansize::FileSize;
+use num_format::{Locale, ToFormattedString};
+
+use crate::template::{Placeholder, Template};
+use crate::walk::{compare_paths, EntryKind, Error, RootEntry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+    Ndjson,
+    /// Raw `<bytes><TAB><path>` records, each terminated by a NUL byte instead of a newline
+    Print0,
+}
+
+/// The order in which `Walk::run_and_print_sorted` prints its results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Ascending by size; entries of equal size are ordered by path
+    Size,
+    /// Lexicographically by the bytes of the path
+    Name,
+}
+
+impl SortOrder {
+    pub(crate) fn compare(self, entry1: &RootEntry, entry2: &RootEntry) -> Ordering {
+        let by_name = || compare_paths(&entry1.path, &entry2.path);
+        match self {
+            SortOrder::Size => entry1.size.cmp(&entry2.size).then_with(by_name),
+            SortOrder::Name => by_name(),
+        }
+    }
+}
+
+/// Whether the output is highlighted with ANSI escape codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Use colors if the respective stream is a TTY and `NO_COLOR` is not set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self, stream: atty::Stream) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").map_or(true, |value| value.is_empty()) && atty::is(stream)
+            }
+        }
+    }
+
+    /// Whether results written to stdout should be colored.
+    pub fn for_stdout(self) -> bool {
+        self.enabled(atty::Stream::Stdout)
+    }
+
+    /// Whether errors and warnings written to stderr should be colored.
+    pub fn for_stderr(self) -> bool {
+        self.enabled(atty::Stream::Stderr)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Style {
+    /// Bold red, for errors and warnings
+    Error,
+    /// Bold cyan, for headers
+    Header,
+    /// Green, for the filled part of share bars
+    Bar,
+    /// Dim and underlined, for the names of the columns
+    ColumnHeader,
+}
+
+fn paint(text: &str, style: Style, color: bool) -> String {
+    if !color {
+        return text.to_string();
+    }
+    let code = match style {
+        Style::Error => "1;31",
+        Style::Header => "1;36",
+        Style::Bar => "32",
+        Style::ColumnHeader => "2;4",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+/// Options that control how `Walk::run_and_print` and `Walk::run_and_print_sorted` report the
+/// results.
+#[derive(Debug)]
+pub struct PrintOptions {
+    pub size_format: SizeFormat,
+    pub output_format: OutputFormat,
+    /// Print the total size of all roots
+    pub total: bool,
+    /// Print every filesystem error instead of a single warning
+    pub verbose: bool,
+    /// Print neither the filesystem errors nor the warning
+    pub quiet: bool,
+    /// Print only a few filesystem errors of each kind, followed by the number of omitted ones
+    pub summarize_errors: bool,
+    /// Print every filesystem error as a JSON object on its own line instead of a sentence
+    pub errors_json: bool,
+    /// Show the share of each root in the total size
+    pub percent: bool,
+    /// Show the share of each root in the total size as a bar like `[#####     ]`
+    pub bar: bool,
+    /// Show the number of files and directories below each root
+    pub count: bool,
+    /// Show the disk usage next to the apparent size of each root
+    pub both: bool,
+    /// Print the names of the columns before the text output
+    pub header: bool,
+    /// Show the size of the hardlinks that were not counted below each root
+    pub show_shared: bool,
+    pub color: ColorChoice,
+    /// Only show the given number of largest entries (`Walk::run_and_print_sorted` only)
+    pub top: Option<usize>,
+    /// Only show the entries of at least the given size in bytes (`Walk::run_and_print_sorted`
+    /// only)
+    pub min_size: Option<u64>,
+    /// List the given number of largest files across all roots after the results
+    pub biggest_files: Option<usize>,
+    /// Break the size of all files down by extension after the results, in at most the given
+    /// number of rows
+    pub by_extension: Option<usize>,
+    /// Break the size of all entries down by their owner after the results (Unix only)
+    pub by_owner: bool,
+    /// Break the size of all entries down by the filesystem they are stored on after the results
+    pub by_device: bool,
+    /// Redraw the partial size of every root while walking (`Walk::run_and_print` with text
+    /// output only, if stdout is a TTY)
+    pub live: bool,
+    /// Write the results to the given file instead of stdout
+    pub output_file: Option<PathBuf>,
+    /// Render every line of the text output with the given template
+    pub template: Option<Template>,
+    /// Align the columns of humanized text output and print the total below a header. Otherwise,
+    /// every line has the form `<size><TAB><path>`.
+    pub pretty: bool,
+    /// Separate groups of thousands in raw numbers of the text output with the given character
+    pub digit_separator: Option<char>,
+    /// Append `/` to directories and `@` to symlinks in the text output, like `ls -F`
+    pub classify: bool,
+}
+
+impl Default for PrintOptions {
+    fn default() -> PrintOptions {
+        PrintOptions {
+            size_format: SizeFormat::Humanized(file_size_opts::DECIMAL),
+            output_format: OutputFormat::Text,
+            total: false,
+            verbose: false,
+            quiet: false,
+            summarize_errors: false,
+            errors_json: false,
+            percent: false,
+            bar: false,
+            count: false,
+            both: false,
+            header: false,
+            show_shared: false,
+            color: ColorChoice::Auto,
+            top: None,
+            min_size: None,
+            biggest_files: None,
+            by_extension: None,
+            by_owner: false,
+            by_device: false,
+            live: false,
+            output_file: None,
+            template: None,
+            pretty: true,
+            digit_separator: None,
+            classify: false,
+        }
+    }
+}
+
+/// How sizes are rendered in the results.
+#[derive(Debug)]
+pub enum SizeFormat {
+    /// Human readable sizes like `1.23 MB`
+    Humanized(FileSizeOpts),
+    /// Exact byte counts
+    Bytes,
+    /// Number of blocks of the given size in bytes, rounded up like `du --block-size`
+    Blocks(u64),
+    /// Plain inode counts, for walks with `FilesizeType::Inodes`
+    Inodes,
+    /// Plain counts of 512-byte blocks, for walks with `FilesizeType::Blocks`
+    BlockCounts,
+}
+
+impl SizeFormat {
+    fn humanized(&self, size: u64) -> Option<String> {
+        match self {
+            SizeFormat::Humanized(size_format) => Some(size.file_size(size_format).unwrap()),
+            SizeFormat::Bytes
+            | SizeFormat::Blocks(_)
+            | SizeFormat::Inodes
+            | SizeFormat::BlockCounts => None,
+        }
+    }
+
+    fn raw(&self, size: u64) -> u64 {
+        match self {
+            SizeFormat::Blocks(block_size) => size.div_ceil(*block_size),
+            SizeFormat::Humanized(_)
+            | SizeFormat::Bytes
+            | SizeFormat::Inodes
+            | SizeFormat::BlockCounts => size,
+        }
+    }
+}
+
+/// Renders a number with the given separator between groups of thousands, e.g. `1,234,567`.
+fn group_digits(n: u64, separator: char) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() * 4 / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// A share of the total size, in tenths of a percent.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Percent(u64);
+
+impl Percent {
+    pub(crate) fn of(size: u64, total: u64) -> Percent {
+        if total == 0 {
+            return Percent(0);
+        }
+        let tenths = (u128::from(size) * 2000 / u128::from(total)).div_ceil(2);
+        // Rounding must not make a partial entry look like the whole total
+        let tenths = if size < total {
+            tenths.min(999)
+        } else {
+            tenths
+        };
+        Percent(tenths as u64)
+    }
+
+    fn number(self) -> String {
+        format!("{}.{}", self.0 / 10, self.0 % 10)
+    }
+
+    /// Renders the share as a bar with `width` cells between the brackets.
+    fn bar(self, width: usize, color: bool) -> String {
+        let filled = ((self.0.min(1000) as usize * width + 500) / 1000).min(width);
+        format!(
+            "[{}{}]",
+            paint(&"#".repeat(filled), Style::Bar, color && filled > 0),
+            " ".repeat(width - filled)
+        )
+    }
+}
+
+/// Widths of the aligned columns of humanized text output.
+const SIZE_WIDTH: usize = 10;
+const PERCENT_WIDTH: usize = 6;
+const COUNT_WIDTH: usize = 8;
+
+/// Width of share bars if the width of the terminal is unknown.
+const DEFAULT_BAR_WIDTH: usize = 20;
+
+/// The width of share bars: a quarter of the terminal, if the results are written to one.
+fn bar_width(out: &Sink) -> usize {
+    match term_size::dimensions_stdout() {
+        Some((columns, _)) if out.is_stdout => (columns / 4).clamp(10, 50),
+        _ => DEFAULT_BAR_WIDTH,
+    }
+}
+
+impl fmt::Display for Percent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&format!("{}%", self.number()))
+    }
+}
+
+/// Where the results of a walk are written to: stdout or `PrintOptions::output_file`.
+pub(crate) struct Sink {
+    out: Box<dyn Write + Send>,
+    is_stdout: bool,
+    /// The temporary file that is renamed to the output file once all results are written, so
+    /// that an aborted run never leaves a truncated file behind. It is removed if the results
+    /// are never committed.
+    pending: Option<(PathBuf, PathBuf)>,
+    /// The first error while writing, after which nothing is written anymore. It is returned by
+    /// `commit`.
+    error: Option<io::Error>,
+}
+
+impl Sink {
+    fn open(output_file: Option<&Path>) -> io::Result<Sink> {
+        let output_file = match output_file {
+            Some(output_file) => output_file,
+            None => {
+                return Ok(Sink {
+                    out: Box::new(io::stdout()),
+                    is_stdout: true,
+                    pending: None,
+                    error: None,
+                })
+            }
+        };
+
+        let file_name = output_file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let temp_file =
+            output_file.with_file_name(format!(".{}.diskus-{}.tmp", file_name, process::id()));
+        match File::create(&temp_file) {
+            Ok(file) => Ok(Sink {
+                out: Box::new(BufWriter::new(file)),
+                is_stdout: false,
+                pending: Some((temp_file, output_file.to_path_buf())),
+                error: None,
+            }),
+            Err(err) => Err(io::Error::new(
+                err.kind(),
+                format!(
+                    "could not create output file '{}': {}",
+                    output_file.to_string_lossy(),
+                    err
+                ),
+            )),
+        }
+    }
+
+    fn write(&mut self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.error.is_none() {
+            let result = self.out.write_all(bytes);
+            self.record(result);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.error.is_none() {
+            let result = self.out.flush();
+            self.record(result);
+        }
+    }
+
+    /// Keeps the error of a write (if any), with the file it happened in.
+    fn record(&mut self, result: io::Result<()>) {
+        self.error = result.err().map(|err| match &self.pending {
+            Some((_, output_file)) => io::Error::new(
+                err.kind(),
+                format!(
+                    "could not write output file '{}': {}",
+                    output_file.to_string_lossy(),
+                    err
+                ),
+            ),
+            None => io::Error::new(err.kind(), format!("could not write the results: {}", err)),
+        });
+    }
+
+    /// Whether writing failed, so that the walk can stop early.
+    pub(crate) fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// Whether the highlighted parts of the results should be colored.
+    fn color(&self, color: ColorChoice) -> bool {
+        match color {
+            ColorChoice::Auto if !self.is_stdout => false,
+            _ => color.for_stdout(),
+        }
+    }
+
+    /// Moves the output file into place, once all results have been written. If writing failed,
+    /// the error is returned instead, and the temporary file is removed on drop.
+    fn commit(&mut self) -> io::Result<()> {
+        self.flush();
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        if let Some((temp_file, output_file)) = self.pending.take() {
+            if let Err(err) = fs::rename(&temp_file, &output_file) {
+                let _ = fs::remove_file(&temp_file);
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!(
+                        "could not write output file '{}': {}",
+                        output_file.to_string_lossy(),
+                        err
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        if let Some((temp_file, _)) = self.pending.take() {
+            // Closed first, as open files cannot be removed on Windows
+            self.out = Box::new(io::sink());
+            let _ = fs::remove_file(temp_file);
+        }
+    }
+}
+
+pub(crate) fn print_error(err: &dyn fmt::Display, color: ColorChoice) {
+    eprintln!(
+        "{} {}",
+        paint("diskus:", Style::Error, color.for_stderr()),
+        err
+    );
+}
+
+/// Prints an error as a single line like `{"kind": "could_not_read_dir", "path": "/root"}`.
+fn print_error_json(err: &Error) {
+    eprintln!(
+        "{{\"kind\": \"{}\", {}}}",
+        err.kind(),
+        json_path(err.path())
+    );
+}
+
+/// Prints a warning about the results on stderr, with a `[diskus warning]` label that is colored
+/// like the errors.
+pub fn print_warning(color: ColorChoice, message: fmt::Arguments) {
+    eprintln!(
+        "{} {}",
+        paint("[diskus warning]", Style::Error, color.for_stderr()),
+        message
+    );
+}
+
+/// Prints a note about the walk on stderr, with a `diskus:` label that is colored like the
+/// headers.
+pub fn print_note(color: ColorChoice, message: fmt::Arguments) {
+    eprintln!(
+        "{} {}",
+        paint("diskus:", Style::Header, color.for_stderr()),
+        message
+    );
+}
+
+const TAINTED_RESULTS: &str =
+    "the results may be tainted. Re-run with -v/--verbose to print all errors.";
+
+/// Number of examples of each kind of error that are kept for `PrintOptions::summarize_errors`.
+const MAX_ERROR_EXAMPLES: usize = 3;
+
+/// The first few errors of one kind, and how many there were in total.
+#[derive(Default)]
+struct ErrorGroup {
+    /// The messages of the first errors
+    examples: Vec<String>,
+    count: u64,
+}
+
+impl ErrorGroup {
+    fn add(&mut self, error: &Error) {
+        if self.examples.len() < MAX_ERROR_EXAMPLES {
+            self.examples.push(error.to_string());
+        }
+        self.count += 1;
+    }
+
+    fn print(&self, description: &str, color: ColorChoice) {
+        for error in &self.examples {
+            print_error(error, color);
+        }
+        let more = self.count - self.examples.len() as u64;
+        if more > 0 {
+            eprintln!(
+                "{} … and {} more {}",
+                paint("diskus:", Style::Error, color.for_stderr()),
+                more.to_formatted_string(&Locale::en),
+                description
+            );
+        }
+    }
+}
+
+/// Reports the filesystem errors of a walk on stderr, as selected by the `verbose`, `quiet`
+/// and `summarize_errors` options. The receiver thread passes every error to `error` and calls
+/// `finish` once the walk is done.
+pub(crate) struct ErrorReporter {
+    verbose: bool,
+    quiet: bool,
+    summarize: bool,
+    json: bool,
+    color: ColorChoice,
+    count: u64,
+    no_metadata: ErrorGroup,
+    unreadable_dirs: ErrorGroup,
+    filesystem_loops: ErrorGroup,
+    unreadable_devices: ErrorGroup,
+}
+
+impl ErrorReporter {
+    pub(crate) fn new(options: &PrintOptions) -> ErrorReporter {
+        ErrorReporter {
+            verbose: options.verbose,
+            quiet: options.quiet,
+            summarize: options.summarize_errors,
+            json: options.errors_json,
+            color: options.color,
+            count: 0,
+            no_metadata: ErrorGroup::default(),
+            unreadable_dirs: ErrorGroup::default(),
+            filesystem_loops: ErrorGroup::default(),
+            unreadable_devices: ErrorGroup::default(),
+        }
+    }
+
+    pub(crate) fn error(&mut self, error: &Error) {
+        self.count += 1;
+        if self.json {
+            print_error_json(error);
+        } else if self.verbose {
+            print_error(error, self.color);
+        } else if self.summarize {
+            match error {
+                Error::NoMetadataForRoot(..) | Error::NoMetadataForPath(..) => {
+                    self.no_metadata.add(error)
+                }
+                Error::CouldNotReadDir(..) => self.unreadable_dirs.add(error),
+                Error::FilesystemLoop(_) => self.filesystem_loops.add(error),
+                Error::CouldNotReadDevice(_) => self.unreadable_devices.add(error),
+            }
+        }
+    }
+
+    /// Reports a root that was not walked because it is contained in another one (see
+    /// `Walk::allow_overlap`).
+    pub(crate) fn overlapping_root(&self, path: &Path, outer: &Path) {
+        if !self.quiet {
+            print_warning(
+                self.color,
+                format_args!(
+                    "'{}' is already contained in '{}' and is skipped, use --allow-overlap to \
+                     walk it anyway",
+                    path.to_string_lossy(),
+                    outer.to_string_lossy()
+                ),
+            );
+        }
+    }
+
+    /// Reports a directory that was not walked because of `Walk::one_file_system`. This is not
+    /// an error, so it is only shown with `verbose`.
+    pub(crate) fn skipped_mount_point(&self, path: &Path) {
+        if self.verbose {
+            eprintln!(
+                "{} skipped mount point '{}'",
+                paint("diskus:", Style::Header, self.color.for_stderr()),
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    /// Reports a file that was counted despite `Walk::newer_than`, because its modification time
+    /// is unknown or in the future. Like skipped mount points, it is only shown with `verbose`.
+    pub(crate) fn undated_file(&self, path: &Path) {
+        if self.verbose {
+            eprintln!(
+                "{} counted '{}', its modification time is unknown or in the future",
+                paint("diskus:", Style::Header, self.color.for_stderr()),
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    /// Reports a root that is a block device, whose capacity is reported instead of its usage.
+    pub(crate) fn device_capacity(&self, path: &Path) {
+        if !self.quiet {
+            eprintln!(
+                "{} the size of '{}' is the capacity of the device, not how much of it is used",
+                paint("diskus:", Style::Header, self.color.for_stderr()),
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    /// Reports a directory whose contents were not walked because of `Walk::skip_cache_dirs`,
+    /// only with `verbose`.
+    pub(crate) fn cache_dir(&self, path: &Path) {
+        if self.verbose {
+            eprintln!(
+                "{} skipped the contents of cache directory '{}'",
+                paint("diskus:", Style::Header, self.color.for_stderr()),
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    /// Reports a file that was counted without its extended attributes, because they could not
+    /// be read (see `Walk::include_xattrs`). Only shown with `verbose`.
+    pub(crate) fn unreadable_xattrs(&self, path: &Path) {
+        if self.verbose {
+            eprintln!(
+                "{} counted '{}' without its extended attributes, they could not be read",
+                paint("diskus:", Style::Header, self.color.for_stderr()),
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    pub(crate) fn finish(&self) {
+        // With JSON errors, the consumer already has all the details
+        if self.count == 0 || self.verbose || self.quiet || self.json {
+            return;
+        }
+        if self.summarize {
+            self.no_metadata.print("paths without metadata", self.color);
+            self.unreadable_dirs
+                .print("unreadable directories", self.color);
+            self.filesystem_loops.print("filesystem loops", self.color);
+            self.unreadable_devices
+                .print("unreadable devices", self.color);
+        } else {
+            print_warning(self.color, format_args!("{}", TAINTED_RESULTS));
+        }
+    }
+}
+
+fn print_result<P: AsRef<Path>>(out: &mut Sink, path: P, columns: &[String], terminator: u8) {
+    let mut line = vec![];
+    for column in columns {
+        line.extend_from_slice(column.as_bytes());
+        line.push(b'\t');
+    }
+    line.extend_from_slice(&path_bytes(path.as_ref()));
+    line.push(terminator);
+    out.write_bytes(&line);
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+/// The path of an entry with an indicator of its type appended, like `ls -F` does.
+fn classified(entry: &RootEntry) -> PathBuf {
+    let indicator = match entry.kind {
+        Some(EntryKind::Dir) => "/",
+        Some(EntryKind::Symlink) => "@",
+        Some(EntryKind::File) | None => return entry.path.clone(),
+    };
+    let mut path = entry.path.clone().into_os_string();
+    // 'foo/' is not turned into 'foo//'
+    if !path_bytes(&entry.path).ends_with(indicator.as_bytes()) {
+        path.push(indicator);
+    }
+    PathBuf::from(path)
+}
+
+/// What the sizes in a `Breakdown` are grouped by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BreakdownKind {
+    Extension,
+    Owner,
+    Device,
+}
+
+impl BreakdownKind {
+    fn title(self) -> &'static str {
+        match self {
+            BreakdownKind::Extension => "By extension:",
+            BreakdownKind::Owner => "By owner:",
+            BreakdownKind::Device => "By filesystem:",
+        }
+    }
+
+    /// The name of the grouping key in JSON output.
+    fn key(self) -> &'static str {
+        match self {
+            BreakdownKind::Extension => "extension",
+            BreakdownKind::Owner => "owner",
+            BreakdownKind::Device => "device",
+        }
+    }
+}
+
+/// The size of all files, grouped by some property, largest group first.
+pub(crate) struct Breakdown {
+    kind: BreakdownKind,
+    rows: Vec<(String, u64)>,
+    total: u64,
+}
+
+impl Breakdown {
+    /// Keeps the `max_rows - 1` largest groups, the rest is summed up in an `<other>` row.
+    pub(crate) fn new(kind: BreakdownKind, sizes: HashMap<String, u64>, max_rows: usize) -> Self {
+        let total = sizes.values().sum();
+        let mut rows: Vec<(String, u64)> = sizes.into_iter().collect();
+        rows.sort_by(|(name1, size1), (name2, size2)| size2.cmp(size1).then(name1.cmp(name2)));
+        if rows.len() > max_rows {
+            let other = rows.split_off(max_rows.saturating_sub(1));
+            rows.push((
+                "<other>".to_string(),
+                other.iter().map(|(_, size)| size).sum(),
+            ));
+        }
+        Breakdown { kind, rows, total }
+    }
+}
+
+/// A single root in the results.
+pub(crate) struct Row<'a> {
+    pub entry: &'a RootEntry,
+    pub errors: &'a [Error],
+    pub percent: Option<Percent>,
+}
+
+/// The partial size of a root while it is being walked.
+pub(crate) struct LiveRow<'a> {
+    pub path: &'a Path,
+    pub size: u64,
+    pub finished: bool,
+}
+
+/// Renders the results of a walk. The receiver thread calls `begin` once, feeds every finished
+/// root into `entry`, followed by an optional `omitted` summary, `total` and `largest_files`, and
+/// finally calls `finish`. Errors are passed to `error` as soon as they are received and once
+/// more to the `entry` of their root.
+pub(crate) trait Formatter: Send {
+    fn begin(&mut self) {}
+
+    fn error(&mut self, _error: &Error) {}
+
+    fn entry(&mut self, row: &Row);
+
+    /// Summarizes the `count` entries that were not shown because of `PrintOptions::top` or
+    /// `PrintOptions::min_size`.
+    fn omitted(&mut self, _count: usize, _omitted: &RootEntry) {}
+
+    fn total(&mut self, total: &RootEntry);
+
+    /// Lists the largest files of all roots, largest first (see `PrintOptions::biggest_files`).
+    fn largest_files(&mut self, _files: &[RootEntry]) {}
+
+    fn breakdown(&mut self, _breakdown: &Breakdown) {}
+
+    fn end(&mut self) {}
+
+    fn sink(&mut self) -> &mut Sink;
+
+    /// Ends the output and makes it visible in `PrintOptions::output_file`.
+    fn finish(&mut self) -> io::Result<()> {
+        self.end();
+        self.sink().commit()
+    }
+
+    /// Redraws the partial sizes of all roots in place (see `PrintOptions::live`).
+    fn live(&mut self, _rows: &[LiveRow]) {}
+
+    /// Removes everything that was drawn by `live`.
+    fn clear_live(&mut self) {}
+}
+
+pub(crate) fn formatter(options: PrintOptions) -> io::Result<Box<dyn Formatter>> {
+    let out = Sink::open(options.output_file.as_deref())?;
+    Ok(match options.output_format {
+        OutputFormat::Text => Box::new(TextFormatter {
+            bar_width: bar_width(&out),
+            out,
+            options,
+            terminator: b'\n',
+            live_lines: 0,
+        }),
+        OutputFormat::Print0 => Box::new(TextFormatter {
+            out,
+            options: PrintOptions {
+                size_format: SizeFormat::Bytes,
+                digit_separator: None,
+                classify: false,
+                ..options
+            },
+            terminator: b'\0',
+            live_lines: 0,
+            bar_width: 0,
+        }),
+        OutputFormat::Json => Box::new(JsonFormatter {
+            out,
+            options,
+            first_entry: true,
+            omitted: None,
+            total: None,
+            largest_files: vec![],
+            breakdowns: vec![],
+        }),
+        OutputFormat::Csv => Box::new(CsvFormatter { out, options }),
+        OutputFormat::Ndjson => Box::new(NdjsonFormatter { out, options }),
+    })
+}
+
+struct TextFormatter {
+    out: Sink,
+    options: PrintOptions,
+    terminator: u8,
+    /// Number of lines drawn by the last call to `live`
+    live_lines: usize,
+    bar_width: usize,
+}
+
+impl TextFormatter {
+    fn columns(&self, entry: &RootEntry, percent: Option<Percent>) -> Vec<String> {
+        let size_format = &self.options.size_format;
+        let mut columns = vec![];
+
+        // The format of NUL-separated records is fixed
+        if self.terminator == b'\0' {
+            columns.push(size_format.raw(entry.size).to_string());
+            return columns;
+        }
+
+        // Humanized output is meant to be read, so the columns are aligned. Raw numbers are
+        // meant for scripts and are only separated by tabs.
+        let pretty = self.pretty();
+        let color = self.out.color(self.options.color);
+        let mut push = |value: String, width: usize| {
+            columns.push(if pretty {
+                format!("{: >width$}", value)
+            } else {
+                value
+            })
+        };
+
+        let sizes = std::iter::once(entry.size)
+            .chain(self.options.both.then(|| entry.disk_usage.unwrap_or(0)));
+        for size in sizes {
+            match size_format.humanized(size) {
+                Some(size) => push(size, SIZE_WIDTH),
+                None => push(self.size(size), 0),
+            }
+        }
+        if self.options.bar {
+            let bar = percent.map(|p| p.bar(self.bar_width, color));
+            push(bar.unwrap_or_default(), self.bar_width + 2);
+        }
+        if self.options.percent {
+            push(
+                percent.map(|p| p.to_string()).unwrap_or_default(),
+                PERCENT_WIDTH,
+            );
+        }
+        if self.options.count {
+            push(entry.files.to_string(), COUNT_WIDTH);
+            push(entry.dirs.to_string(), COUNT_WIDTH);
+        }
+
+        columns
+    }
+
+    /// The names of the columns, aligned like the `columns` of every entry.
+    fn header(&self) -> Vec<String> {
+        let mut names = vec![];
+        if self.options.both {
+            names.push(("APPARENT", SIZE_WIDTH));
+            names.push(("DISK", SIZE_WIDTH));
+        } else {
+            names.push(("SIZE", SIZE_WIDTH));
+        }
+        if self.options.bar {
+            names.push(("SHARE", self.bar_width + 2));
+        }
+        if self.options.percent {
+            names.push(("%", PERCENT_WIDTH));
+        }
+        if self.options.count {
+            names.push(("FILES", COUNT_WIDTH));
+            names.push(("DIRS", COUNT_WIDTH));
+        }
+
+        let pretty = self.pretty();
+        names
+            .into_iter()
+            .map(|(name, width)| {
+                if pretty {
+                    format!("{: >width$}", name)
+                } else {
+                    name.to_string()
+                }
+            })
+            .collect()
+    }
+
+    fn print_header(&mut self) {
+        let color = self.out.color(self.options.color);
+        let mut line: Vec<String> = self
+            .header()
+            .iter()
+            .map(|name| paint(name, Style::ColumnHeader, color))
+            .collect();
+        line.push(paint("PATH", Style::ColumnHeader, color));
+        self.out.write(&(line.join("\t") + "\n"));
+    }
+
+    /// The size in the selected size format, as shown in the text output.
+    fn size(&self, size: u64) -> String {
+        let size_format = &self.options.size_format;
+        match (size_format.humanized(size), self.options.digit_separator) {
+            (Some(size), _) => size,
+            (None, Some(separator)) => group_digits(size_format.raw(size), separator),
+            (None, None) => size_format.raw(size).to_string(),
+        }
+    }
+
+    fn print_shared(&mut self, entry: &RootEntry) {
+        // NUL-separated records have to stay parseable
+        if self.options.show_shared && self.terminator != b'\0' {
+            let size = self.size(entry.shared);
+            self.out
+                .write(&format!("  shared via hardlinks: {}\n", size));
+        }
+    }
+
+    fn pretty(&self) -> bool {
+        self.options.pretty && matches!(self.options.size_format, SizeFormat::Humanized(_))
+    }
+
+    fn print(&mut self, path: &Path, entry: &RootEntry, percent: Option<Percent>) {
+        match self.options.template {
+            Some(ref template) => {
+                let mut line = template.render(|placeholder| {
+                    self.placeholder_value(placeholder, path, entry, percent)
+                });
+                line.push(self.terminator);
+                self.out.write_bytes(&line);
+            }
+            None => {
+                let columns = self.columns(entry, percent);
+                print_result(&mut self.out, path, &columns, self.terminator);
+            }
+        }
+    }
+
+    fn placeholder_value(
+        &self,
+        placeholder: Placeholder,
+        path: &Path,
+        entry: &RootEntry,
+        percent: Option<Percent>,
+    ) -> Vec<u8> {
+        let value = match placeholder {
+            Placeholder::Path => return path_bytes(path).into_owned(),
+            Placeholder::Size => self.size(entry.size),
+            Placeholder::SizeBytes => entry.size.to_string(),
+            Placeholder::Percent => percent
+                .map(|percent| format!("{}%", percent.number()))
+                .unwrap_or_default(),
+            Placeholder::Files => entry.files.to_string(),
+            Placeholder::Dirs => entry.dirs.to_string(),
+        };
+        value.into_bytes()
+    }
+}
+
+impl Formatter for TextFormatter {
+    fn sink(&mut self) -> &mut Sink {
+        &mut self.out
+    }
+
+    fn begin(&mut self) {
+        // NUL-separated records have to stay parseable, and templates have no columns
+        if self.options.header && self.terminator != b'\0' && self.options.template.is_none() {
+            self.print_header();
+        }
+    }
+
+    fn entry(&mut self, row: &Row) {
+        if self.options.classify {
+            self.print(&classified(row.entry), row.entry, row.percent);
+        } else {
+            self.print(&row.entry.path, row.entry, row.percent);
+        }
+        if row.entry.depth == 0 {
+            self.print_shared(row.entry);
+        }
+    }
+
+    fn omitted(&mut self, count: usize, omitted: &RootEntry) {
+        // NUL-separated records have to stay parseable
+        if self.terminator == b'\0' {
+            return;
+        }
+        let size = self.size(omitted.size);
+        self.out.write(&format!(
+            "… and {} smaller {} ({})\n",
+            count,
+            if count == 1 { "entry" } else { "entries" },
+            size
+        ));
+    }
+
+    fn total(&mut self, total: &RootEntry) {
+        let columns = self.columns(total, None);
+        match self.options.size_format {
+            _ if self.options.template.is_some() => self.print(Path::new("total"), total, None),
+            SizeFormat::Humanized(_) if self.pretty() && self.terminator != b'\0' => {
+                let header = paint("Total:", Style::Header, self.out.color(self.options.color));
+                self.out.write(&format!("\n{}\n", header));
+                print_result(&mut self.out, "", &columns, self.terminator);
+            }
+            // Raw numbers are meant for scripts, so the total follows the same
+            // '<size><TAB><path>' form as all other lines (like `du -c`)
+            _ => print_result(&mut self.out, "total", &columns, self.terminator),
+        }
+        self.print_shared(total);
+    }
+
+    fn largest_files(&mut self, files: &[RootEntry]) {
+        // NUL-separated records have to stay parseable
+        if self.terminator == b'\0' {
+            return;
+        }
+        let header = paint(
+            "Biggest files:",
+            Style::Header,
+            self.out.color(self.options.color),
+        );
+        self.out.write(&format!("\n{}\n", header));
+        for file in files {
+            let size = self.size(file.size);
+            let size = if self.pretty() {
+                format!("{: >width$}", size, width = SIZE_WIDTH)
+            } else {
+                size
+            };
+            print_result(&mut self.out, &file.path, &[size], self.terminator);
+        }
+    }
+
+    fn breakdown(&mut self, breakdown: &Breakdown) {
+        // NUL-separated records have to stay parseable
+        if self.terminator == b'\0' {
+            return;
+        }
+        let header = paint(
+            breakdown.kind.title(),
+            Style::Header,
+            self.out.color(self.options.color),
+        );
+        self.out.write(&format!("\n{}\n", header));
+        for (name, size) in &breakdown.rows {
+            let percent = Percent::of(*size, breakdown.total);
+            let size = self.size(*size);
+            let columns = if self.pretty() {
+                vec![
+                    format!("{: >width$}", size, width = SIZE_WIDTH),
+                    format!("{: >width$}", percent, width = PERCENT_WIDTH),
+                ]
+            } else {
+                vec![size, percent.to_string()]
+            };
+            print_result(&mut self.out, name, &columns, self.terminator);
+        }
+    }
+
+    fn live(&mut self, rows: &[LiveRow]) {
+        let mut lines = String::new();
+        if self.live_lines > 0 {
+            let _ = write!(lines, "\x1b[{}A", self.live_lines);
+        }
+        for row in rows {
+            let _ = writeln!(
+                lines,
+                "\r\x1b[K{: >10}\t{}{}",
+                self.size(row.size),
+                row.path.to_string_lossy(),
+                if row.finished { "" } else { " …" }
+            );
+        }
+        self.out.write(&lines);
+        self.out.flush();
+        self.live_lines = rows.len();
+    }
+
+    fn clear_live(&mut self) {
+        if self.live_lines > 0 {
+            self.out.write(&format!("\x1b[{}A\x1b[J", self.live_lines));
+            self.live_lines = 0;
+        }
+    }
+}
+
+struct JsonFormatter {
+    out: Sink,
+    options: PrintOptions,
+    first_entry: bool,
+    omitted: Option<(usize, RootEntry)>,
+    total: Option<RootEntry>,
+    largest_files: Vec<RootEntry>,
+    breakdowns: Vec<String>,
+}
+
+impl Formatter for JsonFormatter {
+    fn sink(&mut self) -> &mut Sink {
+        &mut self.out
+    }
+
+    fn begin(&mut self) {
+        self.out.write("{\n  \"roots\": [");
+    }
+
+    fn entry(&mut self, row: &Row) {
+        let errors: Vec<String> = row
+            .errors
+            .iter()
+            .map(|err| {
+                format!(
+                    "{{\"kind\": \"{}\", {}}}",
+                    err.kind(),
+                    json_path(err.path())
+                )
+            })
+            .collect();
+        self.out.write(&format!(
+            "{}\n    {{{}, {}{}, \"errors\": [{}]}}",
+            if self.first_entry { "" } else { "," },
+            json_path(&row.entry.path),
+            json_fields(row.entry, &self.options),
+            json_percent(row.percent),
+            errors.join(", ")
+        ));
+        self.first_entry = false;
+    }
+
+    fn omitted(&mut self, count: usize, omitted: &RootEntry) {
+        self.omitted = Some((count, omitted.clone()));
+    }
+
+    fn total(&mut self, total: &RootEntry) {
+        self.total = Some(total.clone());
+    }
+
+    fn largest_files(&mut self, files: &[RootEntry]) {
+        self.largest_files = files.to_vec();
+    }
+
+    fn breakdown(&mut self, breakdown: &Breakdown) {
+        let rows: Vec<String> = json_breakdown_rows(breakdown, &self.options)
+            .map(|row| format!("\n    {}", row))
+            .collect();
+        self.breakdowns.push(format!(
+            ",\n  \"by_{}\": [{}\n  ]",
+            breakdown.kind.key(),
+            rows.join(",")
+        ));
+    }
+
+    fn end(&mut self) {
+        let mut footer = "\n  ]".to_string();
+        if let Some((count, ref omitted)) = self.omitted {
+            let _ = write!(
+                footer,
+                ",\n  \"omitted\": {{\"count\": {}, {}}}",
+                count,
+                json_fields(omitted, &self.options)
+            );
+        }
+        if let Some(ref total) = self.total {
+            let _ = write!(
+                footer,
+                ",\n  \"total\": {{{}}}",
+                json_fields(total, &self.options)
+            );
+        }
+        if !self.largest_files.is_empty() {
+            let files: Vec<String> = self
+                .largest_files
+                .iter()
+                .map(|file| {
+                    format!(
+                        "\n    {{{}, {}}}",
+                        json_path(&file.path),
+                        json_size(file.size, &self.options.size_format)
+                    )
+                })
+                .collect();
+            let _ = write!(footer, ",\n  \"biggest_files\": [{}\n  ]", files.join(","));
+        }
+        for breakdown in &self.breakdowns {
+            footer.push_str(breakdown);
+        }
+        footer.push_str("\n}\n");
+        self.out.write(&footer);
+    }
+}
+
+/// Writes one JSON object per line and flushes after each of them, so that consumers see every
+/// root as soon as it is finished.
+struct NdjsonFormatter {
+    out: Sink,
+    options: PrintOptions,
+}
+
+impl NdjsonFormatter {
+    fn line(&mut self, line: String) {
+        self.out.write(&(line + "\n"));
+        self.out.flush();
+    }
+}
+
+impl Formatter for NdjsonFormatter {
+    fn sink(&mut self) -> &mut Sink {
+        &mut self.out
+    }
+
+    fn error(&mut self, error: &Error) {
+        self.line(format!(
+            "{{\"error\": \"{}\", {}}}",
+            error.kind(),
+            json_path(error.path())
+        ));
+    }
+
+    fn entry(&mut self, row: &Row) {
+        self.line(format!(
+            "{{{}, {}{}}}",
+            json_path(&row.entry.path),
+            json_fields(row.entry, &self.options),
+            json_percent(row.percent)
+        ));
+    }
+
+    fn omitted(&mut self, count: usize, omitted: &RootEntry) {
+        self.line(format!(
+            "{{\"omitted\": {{\"count\": {}, {}}}}}",
+            count,
+            json_fields(omitted, &self.options)
+        ));
+    }
+
+    fn total(&mut self, total: &RootEntry) {
+        self.line(format!(
+            "{{\"total\": {{{}}}}}",
+            json_fields(total, &self.options)
+        ));
+    }
+
+    fn largest_files(&mut self, files: &[RootEntry]) {
+        for file in files {
+            self.line(format!(
+                "{{\"biggest_file\": {{{}, {}}}}}",
+                json_path(&file.path),
+                json_size(file.size, &self.options.size_format)
+            ));
+        }
+    }
+
+    fn breakdown(&mut self, breakdown: &Breakdown) {
+        let rows: Vec<String> = json_breakdown_rows(breakdown, &self.options)
+            .map(|row| format!("{{\"by_{}\": {}}}", breakdown.kind.key(), row))
+            .collect();
+        for row in rows {
+            self.line(row);
+        }
+    }
+}
+
+struct CsvFormatter {
+    out: Sink,
+    options: PrintOptions,
+}
+
+impl CsvFormatter {
+    fn row(&mut self, path: &str, entry: &RootEntry, percent: Option<Percent>) {
+        let mut row = format!("{},{}", csv_field(path), entry.size);
+        if !matches!(
+            self.options.size_format,
+            SizeFormat::Inodes | SizeFormat::BlockCounts
+        ) {
+            row.push(',');
+            row.push_str(
+                &self
+                    .options
+                    .size_format
+                    .humanized(entry.size)
+                    .unwrap_or_default(),
+            );
+        }
+        if self.options.percent {
+            row.push(',');
+            row.push_str(&percent.map(Percent::number).unwrap_or_default());
+        }
+        if self.options.both {
+            let _ = write!(row, ",{}", entry.disk_usage.unwrap_or(0));
+        }
+        if self.options.count {
+            let _ = write!(row, ",{},{}", entry.files, entry.dirs);
+        }
+        if self.options.show_shared {
+            let _ = write!(row, ",{}", entry.shared);
+        }
+        row.push('\n');
+        self.out.write(&row);
+    }
+}
+
+impl Formatter for CsvFormatter {
+    fn sink(&mut self) -> &mut Sink {
+        &mut self.out
+    }
+
+    fn begin(&mut self) {
+        let mut header = match self.options.size_format {
+            SizeFormat::Inodes => "path,inodes".to_string(),
+            SizeFormat::BlockCounts => "path,blocks".to_string(),
+            _ => "path,size_bytes,size_human".to_string(),
+        };
+        if self.options.percent {
+            header.push_str(",percent");
+        }
+        if self.options.both {
+            header.push_str(",disk_usage_bytes");
+        }
+        if self.options.count {
+            header.push_str(",files,dirs");
+        }
+        if self.options.show_shared {
+            header.push_str(",shared_bytes");
+        }
+        header.push('\n');
+        self.out.write(&header);
+    }
+
+    fn entry(&mut self, row: &Row) {
+        self.row(&row.entry.path.to_string_lossy(), row.entry, row.percent);
+    }
+
+    fn total(&mut self, total: &RootEntry) {
+        // The total row is the only one with an empty path
+        self.row("", total, None);
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Renders the size (and, if requested, the counts) of an entry as JSON object members.
+fn json_fields(entry: &RootEntry, options: &PrintOptions) -> String {
+    let mut fields = json_size(entry.size, &options.size_format);
+    if options.both {
+        let disk_usage = entry.disk_usage.unwrap_or(0);
+        let _ = write!(fields, ", \"disk_usage_bytes\": {}", disk_usage);
+        if let Some(human) = options.size_format.humanized(disk_usage) {
+            let _ = write!(fields, ", \"disk_usage_human\": {}", json_string(&human));
+        }
+    }
+    if options.count {
+        let _ = write!(
+            fields,
+            ", \"files\": {}, \"dirs\": {}",
+            entry.files, entry.dirs
+        );
+    }
+    if options.show_shared {
+        let _ = write!(fields, ", \"shared_bytes\": {}", entry.shared);
+    }
+    fields
+}
+
+fn json_size(size: u64, size_format: &SizeFormat) -> String {
+    match size_format {
+        SizeFormat::Humanized(_) => format!(
+            "\"size_bytes\": {}, \"size_human\": {}",
+            size,
+            json_string(&size_format.humanized(size).unwrap())
+        ),
+        SizeFormat::Bytes => format!("\"size_bytes\": {}", size),
+        SizeFormat::Blocks(_) => format!(
+            "\"size_bytes\": {}, \"size_blocks\": {}",
+            size,
+            size_format.raw(size)
+        ),
+        SizeFormat::Inodes => format!("\"inodes\": {}", size),
+        SizeFormat::BlockCounts => format!("\"blocks\": {}", size),
+    }
+}
+
+/// Renders every row of a breakdown as a JSON object like `{"extension": "mp4", ..}`.
+fn json_breakdown_rows<'a>(
+    breakdown: &'a Breakdown,
+    options: &'a PrintOptions,
+) -> impl Iterator<Item = String> + 'a {
+    breakdown.rows.iter().map(move |(name, size)| {
+        format!(
+            "{{{}: {}, {}{}}}",
+            json_string(breakdown.kind.key()),
+            json_string(name),
+            json_size(*size, &options.size_format),
+            json_percent(Some(Percent::of(*size, breakdown.total)))
+        )
+    })
+}
+
+fn json_percent(percent: Option<Percent>) -> String {
+    match percent {
+        Some(percent) => format!(", \"percent\": {}", percent.number()),
+        None => String::new(),
+    }
+}
+
+/// Renders the `"path"` member of a JSON object. Paths that are not valid UTF-8 are written as a
+/// lossy string, followed by the raw bytes in a `"path_bytes"` array on Unix (or a
+/// `"path_lossy": true` marker elsewhere) so that no information is silently lost.
+fn json_path(path: &Path) -> String {
+    match path.to_str() {
+        Some(s) => format!("\"path\": {}", json_string(s)),
+        None => format!(
+            "\"path\": {}, {}",
+            json_string(&path.to_string_lossy()),
+            json_path_bytes(path)
+        ),
+    }
+}
+
+#[cfg(unix)]
+fn json_path_bytes(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let bytes: Vec<String> = path
+        .as_os_str()
+        .as_bytes()
+        .iter()
+        .map(|b| b.to_string())
+        .collect();
+    format!("\"path_bytes\": [{}]", bytes.join(", "))
+}
+
+#[cfg(not(unix))]
+fn json_path_bytes(_path: &Path) -> String {
+    "\"path_lossy\": true".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_formatter(options: PrintOptions) -> TextFormatter {
+        TextFormatter {
+            out: Sink::open(None).unwrap(),
+            options,
+            terminator: b'\n',
+            live_lines: 0,
+            bar_width: 10,
+        }
+    }
+
+    fn entry(size: u64) -> RootEntry {
+        named_entry("foo", size)
+    }
+
+    fn named_entry(path: &str, size: u64) -> RootEntry {
+        RootEntry {
+            path: PathBuf::from(path),
+            size,
+            files: 1,
+            dirs: 0,
+            depth: 0,
+            kind: None,
+            shared: 0,
+            disk_usage: None,
+        }
+    }
+
+    #[test]
+    fn pretty_columns_are_aligned() {
+        let formatter = text_formatter(PrintOptions {
+            count: true,
+            ..PrintOptions::default()
+        });
+        assert_eq!(
+            formatter.columns(&entry(1234), None),
+            ["   1.23 KB", "       1", "       0"]
+        );
+    }
+
+    #[test]
+    fn plain_columns_are_not_padded() {
+        let formatter = text_formatter(PrintOptions {
+            count: true,
+            pretty: false,
+            ..PrintOptions::default()
+        });
+        assert_eq!(formatter.columns(&entry(1234), None), ["1.23 KB", "1", "0"]);
+
+        let formatter = text_formatter(PrintOptions {
+            size_format: SizeFormat::Bytes,
+            ..PrintOptions::default()
+        });
+        assert_eq!(formatter.columns(&entry(1234), None), ["1234"]);
+    }
+
+    #[test]
+    fn equal_sizes_are_ordered_by_path() {
+        let mut entries = [
+            named_entry("b", 100),
+            named_entry("c", 50),
+            named_entry("a", 100),
+        ];
+        entries.sort_by(|a, b| SortOrder::Size.compare(a, b));
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.clone()).collect();
+        assert_eq!(paths, ["c", "a", "b"].map(PathBuf::from));
+
+        // The order is total, so reversing it is the same as reversing the sorted entries
+        entries.sort_by(|a, b| SortOrder::Size.compare(b, a));
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.clone()).collect();
+        assert_eq!(paths, ["b", "a", "c"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn share_bars() {
+        let bar = |size, total| Percent::of(size, total).bar(10, false);
+        assert_eq!(bar(0, 100), "[          ]");
+        assert_eq!(bar(48, 100), "[#####     ]");
+        assert_eq!(bar(100, 100), "[##########]");
+        // Nothing to divide by
+        assert_eq!(bar(0, 0), "[          ]");
+
+        let formatter = text_formatter(PrintOptions {
+            bar: true,
+            percent: true,
+            ..PrintOptions::default()
+        });
+        assert_eq!(
+            formatter.columns(&entry(1234), Some(Percent::of(25, 100))),
+            ["   1.23 KB", "[###       ]", " 25.0%"]
+        );
+    }
+
+    #[test]
+    fn digit_grouping() {
+        assert_eq!(group_digits(0, ','), "0");
+        assert_eq!(group_digits(999, ','), "999");
+        assert_eq!(group_digits(1000, ','), "1,000");
+        assert_eq!(group_digits(483920184320, '_'), "483_920_184_320");
+        assert_eq!(group_digits(u64::MAX, ','), "18,446,744,073,709,551,615");
+    }
+
+    #[test]
+    fn breakdown_rows_are_limited() {
+        let sizes: HashMap<String, u64> = [("mp4", 500), ("jpg", 300), ("txt", 10), ("md", 20)]
+            .into_iter()
+            .map(|(name, size)| (name.to_string(), size))
+            .collect();
+
+        let breakdown = Breakdown::new(BreakdownKind::Extension, sizes.clone(), 3);
+        assert_eq!(breakdown.total, 830);
+        assert_eq!(
+            breakdown.rows,
+            [
+                ("mp4".to_string(), 500),
+                ("jpg".to_string(), 300),
+                ("<other>".to_string(), 30)
+            ]
+        );
+
+        let breakdown = Breakdown::new(BreakdownKind::Extension, sizes, 4);
+        assert_eq!(breakdown.rows.len(), 4);
+        assert_eq!(breakdown.rows[3], ("txt".to_string(), 10));
+    }
+
+    #[test]
+    fn header_is_aligned_with_columns() {
+        let formatter = text_formatter(PrintOptions {
+            count: true,
+            percent: true,
+            ..PrintOptions::default()
+        });
+        let header = formatter.header();
+        assert_eq!(header, ["      SIZE", "     %", "   FILES", "    DIRS"]);
+        let columns = formatter.columns(&entry(1234), Some(Percent::of(1, 2)));
+        let widths = |cells: &[String]| cells.iter().map(String::len).collect::<Vec<_>>();
+        assert_eq!(widths(&header), widths(&columns));
+
+        let formatter = text_formatter(PrintOptions {
+            both: true,
+            pretty: false,
+            ..PrintOptions::default()
+        });
+        assert_eq!(formatter.header(), ["APPARENT", "DISK"]);
+    }
+
+    /// Fails every write, like a full disk.
+    struct Full;
+
+    impl Write for Full {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("no space left on device"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_errors_are_returned_and_leave_no_file_behind() {
+        let tmp_dir = tempdir::TempDir::new("diskus-tests").unwrap();
+        let output_file = tmp_dir.path().join("report.csv");
+        let mut sink = Sink::open(Some(&output_file)).unwrap();
+        sink.out = Box::new(Full);
+
+        sink.write("path,size_bytes\n");
+        assert!(sink.failed());
+        let err = sink.commit().unwrap_err();
+        assert!(err.to_string().contains("could not write output file"));
+        drop(sink);
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,52 @@
+#[cfg(unix)]
+pub fn owner_of(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.uid())
+}
+
+#[cfg(not(unix))]
+pub fn owner_of(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Looks up the name of the user with the given ID in the users database. Users without an
+/// entry are shown by their numeric ID.
+#[cfg(unix)]
+pub fn user_name(uid: u32) -> String {
+    use std::ffi::CStr;
+    use std::mem::MaybeUninit;
+    use std::ptr;
+
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: all pointers are valid for the duration of the call, and `buffer.len()` is the
+        // size of the buffer that the strings of the entry are written to
+        let status = unsafe {
+            libc::getpwuid_r(
+                uid,
+                passwd.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        if status == libc::ERANGE && buffer.len() < 1 << 20 {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        if status != 0 || result.is_null() {
+            return uid.to_string();
+        }
+        // SAFETY: `result` is not null, so the entry has been written and `pw_name` points to a
+        // NUL-terminated string in `buffer`
+        let name = unsafe { CStr::from_ptr((*result).pw_name) };
+        return name.to_string_lossy().into_owned();
+    }
+}
+
+#[cfg(not(unix))]
+pub fn user_name(uid: u32) -> String {
+    uid.to_string()
+}
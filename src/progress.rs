@@ -0,0 +1,141 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+#[cfg(feature = "cli")]
+use std::{thread, time::Duration};
+
+#[cfg(feature = "cli")]
+use humansize::{file_size_opts, FileSize};
+#[cfg(feature = "cli")]
+use num_format::{Locale, ToFormattedString};
+
+/// How often the status line is redrawn.
+#[cfg(feature = "cli")]
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Live counters of a running walk, see `Walk::progress`. They are updated by the walker threads
+/// without any synchronization, so they can lag slightly behind, but they never go down while a
+/// walk is running.
+#[derive(Debug, Default)]
+pub struct Progress {
+    entries: AtomicU64,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Progress {
+    /// Number of entries that were counted so far.
+    pub fn entries(&self) -> u64 {
+        self.entries.load(Ordering::Relaxed)
+    }
+
+    /// Sum of the sizes of the entries that were counted so far. Unlike the results, this
+    /// includes every hardlink of a file.
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Number of errors so far, without the ones dropped by `Walk::ignore_errors`.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add(&self, size: u64) {
+        self.entries.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Starts counting from zero, for a new run of the walk.
+    pub(crate) fn reset(&self) {
+        self.entries.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.errors.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The status line that is shown on stderr while a walk is running. It is only drawn with the
+/// `cli` feature.
+pub(crate) struct StatusLine {
+    counters: Arc<Progress>,
+    /// Whether the walk has finished. The lock is also held while the status line is drawn.
+    finished: Mutex<bool>,
+    wakeup: Condvar,
+}
+
+impl StatusLine {
+    /// Spawns the thread that draws the status line, unless stderr is not a TTY.
+    #[cfg(feature = "cli")]
+    pub(crate) fn spawn(counters: Arc<Progress>) -> Option<(Arc<StatusLine>, JoinHandle<()>)> {
+        if !atty::is(atty::Stream::Stderr) {
+            return None;
+        }
+
+        let status_line = Arc::new(StatusLine {
+            counters,
+            finished: Mutex::new(false),
+            wakeup: Condvar::new(),
+        });
+
+        let status = Arc::clone(&status_line);
+        let handle = thread::spawn(move || {
+            let mut finished = status.finished.lock().unwrap();
+            loop {
+                finished = status
+                    .wakeup
+                    .wait_timeout(finished, REFRESH_INTERVAL)
+                    .unwrap()
+                    .0;
+                if *finished {
+                    break;
+                }
+                status.draw();
+            }
+        });
+
+        Some((status_line, handle))
+    }
+
+    #[cfg(not(feature = "cli"))]
+    pub(crate) fn spawn(_counters: Arc<Progress>) -> Option<(Arc<StatusLine>, JoinHandle<()>)> {
+        None
+    }
+
+    #[cfg(feature = "cli")]
+    fn draw(&self) {
+        let _ = write!(
+            io::stderr(),
+            "\r\x1b[KScanned {} entries ({})",
+            self.counters.entries().to_formatted_string(&Locale::en),
+            self.counters
+                .bytes()
+                .file_size(file_size_opts::DECIMAL)
+                .unwrap()
+        );
+    }
+
+    fn clear() {
+        let _ = write!(io::stderr(), "\r\x1b[K");
+    }
+
+    /// Clears the status line while `f` writes to the terminal. It is redrawn on the next refresh.
+    pub(crate) fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _finished = self.finished.lock().unwrap();
+        StatusLine::clear();
+        f()
+    }
+
+    /// Clears the status line for good and stops the status thread.
+    pub(crate) fn finish(&self) {
+        let mut finished = self.finished.lock().unwrap();
+        if !*finished {
+            *finished = true;
+            StatusLine::clear();
+        }
+        self.wakeup.notify_all();
+    }
+}
@@ -0,0 +1,498 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The largest number of instructions of a compiled pattern, which bounds the memory and time that
+/// matching needs (`a{1000}{1000}` would otherwise need a million).
+const MAX_PROGRAM_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Literal(char),
+    /// `.`, any single character
+    Any,
+    /// `[a-z_]`, `[^0-9]` or one of the escapes `\d`, `\w` and `\s`
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+    /// `^`
+    Start,
+    /// `$`
+    End,
+    /// A sequence of nodes that all have to match one after the other
+    Concat(Vec<Node>),
+    /// `a|b`
+    Alternation(Vec<Node>),
+    /// `a*`, `a+`, `a?` and `a{n,m}`
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+/// An instruction of a compiled pattern, which is run by `Regex::is_match`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Inst {
+    Literal(char),
+    Any,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+    Start,
+    End,
+    /// Continue at both of the instructions
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+impl Inst {
+    /// Whether the instruction consumes the character `c`.
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Inst::Literal(literal) => *literal == c,
+            Inst::Any => true,
+            Inst::Class { negated, ranges } => {
+                ranges.iter().any(|&(from, to)| from <= c && c <= to) != *negated
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A regular expression like `^core\.\d+$`, for filters that globs cannot express.
+///
+/// The syntax is a common subset of the usual regex dialects: literals, `.`, classes like
+/// `[a-z]` or `[^0-9]`, the escapes `\d`, `\w` and `\s` (and their negations `\D`, `\W` and
+/// `\S`), the anchors `^` and `$`, groups with alternatives like `(jpe?g|png)` and the
+/// quantifiers `*`, `+`, `?` and `{n,m}`. A pattern matches a text if it matches any part of it,
+/// unless it is anchored.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+}
+
+impl Regex {
+    /// Whether the pattern matches any part of `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let len = chars.len();
+        // The instructions that the part of the text read so far can lead to, like a Thompson NFA,
+        // so that matching never takes more than linear time in the length of the text
+        let mut seen = vec![false; self.program.len()];
+        let mut threads = vec![];
+        if self.add_thread(&mut threads, &mut seen, 0, 0, len) {
+            return true;
+        }
+
+        for (at, &c) in chars.iter().enumerate() {
+            seen.fill(false);
+            let mut next = vec![];
+            let mut matched = false;
+            for &pc in &threads {
+                if self.program[pc].matches(c) {
+                    matched |= self.add_thread(&mut next, &mut seen, pc + 1, at + 1, len);
+                }
+            }
+            // A match can start at every position
+            matched |= self.add_thread(&mut next, &mut seen, 0, at + 1, len);
+            if matched {
+                return true;
+            }
+            threads = next;
+        }
+        false
+    }
+
+    /// Adds the instructions that consume a character and can be reached from `pc` at position
+    /// `at` of the text to `threads`. Returns whether the pattern matches at `at`.
+    fn add_thread(
+        &self,
+        threads: &mut Vec<usize>,
+        seen: &mut [bool],
+        pc: usize,
+        at: usize,
+        len: usize,
+    ) -> bool {
+        if seen[pc] {
+            return false;
+        }
+        seen[pc] = true;
+        match self.program[pc] {
+            Inst::Split(first, second) => {
+                // Both branches have to be followed, even if the first one matches
+                let first = self.add_thread(threads, seen, first, at, len);
+                self.add_thread(threads, seen, second, at, len) || first
+            }
+            Inst::Jump(target) => self.add_thread(threads, seen, target, at, len),
+            Inst::Start => at == 0 && self.add_thread(threads, seen, pc + 1, at, len),
+            Inst::End => at == len && self.add_thread(threads, seen, pc + 1, at, len),
+            Inst::Match => true,
+            _ => {
+                threads.push(pc);
+                false
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Regex").field(&self.pattern).finish()
+    }
+}
+
+impl FromStr for Regex {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Regex, String> {
+        let error = |message: &str| format!("invalid regex '{}': {}", s, message);
+
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            position: 0,
+        };
+        let node = parser.alternation().map_err(|err| error(&err))?;
+        if parser.position < parser.chars.len() {
+            // Only an unmatched ')' ends the outermost alternation early
+            return Err(error("unmatched ')'"));
+        }
+
+        let mut program = vec![];
+        compile(&node, &mut program).map_err(|err| error(&err))?;
+        program.push(Inst::Match);
+        Ok(Regex {
+            pattern: s.to_string(),
+            program,
+        })
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.position += 1;
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Parses alternatives like `a|bc`, up to the end of the pattern or a closing `)`.
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut alternatives = vec![self.concat()?];
+        while self.eat('|') {
+            alternatives.push(self.concat()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Node::Alternation(alternatives)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.next().unwrap() {
+            '(' => {
+                // Non-capturing groups are the same as groups here
+                if self.chars[self.position..].starts_with(&['?', ':']) {
+                    self.position += 2;
+                }
+                let node = self.alternation()?;
+                if !self.eat(')') {
+                    return Err("unterminated '('".to_string());
+                }
+                Ok(node)
+            }
+            '[' => self.class(),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '\\' => self.escape(),
+            c @ ('*' | '+' | '?' | '{') => Err(format!("nothing to repeat before '{}'", c)),
+            c => Ok(Node::Literal(c)),
+        }
+    }
+
+    /// Parses the escape sequence after a `\`.
+    fn escape(&mut self) -> Result<Node, String> {
+        let c = self.next().ok_or_else(|| "trailing '\\'".to_string())?;
+        if let Some((negated, ranges)) = class_escape(c) {
+            return Ok(Node::Class { negated, ranges });
+        }
+        match c {
+            'n' => Ok(Node::Literal('\n')),
+            't' => Ok(Node::Literal('\t')),
+            'r' => Ok(Node::Literal('\r')),
+            c if c.is_alphanumeric() => Err(format!("unsupported escape '\\{}'", c)),
+            c => Ok(Node::Literal(c)),
+        }
+    }
+
+    /// Parses a class like `[a-z_]` after the opening bracket.
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.eat('^');
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = self.next().ok_or_else(|| "unterminated '['".to_string())?;
+            let from = match c {
+                // A ']' right after the opening bracket is a literal
+                ']' if !first => break,
+                '\\' => {
+                    let c = self.next().ok_or_else(|| "unterminated '['".to_string())?;
+                    match class_escape(c) {
+                        Some((false, escaped)) => {
+                            ranges.extend(escaped);
+                            first = false;
+                            continue;
+                        }
+                        Some((true, _)) => {
+                            return Err(format!("unsupported escape '\\{}' in a class", c))
+                        }
+                        None if c.is_alphanumeric() => {
+                            return Err(format!("unsupported escape '\\{}'", c))
+                        }
+                        None => c,
+                    }
+                }
+                c => c,
+            };
+            first = false;
+
+            let range_end = self.chars.get(self.position + 1).copied();
+            if self.peek() == Some('-') && range_end.is_some_and(|to| to != ']') {
+                self.position += 2;
+                let to = range_end.unwrap();
+                if to < from {
+                    return Err(format!("invalid range '{}-{}'", from, to));
+                }
+                ranges.push((from, to));
+            } else {
+                ranges.push((from, from));
+            }
+        }
+        Ok(Node::Class { negated, ranges })
+    }
+
+    /// Parses the quantifier after an atom, if there is one.
+    fn quantified(&mut self, node: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.position += 1;
+                return self.counted(node);
+            }
+            _ => return Ok(node),
+        };
+        self.position += 1;
+        self.repeat(node, min, max)
+    }
+
+    /// Parses a quantifier like `{3}`, `{3,}` or `{3,5}` after the opening brace.
+    fn counted(&mut self, node: Node) -> Result<Node, String> {
+        let end = self.chars[self.position..]
+            .iter()
+            .position(|&c| c == '}')
+            .ok_or_else(|| "unterminated '{'".to_string())?;
+        let counts: String = self.chars[self.position..self.position + end]
+            .iter()
+            .collect();
+        self.position += end + 1;
+
+        let count = |s: &str| {
+            s.parse::<u32>()
+                .map_err(|_| format!("invalid repetition '{{{}}}'", counts))
+        };
+        let (min, max) = match counts.split_once(',') {
+            None => (count(&counts)?, Some(count(&counts)?)),
+            Some((min, "")) => (count(min)?, None),
+            Some((min, max)) => (count(min)?, Some(count(max)?)),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("invalid repetition '{{{}}}'", counts));
+        }
+        self.repeat(node, min, max)
+    }
+
+    fn repeat(&mut self, node: Node, min: u32, max: Option<u32>) -> Result<Node, String> {
+        if matches!(node, Node::Start | Node::End) {
+            return Err("nothing to repeat".to_string());
+        }
+        // Lazy quantifiers match the same texts
+        self.eat('?');
+        Ok(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+        })
+    }
+}
+
+/// The ranges of the class escapes `\d`, `\w` and `\s`, and whether they are negated (`\D`, `\W`
+/// and `\S`).
+fn class_escape(c: char) -> Option<(bool, Vec<(char, char)>)> {
+    let ranges = match c.to_ascii_lowercase() {
+        'd' => vec![('0', '9')],
+        'w' => vec![('0', '9'), ('A', 'Z'), ('a', 'z'), ('_', '_')],
+        's' => vec![(' ', ' '), ('\t', '\r')],
+        _ => return None,
+    };
+    Some((c.is_ascii_uppercase(), ranges))
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    if program.len() > MAX_PROGRAM_SIZE {
+        return Err("pattern is too large".to_string());
+    }
+    match node {
+        Node::Literal(c) => program.push(Inst::Literal(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class { negated, ranges } => program.push(Inst::Class {
+            negated: *negated,
+            ranges: ranges.clone(),
+        }),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program)?;
+            }
+        }
+        Node::Alternation(alternatives) => {
+            let mut jumps = vec![];
+            for (i, alternative) in alternatives.iter().enumerate() {
+                if i + 1 < alternatives.len() {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(alternative, program)?;
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    program[split] = Inst::Split(split + 1, program.len());
+                } else {
+                    compile(alternative, program)?;
+                }
+            }
+            for jump in jumps {
+                program[jump] = Inst::Jump(program.len());
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, program)?;
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program)?;
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = vec![];
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(program.len() + 1, 0));
+                        compile(node, program)?;
+                    }
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, program.len());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        pattern.parse::<Regex>().unwrap().is_match(text)
+    }
+
+    #[test]
+    fn literals_and_anchors() {
+        assert!(matches("core", "core.1234"));
+        assert!(matches("1234", "core.1234"));
+        assert!(!matches("^1234", "core.1234"));
+        assert!(matches(r"^core\.\d+$", "core.1234"));
+        assert!(!matches(r"^core\.\d+$", "core.1234.gz"));
+        assert!(!matches(r"^core\.\d+$", "core.x"));
+        assert!(matches("^$", ""));
+        assert!(matches("", "anything"));
+    }
+
+    #[test]
+    fn classes_and_quantifiers() {
+        assert!(matches("^[a-c]+$", "abcabc"));
+        assert!(!matches("^[^a-c]+$", "xyzb"));
+        assert!(matches(r"^[\w.-]+$", "my-file.tar"));
+        assert!(matches("^a{2,3}$", "aaa"));
+        assert!(!matches("^a{2,3}$", "aaaa"));
+        assert!(matches("^a{2,}$", "aaaaa"));
+        assert!(matches("^a{2}b?$", "aab"));
+        assert!(matches(r"^\S+\s\S+$", "two words"));
+        assert!(matches("^x.*?y$", "x123y"));
+        assert!(matches("^(a*)*b$", "aaaab"));
+    }
+
+    #[test]
+    fn alternatives() {
+        assert!(matches(r"\.(jpe?g|png)$", "photo.jpeg"));
+        assert!(matches(r"\.(jpe?g|png)$", "photo.png"));
+        assert!(!matches(r"\.(jpe?g|png)$", "photo.gif"));
+        assert!(matches("^(?:ab|cd)+$", "abcdab"));
+        assert!(matches("^a|b$", "ax"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in [
+            "(ab", "ab)", "[ab", "*a", "a{3,1}", "a{x}", r"\q", "ab\\", "[z-a]",
+        ] {
+            let err = pattern.parse::<Regex>().unwrap_err();
+            assert!(err.starts_with(&format!("invalid regex '{}'", pattern)));
+        }
+        assert!("a{1000}{1000}".parse::<Regex>().is_err());
+    }
+}
@@ -0,0 +1,159 @@
+//! `Serialize` and `Deserialize` for the types that serde cannot derive them for.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use crate::walk::Error;
+
+/// Paths as strings if they are valid UTF-8 (in human-readable formats like JSON), and as their
+/// bytes otherwise, so that every path round-trips on Unix. Elsewhere, invalid UTF-8 is replaced
+/// by U+FFFD. For use with `#[serde(with = "crate::serialize::path")]`.
+pub(crate) mod path {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(path) if serializer.is_human_readable() => serializer.serialize_str(path),
+            _ => serialize_bytes(path, serializer),
+        }
+    }
+
+    #[cfg(unix)]
+    fn serialize_bytes<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        use std::os::unix::ffi::OsStrExt;
+
+        serializer.serialize_bytes(path.as_os_str().as_bytes())
+    }
+
+    #[cfg(not(unix))]
+    fn serialize_bytes<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        let path = path.to_string_lossy();
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&path)
+        } else {
+            serializer.serialize_bytes(path.as_bytes())
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<PathBuf, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(PathVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(PathVisitor)
+        }
+    }
+
+    struct PathVisitor;
+
+    impl<'de> Visitor<'de> for PathVisitor {
+        type Value = PathBuf;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a path as a string or as bytes")
+        }
+
+        fn visit_str<E: de::Error>(self, path: &str) -> Result<PathBuf, E> {
+            Ok(PathBuf::from(path))
+        }
+
+        fn visit_bytes<E: de::Error>(self, path: &[u8]) -> Result<PathBuf, E> {
+            Ok(path_of(path.to_vec()))
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, path: Vec<u8>) -> Result<PathBuf, E> {
+            Ok(path_of(path))
+        }
+
+        /// Formats without a type for bytes (like JSON) serialize them as a sequence.
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<PathBuf, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(path_of(bytes))
+        }
+    }
+
+    #[cfg(unix)]
+    fn path_of(bytes: Vec<u8>) -> PathBuf {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+
+        PathBuf::from(OsString::from_vec(bytes))
+    }
+
+    #[cfg(not(unix))]
+    fn path_of(bytes: Vec<u8>) -> PathBuf {
+        PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+/// An `Error` like `{"kind": "could_not_read_dir", "path": "/root", "message": "Permission
+/// denied (os error 13)", "os_error": 13}`. The `io::Error` is restored from its OS error code
+/// if it has one, and only with its message otherwise. Errors without an `io::Error` have
+/// neither.
+#[derive(Serialize, Deserialize)]
+struct SerializedError {
+    kind: String,
+    #[serde(with = "path")]
+    path: PathBuf,
+    message: Option<String>,
+    os_error: Option<i32>,
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let io_error = match self {
+            Error::NoMetadataForRoot(_, err)
+            | Error::NoMetadataForPath(_, err)
+            | Error::CouldNotReadDir(_, err) => Some(err),
+            Error::FilesystemLoop(_) | Error::CouldNotReadDevice(_) => None,
+        };
+        SerializedError {
+            kind: self.kind().to_string(),
+            path: self.path().to_path_buf(),
+            message: io_error.map(|err| err.to_string()),
+            os_error: io_error.and_then(|err| err.raw_os_error()),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Error {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Error, D::Error> {
+        let SerializedError {
+            kind,
+            path,
+            message,
+            os_error,
+        } = SerializedError::deserialize(deserializer)?;
+        let io_error = || match os_error {
+            Some(code) => io::Error::from_raw_os_error(code),
+            None => io::Error::other(message.unwrap_or_default()),
+        };
+        match kind.as_str() {
+            "no_metadata_for_root" => Ok(Error::NoMetadataForRoot(path, io_error())),
+            "no_metadata_for_path" => Ok(Error::NoMetadataForPath(path, io_error())),
+            "could_not_read_dir" => Ok(Error::CouldNotReadDir(path, io_error())),
+            "filesystem_loop" => Ok(Error::FilesystemLoop(path)),
+            "could_not_read_device" => Ok(Error::CouldNotReadDevice(path)),
+            kind => Err(de::Error::unknown_variant(
+                kind,
+                &[
+                    "no_metadata_for_root",
+                    "no_metadata_for_path",
+                    "could_not_read_dir",
+                    "filesystem_loop",
+                    "could_not_read_device",
+                ],
+            )),
+        }
+    }
+}
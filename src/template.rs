@@ -0,0 +1,145 @@
+use std::str::FromStr;
+
+/// A value that can be referenced in a `Template`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    /// The size in the selected size format
+    Size,
+    /// The exact size in bytes
+    SizeBytes,
+    Path,
+    /// The share of the entry in the total size
+    Percent,
+    Files,
+    Dirs,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Placeholder> {
+        match name {
+            "size" => Some(Placeholder::Size),
+            "size_bytes" => Some(Placeholder::SizeBytes),
+            "path" => Some(Placeholder::Path),
+            "percent" => Some(Placeholder::Percent),
+            "files" => Some(Placeholder::Files),
+            "dirs" => Some(Placeholder::Dirs),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// A line template like `{path} => {size}`. Literal braces are written as `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Whether the given placeholder appears in the template.
+    pub fn uses(&self, placeholder: Placeholder) -> bool {
+        self.parts.contains(&Part::Placeholder(placeholder))
+    }
+
+    /// Renders the template. Paths are passed as bytes, since they are not necessarily valid
+    /// UTF-8.
+    pub(crate) fn render(&self, value: impl Fn(Placeholder) -> Vec<u8>) -> Vec<u8> {
+        let mut line = vec![];
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => line.extend_from_slice(literal.as_bytes()),
+                Part::Placeholder(placeholder) => line.extend(value(*placeholder)),
+            }
+        }
+        line
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Template, String> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or("unterminated placeholder, use '{{' for a literal '{'")?;
+                    let name = &rest[..end];
+                    let placeholder = Placeholder::from_name(name).ok_or_else(|| {
+                        format!(
+                            "unknown placeholder '{{{}}}', expected one of {{size}}, \
+                             {{size_bytes}}, {{path}}, {{percent}}, {{files}}, {{dirs}}",
+                            name
+                        )
+                    })?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err("unmatched '}', use '}}' for a literal '}'".to_string()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Template { parts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> String {
+        let template: Template = template.parse().unwrap();
+        let line = template.render(|placeholder| match placeholder {
+            Placeholder::Path => b"foo".to_vec(),
+            Placeholder::Size => b"1.00 KB".to_vec(),
+            _ => b"1000".to_vec(),
+        });
+        String::from_utf8(line).unwrap()
+    }
+
+    #[test]
+    fn placeholders_are_substituted() {
+        assert_eq!(render("{path} => {size}"), "foo => 1.00 KB");
+        assert_eq!(render("{size_bytes}\t{path}"), "1000\tfoo");
+        assert_eq!(render("plain"), "plain");
+    }
+
+    #[test]
+    fn braces_can_be_escaped() {
+        assert_eq!(render("{{{path}}}"), "{foo}");
+        assert_eq!(render("{{size}}"), "{size}");
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert!("{size".parse::<Template>().is_err());
+        assert!("size}".parse::<Template>().is_err());
+        assert!("{name}".parse::<Template>().is_err());
+    }
+}
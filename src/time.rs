@@ -0,0 +1,171 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Parses a point in time like `30d` (30 days before `now`), `12h` or
+/// `2024-03-01T12:00:00+01:00`.
+///
+/// Durations are a number followed by one of the units `s`, `m` (minutes), `h`, `d`, `w` and `y`
+/// (365 days). Dates are RFC 3339 timestamps, or plain dates like `2024-03-01`, which stand for
+/// midnight UTC.
+pub fn parse_time(s: &str, now: SystemTime) -> Result<SystemTime, String> {
+    let s = s.trim();
+    if s.len() >= 10 && s.as_bytes()[4] == b'-' {
+        return parse_date(s).ok_or_else(|| {
+            format!(
+                "invalid date '{}': expected a date like '2024-03-01' or '2024-03-01T12:00:00Z'",
+                s
+            )
+        });
+    }
+
+    parse_duration(s).and_then(|duration| {
+        now.checked_sub(duration)
+            .ok_or_else(|| format!("invalid duration '{}': too long", s))
+    })
+}
+
+/// Parses a duration like `30s`, `10m` or `12h`, with the same units as `parse_time`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}': expected a number", s))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        "" => return Err(format!("invalid duration '{}': missing unit", s)),
+        _ => {
+            return Err(format!(
+                "invalid duration '{}': unknown unit '{}' (expected s, m, h, d, w or y)",
+                s, unit
+            ))
+        }
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration '{}': too long", s))
+}
+
+/// Parses an RFC 3339 timestamp or a plain date.
+fn parse_date(s: &str) -> Option<SystemTime> {
+    let (date, time) = match s.find(['T', 't', ' ']) {
+        Some(split) => (&s[..split], Some(&s[split + 1..])),
+        None => (s, None),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = number(parts.next()?, 4)?;
+    let month: u32 = number(parts.next()?, 2)?;
+    let day: u32 = number(parts.next()?, 2)?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut seconds = days_from_civil(year, month, day) * 24 * 60 * 60;
+
+    if let Some(time) = time {
+        // The offset is required, there is no sensible default for the time zone
+        let (time, offset) = match time.find(['Z', 'z', '+', '-']) {
+            Some(split) => time.split_at(split),
+            None => return None,
+        };
+        let time = time.split('.').next()?;
+        let mut parts = time.splitn(3, ':');
+        let hours: i64 = number(parts.next()?, 2)?;
+        let minutes: i64 = number(parts.next()?, 2)?;
+        let secs: i64 = number(parts.next()?, 2)?;
+        if hours > 23 || minutes > 59 || secs > 60 {
+            return None;
+        }
+        seconds += hours * 60 * 60 + minutes * 60 + secs;
+
+        if !offset.eq_ignore_ascii_case("z") {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let hours: i64 = number(hours, 2)?;
+            let minutes: i64 = number(minutes, 2)?;
+            seconds -= sign * (hours * 60 * 60 + minutes * 60);
+        }
+    }
+
+    if seconds >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))
+    }
+}
+
+/// Parses a number with exactly the given number of digits.
+fn number<T: std::str::FromStr>(s: &str, digits: usize) -> Option<T> {
+    if s.len() == digits && s.bytes().all(|b| b.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Number of days between 1970-01-01 and the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Years start in March here, so that the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    #[test]
+    fn durations() {
+        let now = UNIX_EPOCH + Duration::from_secs(100 * 24 * 60 * 60);
+        assert_eq!(seconds(parse_time("30d", now).unwrap()), 70 * 24 * 60 * 60);
+        assert_eq!(
+            seconds(parse_time("12h", now).unwrap()),
+            seconds(now) - 12 * 60 * 60
+        );
+        assert_eq!(seconds(parse_time("1w", now).unwrap()), 93 * 24 * 60 * 60);
+        assert!(parse_time("30", now).is_err());
+        assert!(parse_time("30x", now).is_err());
+        assert!(parse_time("d", now).is_err());
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn dates() {
+        let now = SystemTime::now();
+        let date = |s| parse_time(s, now).map(seconds);
+        assert_eq!(date("1970-01-02"), Ok(24 * 60 * 60));
+        assert_eq!(date("2024-03-01"), Ok(1_709_251_200));
+        assert_eq!(date("2024-03-01T12:30:00Z"), Ok(1_709_296_200));
+        assert_eq!(date("2024-03-01T12:30:00.25+01:00"), Ok(1_709_292_600));
+        assert_eq!(date("2024-03-01t12:30:00-00:30"), Ok(1_709_298_000));
+        assert_eq!(date("2024-02-29"), Ok(1_709_164_800));
+        assert!(date("2023-02-29").is_err());
+        assert!(date("2024-13-01").is_err());
+        assert!(date("2024-03-01T12:30:00").is_err());
+        assert!(date("2024-3-01").is_err());
+    }
+}
@@ -0,0 +1,41 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::progress::StatusLine;
+
+/// Logs every directory on stderr as it is entered, together with the time since the start of
+/// the walk. The lines are written under a lock, so that the walker threads cannot interleave
+/// them.
+pub(crate) struct Trace {
+    start: Instant,
+    out: Mutex<io::Stderr>,
+    progress: Option<Arc<StatusLine>>,
+}
+
+impl Trace {
+    pub(crate) fn new(progress: Option<Arc<StatusLine>>) -> Trace {
+        Trace {
+            start: Instant::now(),
+            out: Mutex::new(io::stderr()),
+            progress,
+        }
+    }
+
+    pub(crate) fn enter(&self, dir: &Path) {
+        let line = format!(
+            "diskus: [{:>9.3} s] entering '{}'\n",
+            self.start.elapsed().as_secs_f64(),
+            dir.to_string_lossy()
+        );
+        let write = || {
+            let mut out = self.out.lock().unwrap();
+            let _ = out.write_all(line.as_bytes());
+        };
+        match self.progress {
+            Some(ref progress) => progress.suspend(write),
+            None => write(),
+        }
+    }
+}
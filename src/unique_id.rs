@@ -1,11 +1,19 @@
-#[derive(Eq, PartialEq, Hash)]
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct UniqueID {
     device: u64,
     inode: u64,
 }
 
+/// The ID of a file with more than one hard link. On Windows, hardlinks are only detected if
+/// `by_handle` is set, as every file has to be opened for that.
 #[cfg(not(windows))]
-pub fn generate_unique_id(metadata: &std::fs::Metadata) -> Option<UniqueID> {
+pub fn generate_unique_id(
+    _path: &Path,
+    metadata: &std::fs::Metadata,
+    _by_handle: bool,
+) -> Option<UniqueID> {
     use std::os::unix::fs::MetadataExt;
     // If the entry has more than one hard link, generate
     // a unique ID consisting of device and inode in order
@@ -20,13 +28,99 @@ pub fn generate_unique_id(metadata: &std::fs::Metadata) -> Option<UniqueID> {
     }
 }
 
+/// The ID of any entry, regardless of its number of hard links. Used for the directories and the
+/// targets of symlinks with `Walk::follow_symlinks`, which can be reached on more than one path.
+#[cfg(not(windows))]
+pub fn entry_id(metadata: &std::fs::Metadata) -> Option<UniqueID> {
+    use std::os::unix::fs::MetadataExt;
+    Some(UniqueID {
+        device: metadata.dev(),
+        inode: metadata.ino(),
+    })
+}
+
+#[cfg(windows)]
+pub fn entry_id(_metadata: &std::fs::Metadata) -> Option<UniqueID> {
+    None
+}
+
 #[cfg(windows)]
-pub fn generate_unique_id(_metadata: &std::fs::Metadata) -> Option<UniqueID> {
+pub fn generate_unique_id(
+    path: &Path,
+    metadata: &std::fs::Metadata,
+    by_handle: bool,
+) -> Option<UniqueID> {
     // Windows-internal tools such as Powershell, Explorer or `dir` are not respecting hardlinks
-    // or junction points when determining the size of a directory. `diskus` does the same and
-    // counts such entries multiple times (on Unix systems, multiple hardlinks to a single file are
-    // counted just once).
+    // or junction points when determining the size of a directory. By default, `diskus` does the
+    // same and counts such entries multiple times (on Unix systems, multiple hardlinks to a single
+    // file are counted just once).
     //
     // See: https://github.com/sharkdp/diskus/issues/32
-    None
+    if by_handle && metadata.is_file() {
+        file_index(path)
+    } else {
+        None
+    }
+}
+
+/// The volume serial number and the file index of a file with more than one hard link, from
+/// `GetFileInformationByHandle`. Returns `None` if the file cannot be opened.
+#[cfg(windows)]
+fn file_index(path: &Path) -> Option<UniqueID> {
+    use std::fs::OpenOptions;
+    use std::mem::MaybeUninit;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::{AsRawHandle, RawHandle};
+
+    const FILE_SHARE_READ: u32 = 0x1;
+    const FILE_SHARE_WRITE: u32 = 0x2;
+    const FILE_SHARE_DELETE: u32 = 0x4;
+
+    #[repr(C)]
+    struct FileTime {
+        low: u32,
+        high: u32,
+    }
+
+    /// The layout of `BY_HANDLE_FILE_INFORMATION`
+    #[repr(C)]
+    struct FileInformation {
+        file_attributes: u32,
+        creation_time: FileTime,
+        last_access_time: FileTime,
+        last_write_time: FileTime,
+        volume_serial_number: u32,
+        file_size_high: u32,
+        file_size_low: u32,
+        number_of_links: u32,
+        file_index_high: u32,
+        file_index_low: u32,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetFileInformationByHandle(file: RawHandle, information: *mut FileInformation) -> i32;
+    }
+
+    // Neither read nor write access is needed for the file information, so this also works for
+    // files that other processes have opened
+    let file = OpenOptions::new()
+        .access_mode(0)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+        .open(path)
+        .ok()?;
+    let mut information = MaybeUninit::<FileInformation>::uninit();
+    // SAFETY: the handle stays open while `file` is alive, and the information is only read if
+    // the call succeeded
+    let information = unsafe {
+        if GetFileInformationByHandle(file.as_raw_handle(), information.as_mut_ptr()) == 0 {
+            return None;
+        }
+        information.assume_init()
+    };
+    (information.number_of_links > 1).then(|| UniqueID {
+        device: u64::from(information.volume_serial_number),
+        inode: (u64::from(information.file_index_high) << 32)
+            | u64::from(information.file_index_low),
+    })
 }
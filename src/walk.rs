@@ -1,324 +1,3670 @@
-use std::collections::{HashMap, HashSet};
-use std::io::Write;
-use std::path::{Path, PathBuf};
-use std::process::exit;
-use std::thread;
-use std::{fs, io};
+use std::cmp::Reverse;
+use std::collections::{hash_map::Entry, BinaryHeap, HashMap, HashSet};
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::io::{self, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
 use crossbeam_channel as channel;
+#[cfg(feature = "async")]
+use futures_channel::{mpsc, oneshot};
+#[cfg(feature = "async")]
+use futures_core::Stream;
 
-use colored::Colorize;
-use humansize::file_size_opts::FileSizeOpts;
-use humansize::FileSize;
 use rayon::{self, prelude::*};
 
+#[cfg(feature = "cli")]
+use crate::device::device_names;
+use crate::device::{bind_mounts, device_capacity, device_of, is_block_device, special_devices};
 use crate::filesize::FilesizeType;
-use crate::unique_id::{generate_unique_id, UniqueID};
+use crate::gitignore::Ignore;
+use crate::glob::GlobSet;
+#[cfg(feature = "cli")]
+use crate::output::{
+    formatter, Breakdown, BreakdownKind, ErrorReporter, Formatter, LiveRow, OutputFormat, Percent,
+    PrintOptions, Row, SortOrder,
+};
+use crate::owner::owner_of;
+#[cfg(feature = "cli")]
+use crate::owner::user_name;
+use crate::progress::{Progress, StatusLine};
+use crate::regex::Regex;
+use crate::trace::Trace;
+use crate::unique_id::{entry_id, generate_unique_id, UniqueID};
+use crate::xattr::xattr_size;
 
-fn safe_write(s: String) {
-    match io::stdout().write_all(s.as_bytes()) {
-        Ok(_) => {}
-        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => exit(0),
-        Err(ref err) => {
-            eprintln!("Unexpected err: {:?}", err);
-            exit(1)
+/// An error that does not stop the walk. New kinds of errors may be added in minor releases.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// One of the root paths of the walk does not exist or is not accessible
+    NoMetadataForRoot(PathBuf, io::Error),
+    NoMetadataForPath(PathBuf, io::Error),
+    CouldNotReadDir(PathBuf, io::Error),
+    /// A directory is one of the directories it is contained in, which happens with symlinks (see
+    /// `Walk::follow_symlinks`) or with directories that are bind-mounted into themselves
+    FilesystemLoop(PathBuf),
+    /// One of the root paths is a block device whose capacity cannot be read, e.g. because of
+    /// missing permissions
+    CouldNotReadDevice(PathBuf),
+}
+
+impl Error {
+    pub(crate) fn path(&self) -> &Path {
+        match self {
+            Error::NoMetadataForRoot(path, _)
+            | Error::NoMetadataForPath(path, _)
+            | Error::CouldNotReadDir(path, _)
+            | Error::FilesystemLoop(path)
+            | Error::CouldNotReadDevice(path) => path,
+        }
+    }
+
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Error::NoMetadataForRoot(..) => "no_metadata_for_root",
+            Error::NoMetadataForPath(..) => "no_metadata_for_path",
+            Error::CouldNotReadDir(..) => "could_not_read_dir",
+            Error::FilesystemLoop(_) => "filesystem_loop",
+            Error::CouldNotReadDevice(_) => "could_not_read_device",
+        }
+    }
+}
+
+/// Errors are equal if they are of the same kind and for the same path, the underlying
+/// `io::Error`s are not compared.
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        self.kind() == other.kind() && self.path() == other.path()
+    }
+}
+
+impl Eq for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoMetadataForRoot(path, err) | Error::NoMetadataForPath(path, err) => write!(
+                f,
+                "could not retrieve metadata for path '{}': {}",
+                path.to_string_lossy(),
+                err
+            ),
+            Error::CouldNotReadDir(path, err) => write!(
+                f,
+                "could not read contents of directory '{}': {}",
+                path.to_string_lossy(),
+                err
+            ),
+            Error::FilesystemLoop(path) => write!(
+                f,
+                "not descending into '{}', it leads back to one of its parent directories",
+                path.to_string_lossy()
+            ),
+            Error::CouldNotReadDevice(path) => write!(
+                f,
+                "could not open block device '{}' to read its capacity",
+                path.to_string_lossy()
+            ),
+        }
+    }
+}
+
+// `io::Error` is not `Clone`, so the clones only keep its kind and message
+impl Clone for Error {
+    fn clone(&self) -> Error {
+        match self {
+            Error::NoMetadataForRoot(path, err) => {
+                Error::NoMetadataForRoot(path.clone(), clone_io_error(err))
+            }
+            Error::NoMetadataForPath(path, err) => {
+                Error::NoMetadataForPath(path.clone(), clone_io_error(err))
+            }
+            Error::CouldNotReadDir(path, err) => {
+                Error::CouldNotReadDir(path.clone(), clone_io_error(err))
+            }
+            Error::FilesystemLoop(path) => Error::FilesystemLoop(path.clone()),
+            Error::CouldNotReadDevice(path) => Error::CouldNotReadDevice(path.clone()),
+        }
+    }
+}
+
+fn clone_io_error(err: &io::Error) -> io::Error {
+    match err.raw_os_error() {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(err.kind(), err.to_string()),
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NoMetadataForRoot(_, err)
+            | Error::NoMetadataForPath(_, err)
+            | Error::CouldNotReadDir(_, err) => Some(err),
+            Error::FilesystemLoop(_) | Error::CouldNotReadDevice(_) => None,
+        }
+    }
+}
+
+/// Why `WalkBuilder::build` rejected its configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// No paths to walk were given
+    NoPaths,
+    /// The number of threads is zero
+    NoThreads,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NoPaths => write!(f, "no paths to walk were given"),
+            ConfigError::NoThreads => write!(f, "the number of threads has to be at least 1"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Why a walk could not be run to the end.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WalkError {
+    /// The thread pool for the walk could not be built
+    ThreadPool(rayon::ThreadPoolBuildError),
+    /// One of the threads of the walk panicked, e.g. in a `WalkVisitor` or a filter
+    Panicked,
+    /// The results of `Walk::run_and_print` could not be written, e.g. to
+    /// `PrintOptions::output_file`
+    Output(io::Error),
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalkError::ThreadPool(err) => write!(f, "could not build the thread pool: {}", err),
+            WalkError::Panicked => write!(f, "a thread of the walk panicked"),
+            WalkError::Output(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for WalkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WalkError::ThreadPool(err) => Some(err),
+            WalkError::Panicked => None,
+            WalkError::Output(err) => Some(err),
+        }
+    }
+}
+
+/// How often the partial sizes are redrawn with `PrintOptions::live`.
+#[cfg(feature = "cli")]
+const LIVE_REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Errors paired with the root directory they were encountered in.
+type RootErrors = Vec<(PathBuf, Error)>;
+
+/// The summed up sizes by the user ID of their owner.
+#[cfg(unix)]
+type OwnerSizes = HashMap<u32, u64>;
+
+/// Everything that the receiver thread of `Walk::run_with_roots` collects.
+struct Collected {
+    entries: Vec<RootEntry>,
+    errors: RootErrors,
+    stats: Stats,
+    extras: Extras,
+    /// See `ExtrasConfig::files`
+    files: Vec<FileEntry>,
+    /// See `Message::SkippedMountPoint`
+    skipped_mount_points: Vec<PathBuf>,
+    /// See `Message::UndatedFile`
+    undated_files: Vec<PathBuf>,
+    /// See `Message::CacheDir`
+    cache_dirs: Vec<PathBuf>,
+    /// See `Message::UnreadableXattrs`
+    unreadable_xattrs: Vec<PathBuf>,
+    /// See `Message::DeviceCapacity`
+    devices: Vec<PathBuf>,
+    /// The roots that were not walked, see `Walk::allow_overlap`
+    overlaps: Vec<Overlap>,
+}
+
+/// What is collected about individual entries, in addition to the per-root sums.
+#[derive(Debug, Clone, Copy, Default)]
+struct ExtrasConfig {
+    /// Sum up the apparent size and the disk usage of every root at the same time
+    both: bool,
+    /// Number of largest files to keep
+    largest_files: usize,
+    /// Number of rows of the breakdown by extension, if sizes are broken down by extension
+    extensions: Option<usize>,
+    /// Break sizes down by the user ID of their owner
+    owners: bool,
+    /// Break sizes down by the device that the entries are stored on
+    devices: bool,
+    /// Report the path of every counted entry, see `Walk::run_streaming`
+    paths: bool,
+    /// Keep every counted entry, see `Walk::run_detailed`
+    files: bool,
+}
+
+impl ExtrasConfig {
+    #[cfg(feature = "cli")]
+    fn of(options: &PrintOptions) -> ExtrasConfig {
+        ExtrasConfig {
+            both: options.both,
+            largest_files: options.biggest_files.unwrap_or(0),
+            extensions: options.by_extension,
+            owners: options.by_owner,
+            devices: options.by_device,
+            paths: false,
+            files: false,
+        }
+    }
+
+    /// Whether the walker threads have to send the path of every entry.
+    fn entry_paths(self) -> bool {
+        self.paths || self.files || self.largest_files > 0 || self.extensions.is_some()
+    }
+}
+
+/// The summaries of individual entries that are printed after the results.
+struct Extras {
+    /// The largest files across all roots, largest first
+    largest_files: Vec<RootEntry>,
+    /// Sizes by the user ID of their owner, if `ExtrasConfig::owners` is set
+    owners: HashMap<u32, u64>,
+    #[cfg(feature = "cli")]
+    breakdowns: Vec<Breakdown>,
+}
+
+#[cfg(feature = "cli")]
+impl Extras {
+    fn print(self, formatter: &mut dyn Formatter) {
+        if !self.largest_files.is_empty() {
+            formatter.largest_files(&self.largest_files);
+        }
+        for breakdown in &self.breakdowns {
+            formatter.breakdown(breakdown);
+        }
+    }
+}
+
+/// The type of a filesystem entry. Symlinks are `Symlink`, unless they are followed (everywhere
+/// with `Walk::follow_symlinks`, or only for the roots with `Walk::dereference_roots`): then
+/// they have the type of their target. Broken symlinks are always `Symlink`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+impl EntryKind {
+    fn of(metadata: &fs::Metadata) -> EntryKind {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else {
+            EntryKind::File
+        }
+    }
+}
+
+/// Which roots a file that can be reached from several roots (through hardlinks, or followed
+/// symlinks) is counted for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupScope {
+    /// Only count every file once across all roots, for the first root (in the order the roots
+    /// were given in) that contains it
+    #[default]
+    Global,
+    /// Count every file once for every root that contains it. The total still counts the file
+    /// for every root then.
+    PerRoot,
+}
+
+/// What happens with an entry below the roots, as decided by `Walk::filter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// Count the entry, and walk it if it is a directory
+    Count,
+    /// Do not count the entry itself, but still walk it if it is a directory
+    Skip,
+    /// Skip the entry and everything below it
+    Prune,
+}
+
+impl FilterDecision {
+    /// Combines the decision of a filter with the one of the next filter: the entry is pruned if
+    /// either prunes it, and skipped if either skips it. The next filter is not asked if the entry
+    /// is pruned already.
+    fn and_then(self, next: impl FnOnce() -> FilterDecision) -> FilterDecision {
+        match self {
+            FilterDecision::Prune => FilterDecision::Prune,
+            FilterDecision::Skip => match next() {
+                FilterDecision::Prune => FilterDecision::Prune,
+                _ => FilterDecision::Skip,
+            },
+            FilterDecision::Count => next(),
+        }
+    }
+}
+
+/// What the filters of a walk know about an entry, besides its path and metadata.
+struct FilterContext<'a> {
+    tx: &'a channel::Sender<Message>,
+    root: &'a Root,
+    parent: &'a Parent,
+}
+
+type FilterFn = dyn Fn(&Path, &fs::Metadata, &FilterContext) -> FilterDecision + Send + Sync;
+
+/// The closure of `Walk::filter`, or one of the filters of the options that exclude entries
+/// (see `Walk::filters`).
+#[derive(Clone)]
+struct EntryFilter(Arc<FilterFn>);
+
+impl fmt::Debug for EntryFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EntryFilter")
+    }
+}
+
+/// The closure of `Walk::on_error`.
+#[derive(Clone)]
+struct ErrorHandler(Arc<dyn Fn(&Error) + Send + Sync>);
+
+impl fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorHandler")
+    }
+}
+
+/// Passes an error to the handler of `Walk::on_error`, if there is one, and returns it otherwise.
+fn handle_error(on_error: Option<&ErrorHandler>, error: Error) -> Option<Error> {
+    match on_error {
+        Some(on_error) => {
+            (on_error.0)(&error);
+            None
+        }
+        None => Some(error),
+    }
+}
+
+/// A handle to stop a running walk early (see `Walk::cancel_token`). All clones share the same
+/// state, so the walk can be cancelled from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    /// The token that this one was derived from with `CancelToken::child`, if any
+    parent: Option<Arc<AtomicBool>>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Stops the walks that use this token. Entries that are already being walked are still
+    /// finished, everything else is skipped.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.load(Ordering::Relaxed))
+    }
+
+    /// A new token that is cancelled along with this one, but can also be cancelled on its own.
+    /// Every run of a walk uses one, so that a timeout only stops that run, and not the token
+    /// the walk was given (or later runs of the walk).
+    fn child(&self) -> CancelToken {
+        CancelToken {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            parent: Some(Arc::clone(&self.cancelled)),
+        }
+    }
+}
+
+/// The accumulated size of one of the root directories of a walk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RootEntry {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::path"))]
+    pub path: PathBuf,
+    pub size: u64,
+    /// Number of files (everything that is not a directory) below this root
+    pub files: u64,
+    /// Number of directories below this root, including the root itself
+    pub dirs: u64,
+    /// Depth below the root that was walked. Only non-zero for the subdirectories that are
+    /// reported with `Walk::report_depth`.
+    pub depth: u64,
+    /// The type of the root itself, unless its metadata could not be retrieved
+    pub kind: Option<EntryKind>,
+    /// Size of the hardlinks below this root that were not counted, because the same file had
+    /// already been counted before. Always zero for reported subdirectories.
+    pub shared: u64,
+    /// The disk usage below this root, if both sizes were requested (see `Walk::run_both`).
+    /// `size` is the apparent size then.
+    pub disk_usage: Option<u64>,
+}
+
+impl RootEntry {
+    fn new(path: PathBuf) -> RootEntry {
+        RootEntry {
+            path,
+            size: 0,
+            files: 0,
+            dirs: 0,
+            depth: 0,
+            kind: None,
+            shared: 0,
+            disk_usage: None,
+        }
+    }
+
+    fn add(&mut self, size: u64, disk_usage: Option<u64>, is_dir: bool) {
+        self.size += size;
+        self.add_disk_usage(disk_usage);
+        if is_dir {
+            self.dirs += 1;
+        } else {
+            self.files += 1;
+        }
+    }
+
+    fn add_disk_usage(&mut self, disk_usage: Option<u64>) {
+        if let Some(disk_usage) = disk_usage {
+            *self.disk_usage.get_or_insert(0) += disk_usage;
+        }
+    }
+
+    /// Reverts `add` for an entry that turned out to be counted for another root.
+    fn remove(&mut self, claim: &Claim) {
+        self.size -= claim.size;
+        if let (Some(total), Some(disk_usage)) = (self.disk_usage.as_mut(), claim.disk_usage) {
+            *total -= disk_usage;
+        }
+        if claim.is_dir {
+            self.dirs -= 1;
+        } else {
+            self.files -= 1;
+        }
+    }
+}
+
+/// A single entry below one of the roots, as reported by `Walk::run_detailed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileEntry {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::path"))]
+    pub path: PathBuf,
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+/// Everything that `Walk::run` reports about a walk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WalkResult {
+    /// The roots in the order they were given in, each followed by its reported subdirectories
+    /// (see `Walk::report_depth`)
+    pub entries: Vec<RootEntry>,
+    pub errors: Vec<Error>,
+    pub stats: Stats,
+}
+
+/// The apparent size and the disk usage of one of the root directories of a walk, as reported
+/// by `Walk::run_both`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BothSizes {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::path"))]
+    pub path: PathBuf,
+    pub apparent_size: u64,
+    pub disk_usage: u64,
+}
+
+/// Statistics about the work done by a walk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stats {
+    /// Number of visited files (everything that is neither a directory nor a symlink)
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    pub errors: u64,
+    /// Number of root paths that could not be walked at all, included in `errors`
+    pub missing_roots: u64,
+    /// Number of errors that were dropped because of `Walk::ignore_errors`, not included in
+    /// `errors`
+    pub ignored_errors: u64,
+    /// Number of directories whose contents were not walked because of
+    /// `Walk::max_depth`
+    pub truncated_dirs: u64,
+    /// Number of files that were not counted because of `Walk::max_file_size`
+    pub skipped_large_files: u64,
+    /// Number of sockets, FIFOs and device nodes that were not counted because of
+    /// `Walk::skip_special_files`
+    pub skipped_special_files: u64,
+    /// Number of entries owned by other users that were not counted (or, for directories, not
+    /// walked) because of `Walk::owner`
+    pub skipped_other_owners: u64,
+    /// Number of directories that were not walked because of `Walk::prune`
+    pub pruned_dirs: u64,
+    /// Number of directories whose contents were not walked because of
+    /// `Walk::respect_ignore_markers`
+    pub marked_dirs: u64,
+    /// Whether the walk stopped early because of `Walk::limit`, so that the sizes are incomplete
+    pub limit_reached: bool,
+    /// Whether the walk was stopped early by its `CancelToken` (or `Walk::timeout`), so that the
+    /// sizes are incomplete
+    pub cancelled: bool,
+    /// Whether the walk was stopped early by `Walk::timeout`
+    pub timed_out: bool,
+    /// Size of all hardlinks that were not counted, see `RootEntry::shared`
+    pub shared: u64,
+    /// With `FilesizeType::Physical`, the disk usage of the counted files that is shared with
+    /// APFS clones and not included in the sizes
+    pub clone_shared: u64,
+    /// With `FilesizeType::Physical`, the number of files whose shared space is unknown, which
+    /// are counted with their full disk usage
+    pub unknown_clone_shares: u64,
+    /// Wall time of the walk itself, without the setup of the thread pool
+    pub elapsed: Duration,
+}
+
+impl Stats {
+    /// Total number of visited entries.
+    pub fn entries(&self) -> u64 {
+        self.files + self.dirs + self.symlinks
+    }
+
+    pub fn entries_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.entries() as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// What happens during a walk that is started with `Walk::run_streaming`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalkEvent {
+    /// An entry below `root` was counted with the given size. Hardlinks are only reported for
+    /// the first path they are found at below each root.
+    Entry {
+        root: PathBuf,
+        path: PathBuf,
+        size: u64,
+    },
+    /// All entries below a root have been counted, with the given total
+    RootFinished(RootEntry),
+    Error {
+        root: PathBuf,
+        error: Error,
+    },
+}
+
+/// A running walk. Dropping the handle (or calling `wait`) blocks until the walk is finished.
+pub struct WalkHandle {
+    /// The thread that runs the walk on the thread pool
+    walker: Option<JoinHandle<Result<Walked, WalkError>>>,
+    progress: Option<(Arc<StatusLine>, JoinHandle<()>)>,
+    /// The thread that turns the messages of the walker threads into events, if any
+    receiver: Option<JoinHandle<Stats>>,
+    /// The token of this run of the walk, see `CancelToken::child`
+    #[cfg(feature = "async")]
+    cancel: CancelToken,
+}
+
+impl WalkHandle {
+    /// Blocks until the walk is finished, and returns the statistics about it.
+    pub fn wait(mut self) -> Result<Stats, WalkError> {
+        let stats = match self.receiver.take() {
+            Some(receiver) => receiver.join(),
+            None => Ok(Stats::default()),
+        };
+        match stats {
+            Ok(stats) => self.join(stats),
+            Err(_) => {
+                // The other threads are still waited for
+                let _ = self.join(Stats::default());
+                Err(WalkError::Panicked)
+            }
+        }
+    }
+
+    /// Waits for the walker threads, and adds the time they took to the given statistics.
+    fn join(&mut self, mut stats: Stats) -> Result<Stats, WalkError> {
+        let mut panicked = false;
+        if let Some(walker) = self.walker.take() {
+            match walker.join() {
+                Ok(Ok((elapsed, cancelled, timed_out))) => {
+                    stats.elapsed = elapsed;
+                    stats.cancelled = cancelled;
+                    stats.timed_out = timed_out;
+                }
+                Ok(Err(_)) | Err(_) => panicked = true,
+            }
+        }
+        if let Some((progress, status_thread)) = self.progress.take() {
+            progress.finish();
+            panicked |= status_thread.join().is_err();
+        }
+        if panicked {
+            Err(WalkError::Panicked)
+        } else {
+            Ok(stats)
+        }
+    }
+}
+
+impl Drop for WalkHandle {
+    fn drop(&mut self) {
+        if let Some(receiver) = self.receiver.take() {
+            let _ = receiver.join();
+        }
+        let _ = self.join(Stats::default());
+    }
+}
+
+/// The events of a walk that was started with `Walk::run_async`, as a `Stream`. Dropping the
+/// stream before it ended cancels this run of the walk, but not the token of
+/// `Walk::cancel_token`.
+#[cfg(feature = "async")]
+pub struct WalkStream {
+    events: mpsc::UnboundedReceiver<WalkEvent>,
+    cancel: CancelToken,
+    /// Whether all events have been received, so that the walk is finished
+    ended: bool,
+}
+
+#[cfg(feature = "async")]
+impl Stream for WalkStream {
+    type Item = WalkEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<WalkEvent>> {
+        let polled = Pin::new(&mut self.events).poll_next(cx);
+        if let Poll::Ready(None) = polled {
+            self.ended = true;
+        }
+        polled
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for WalkStream {
+    fn drop(&mut self) {
+        if !self.ended {
+            self.cancel.cancel();
+        }
+    }
+}
+
+/// The result of a walk that was started with `Walk::run_async`, once it is finished. It does
+/// not depend on the `WalkStream`, which does not have to be polled for the walk to go on.
+#[cfg(feature = "async")]
+pub struct WalkFuture {
+    result: oneshot::Receiver<Result<WalkResult, WalkError>>,
+}
+
+#[cfg(feature = "async")]
+impl Future for WalkFuture {
+    type Output = Result<WalkResult, WalkError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.result).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            // The receiver thread panicked before it sent the result
+            Poll::Ready(Err(oneshot::Canceled)) => Poll::Ready(Err(WalkError::Panicked)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Receives the results of `Walk::run_with_visitor`, directly from the walker threads.
+///
+/// The methods are called from many threads at the same time, which is why visitors have to be
+/// `Sync`. Visitors that are not can be wrapped in a `LockedVisitor`. All methods do nothing by
+/// default.
+pub trait WalkVisitor {
+    /// An entry below `root` was counted with the given size. Hardlinks are only visited for the
+    /// first path they are found at (below each root, with `DedupScope::PerRoot`).
+    fn entry(&self, _root: &Path, _path: &Path, _size: u64) {}
+
+    /// An error that is not dropped by `Walk::ignore_errors`.
+    fn error(&self, _error: &Error) {}
+
+    /// All entries below `root` have been visited, and add up to `total`.
+    fn root_finished(&self, _root: &Path, _total: u64) {}
+}
+
+/// Makes a visitor that is not `Sync` usable with `Walk::run_with_visitor`, by only calling it
+/// with a lock held.
+#[derive(Debug, Default)]
+pub struct LockedVisitor<V>(Mutex<V>);
+
+impl<V> LockedVisitor<V> {
+    pub fn new(visitor: V) -> LockedVisitor<V> {
+        LockedVisitor(Mutex::new(visitor))
+    }
+
+    pub fn into_inner(self) -> V {
+        self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The visitor, even if it panicked in an earlier call (which `run_with_visitor` returns as
+    /// `WalkError::Panicked`).
+    fn lock(&self) -> MutexGuard<'_, V> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<V: WalkVisitor> WalkVisitor for LockedVisitor<V> {
+    fn entry(&self, root: &Path, path: &Path, size: u64) {
+        self.lock().entry(root, path, size);
+    }
+
+    fn error(&self, error: &Error) {
+        self.lock().error(error);
+    }
+
+    fn root_finished(&self, root: &Path, total: u64) {
+        self.lock().root_finished(root, total);
+    }
+}
+
+/// The state of `Walk::run_with_visitor` that is shared by the walker threads. The entries are
+/// summed up here instead of in the receiver thread.
+struct Visit<'a> {
+    visitor: &'a (dyn WalkVisitor + Sync),
+    dedup: DedupScope,
+    /// The entries with a unique ID that were already visited, with their root for
+    /// `DedupScope::PerRoot`. Unlike in `RootTotals`, the first root to reach an entry wins (see
+    /// `root_walk`).
+    seen: Mutex<HashSet<(Option<PathBuf>, UniqueID)>>,
+    totals: HashMap<PathBuf, AtomicU64>,
+    files: AtomicU64,
+    dirs: AtomicU64,
+    symlinks: AtomicU64,
+    shared: AtomicU64,
+}
+
+impl Visit<'_> {
+    fn entry(
+        &self,
+        root: &Path,
+        path: &Path,
+        unique_id: Option<UniqueID>,
+        size: u64,
+        metadata: &fs::Metadata,
+    ) {
+        if let Some(unique_id) = unique_id {
+            let scope = (self.dedup == DedupScope::PerRoot).then(|| root.to_path_buf());
+            if !self
+                .seen
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert((scope, unique_id))
+            {
+                self.shared.fetch_add(size, Ordering::Relaxed);
+                return;
+            }
+        }
+        let count = if metadata.is_dir() {
+            &self.dirs
+        } else if metadata.file_type().is_symlink() {
+            &self.symlinks
+        } else {
+            &self.files
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+        if let Some(total) = self.totals.get(root) {
+            total.fetch_add(size, Ordering::Relaxed);
+        }
+        self.visitor.entry(root, path, size);
+    }
+
+    fn finish_root(&self, root: &Path) {
+        let total = self
+            .totals
+            .get(root)
+            .map_or(0, |total| total.load(Ordering::Relaxed));
+        self.visitor.root_finished(root, total);
+    }
+
+    /// Adds what the walker threads counted to the statistics of the receiver thread.
+    fn add_stats(&self, stats: &mut Stats) {
+        stats.files += self.files.load(Ordering::Relaxed);
+        stats.dirs += self.dirs.load(Ordering::Relaxed);
+        stats.symlinks += self.symlinks.load(Ordering::Relaxed);
+        stats.shared += self.shared.load(Ordering::Relaxed);
+    }
+}
+
+/// A single filesystem entry, as sent from the walker threads to the receiver thread.
+struct SizeEntry {
+    unique_id: Option<UniqueID>,
+    root: PathBuf,
+    /// The subdirectory of the root this entry is reported in, if any
+    dir: Option<PathBuf>,
+    /// The path of the entry itself, only sent if it is needed (see `Settings::entry_paths`)
+    path: Option<PathBuf>,
+    /// The user ID of the owner, only sent if it is needed (see `Settings::owners`)
+    owner: Option<u32>,
+    /// The device the entry is stored on, only sent if it is needed (see `Settings::devices`)
+    device: Option<u64>,
+    size: u64,
+    /// The disk usage, if both sizes are requested (see `Settings::both`). `size` is the
+    /// apparent size then.
+    disk_usage: Option<u64>,
+    is_dir: bool,
+    is_symlink: bool,
+}
+
+enum Message {
+    SizeEntry(SizeEntry),
+    /// All entries below the given root have been sent
+    FinishedEntry {
+        root: PathBuf,
+        kind: Option<EntryKind>,
+    },
+    Error {
+        root: PathBuf,
+        error: Error,
+    },
+    /// A directory that was not walked, because it is on another filesystem than its root
+    SkippedMountPoint(PathBuf),
+    /// A directory whose contents were not walked, because of `Settings::max_depth`
+    TruncatedDir,
+    /// A file that was not counted, because it is larger than `Settings::max_file_size`
+    SkippedLargeFile,
+    /// A socket, FIFO or device node that was not counted, see `Settings::skip_special_files`
+    SkippedSpecialFile,
+    /// An entry that was not counted, because it is owned by someone else than `Settings::owner`
+    SkippedOtherOwner,
+    /// Space of a counted file that is shared with APFS clones and left out of its size
+    CloneShared(u64),
+    /// A file whose space shared with APFS clones is unknown, see `FilesizeType::Physical`
+    UnknownCloneShare,
+    /// A directory that was not walked, because its name is in `Settings::prune`
+    PrunedDir,
+    /// A directory whose contents were not walked, because it contains a `.diskusignore` file
+    MarkedDir,
+    /// `Settings::limit` was reached, all further entries are skipped
+    LimitReached,
+    /// A file that was counted despite `Settings::newer_than` or `Settings::older_than`, because
+    /// its modification time is unknown or in the future
+    UndatedFile(PathBuf),
+    /// A directory whose contents were not walked, because it is tagged as a cache directory
+    CacheDir(PathBuf),
+    /// A root that is a block device, which is reported with the capacity of the device
+    DeviceCapacity(PathBuf),
+    /// A file whose extended attributes could not be read for `Settings::include_xattrs`,
+    /// which is counted without them
+    UnreadableXattrs(PathBuf),
+}
+
+/// How an entry with a unique ID was counted for one of the roots.
+#[derive(Debug, Clone)]
+struct Claim {
+    /// The position of the root in `RootTotals::order`
+    root: usize,
+    dir: Option<PathBuf>,
+    size: u64,
+    disk_usage: Option<u64>,
+    is_dir: bool,
+}
+
+/// The roots that counted an entry with a unique ID. Most entries are only counted by a single
+/// root, which does not need an allocation.
+#[derive(Debug)]
+struct Claims {
+    first: Claim,
+    others: Vec<Claim>,
+}
+
+impl Claims {
+    fn iter(&self) -> impl Iterator<Item = &Claim> {
+        std::iter::once(&self.first).chain(&self.others)
+    }
+}
+
+/// Per-root sums, as accumulated in the receiver thread.
+#[derive(Default)]
+struct RootTotals {
+    ids: HashMap<UniqueID, Claims>,
+    dedup: DedupScope,
+    /// The position of every root in the order the roots were given in
+    order: HashMap<PathBuf, usize>,
+    /// The IDs of the entries that were counted by more than one root, by the position of the
+    /// root. Only kept with `DedupScope::Global`, see `RootTotals::resolve`.
+    contested: HashMap<usize, HashSet<UniqueID>>,
+    /// The positions of the roots that counted any entry with a unique ID, which the roots before
+    /// them may contain as well. Only kept with `DedupScope::Global`, see `Receiver::release`.
+    claiming: HashSet<usize>,
+    entries: HashMap<PathBuf, RootEntry>,
+    /// Sums of the reported subdirectories of each root. These only contain the entries that are
+    /// not part of a deeper reported subdirectory.
+    subdirs: HashMap<PathBuf, HashMap<PathBuf, RootEntry>>,
+    config: ExtrasConfig,
+    largest_files: LargestFiles,
+    /// Every counted entry, if `ExtrasConfig::files` is set
+    files: Vec<FileEntry>,
+    /// Sizes of all files by lowercase extension, if `ExtrasConfig::extensions` is set
+    extensions: HashMap<String, u64>,
+    owners: HashMap<u32, u64>,
+    devices: HashMap<u64, u64>,
+    stats: Stats,
+}
+
+impl RootTotals {
+    fn new(config: ExtrasConfig, dedup: DedupScope, order: &[PathBuf]) -> RootTotals {
+        let mut positions = HashMap::new();
+        for (position, root) in order.iter().enumerate() {
+            positions.entry(root.clone()).or_insert(position);
+        }
+        RootTotals {
+            config,
+            dedup,
+            order: positions,
+            largest_files: LargestFiles::new(config.largest_files),
+            ..RootTotals::default()
+        }
+    }
+
+    fn position(&self, root: &Path) -> usize {
+        self.order.get(root).copied().unwrap_or(usize::MAX)
+    }
+
+    /// Adds an entry to the sums of its root, and returns whether it was counted, which it is
+    /// not if it is a hardlink that was already counted for the same root.
+    fn add(&mut self, entry: SizeEntry) -> bool {
+        let SizeEntry {
+            unique_id,
+            root,
+            dir,
+            path,
+            owner,
+            device,
+            size,
+            disk_usage,
+            is_dir,
+            is_symlink,
+        } = entry;
+
+        if is_dir {
+            self.stats.dirs += 1;
+        } else if is_symlink {
+            self.stats.symlinks += 1;
+        } else {
+            self.stats.files += 1;
+        }
+
+        // Whether the entry has not been counted for any root yet
+        let mut first = true;
+        if let Some(unique_id) = unique_id {
+            let claim = Claim {
+                root: self.position(&root),
+                dir: dir.clone(),
+                size,
+                disk_usage,
+                is_dir,
+            };
+            if self.dedup == DedupScope::Global {
+                self.claiming.insert(claim.root);
+            }
+            match self.ids.entry(unique_id) {
+                Entry::Vacant(vacant) => {
+                    vacant.insert(Claims {
+                        first: claim,
+                        others: vec![],
+                    });
+                }
+                // Only count this entry once for each root
+                Entry::Occupied(occupied)
+                    if occupied.get().iter().any(|other| other.root == claim.root) =>
+                {
+                    self.stats.shared += size;
+                    self.entries
+                        .entry(root)
+                        .or_insert_with_key(|root| RootEntry::new(root.clone()))
+                        .shared += size;
+                    return false;
+                }
+                Entry::Occupied(mut occupied) => {
+                    first = false;
+                    if self.dedup == DedupScope::Global {
+                        for other in occupied.get().iter() {
+                            self.contested
+                                .entry(other.root)
+                                .or_default()
+                                .insert(unique_id);
+                        }
+                        self.contested
+                            .entry(claim.root)
+                            .or_default()
+                            .insert(unique_id);
+                    }
+                    occupied.get_mut().others.push(claim);
+                }
+            }
+        }
+        // The summaries across all roots only count every entry once
+        let owner = owner.filter(|_| first);
+        let device = device.filter(|_| first);
+        let path = path.filter(|_| first);
+
+        if let Some(dir) = dir {
+            self.subdirs
+                .entry(root.clone())
+                .or_default()
+                .entry(dir)
+                .or_insert_with_key(|dir| RootEntry::new(dir.clone()))
+                .add(size, disk_usage, is_dir);
+        }
+
+        if let Some(owner) = owner {
+            *self.owners.entry(owner).or_default() += size;
+        }
+        if let Some(device) = device {
+            *self.devices.entry(device).or_default() += size;
+        }
+
+        let path = match path {
+            Some(path) if self.config.files => {
+                self.files.push(FileEntry {
+                    path: path.clone(),
+                    size,
+                    is_dir,
+                });
+                Some(path)
+            }
+            path => path,
+        };
+        if let Some(path) = path.filter(|_| !is_dir) {
+            if self.config.extensions.is_some() {
+                let extension = path.extension().map_or_else(
+                    || "<none>".to_string(),
+                    |extension| extension.to_string_lossy().to_lowercase(),
+                );
+                *self.extensions.entry(extension).or_default() += size;
+            }
+            if !is_symlink {
+                self.largest_files.add(size, path);
+            }
+        }
+
+        self.entries
+            .entry(root)
+            .or_insert_with_key(|root| RootEntry::new(root.clone()))
+            .add(size, disk_usage, is_dir);
+        true
+    }
+
+    /// With `DedupScope::Global`, removes the entries that the given root shares with roots that
+    /// were given before it from its sums, so that they are only counted for the first root. This
+    /// must only be called once all of these roots are finished.
+    fn resolve(&mut self, root: &Path) {
+        let position = self.position(root);
+        let Some(ids) = self.contested.remove(&position) else {
+            return;
+        };
+        for id in ids {
+            let claims = &self.ids[&id];
+            if !claims.iter().any(|claim| claim.root < position) {
+                continue;
+            }
+            let Some(claim) = claims.iter().find(|claim| claim.root == position) else {
+                continue;
+            };
+            if let Some(entry) = self.entries.get_mut(root) {
+                entry.remove(claim);
+                entry.shared += claim.size;
+            }
+            let subdir = claim.dir.as_ref().and_then(|dir| {
+                self.subdirs
+                    .get_mut(root)
+                    .and_then(|subdirs| subdirs.get_mut(dir))
+            });
+            if let Some(subdir) = subdir {
+                subdir.remove(claim);
+            }
+            self.stats.shared += claim.size;
+        }
+    }
+
+    /// Whether the sums of the given root may still change once it is finished, because it
+    /// counted entries that are attributed to the roots before it if they contain them as well.
+    fn may_share(&self, root: &Path) -> bool {
+        self.claiming.contains(&self.position(root))
+    }
+
+    /// Counts the messages that only show up in the statistics.
+    fn count(&mut self, msg: &Message) {
+        match *msg {
+            Message::TruncatedDir => self.stats.truncated_dirs += 1,
+            Message::SkippedLargeFile => self.stats.skipped_large_files += 1,
+            Message::SkippedSpecialFile => self.stats.skipped_special_files += 1,
+            Message::SkippedOtherOwner => self.stats.skipped_other_owners += 1,
+            Message::CloneShared(size) => self.stats.clone_shared += size,
+            Message::UnknownCloneShare => self.stats.unknown_clone_shares += 1,
+            Message::PrunedDir => self.stats.pruned_dirs += 1,
+            Message::MarkedDir => self.stats.marked_dirs += 1,
+            Message::LimitReached => self.stats.limit_reached = true,
+            _ => {}
+        }
+    }
+
+    fn count_error(&mut self, error: &Error) {
+        self.stats.errors += 1;
+        if let Error::NoMetadataForRoot(..) | Error::CouldNotReadDevice(_) = error {
+            self.stats.missing_roots += 1;
+        }
+    }
+
+    /// Records the type of a root, once it is finished.
+    fn finish(&mut self, root: &Path, kind: Option<EntryKind>) {
+        if let Some(entry) = self.entries.get_mut(root) {
+            entry.kind = kind;
+        }
+    }
+
+    /// Removes the reported subdirectories of the given root. Every subdirectory includes the
+    /// sums of its own subdirectories, and they are ordered by path so that children directly
+    /// follow their parents.
+    fn take_subdirs(&mut self, root: &Path) -> Vec<RootEntry> {
+        let subdirs = self.subdirs.remove(root).unwrap_or_default();
+
+        let mut cumulated = subdirs.clone();
+        for (dir, entry) in &subdirs {
+            for ancestor in dir.ancestors().skip(1) {
+                if ancestor == root {
+                    break;
+                }
+                if let Some(ancestor) = cumulated.get_mut(ancestor) {
+                    ancestor.size += entry.size;
+                    ancestor.files += entry.files;
+                    ancestor.dirs += entry.dirs;
+                    ancestor.add_disk_usage(entry.disk_usage);
+                }
+            }
+        }
+
+        let mut entries: Vec<RootEntry> = cumulated
+            .into_values()
+            .map(|mut entry| {
+                entry.depth = entry
+                    .path
+                    .strip_prefix(root)
+                    .map_or(0, |path| path.components().count() as u64);
+                entry.kind = Some(EntryKind::Dir);
+                entry
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
+
+    fn total(&self) -> RootEntry {
+        sum_entries(self.entries.values())
+    }
+
+    fn take_extras(&mut self) -> Extras {
+        Extras {
+            #[cfg(feature = "cli")]
+            breakdowns: self.take_breakdowns(),
+            largest_files: std::mem::take(&mut self.largest_files).into_entries(),
+            owners: std::mem::take(&mut self.owners),
+        }
+    }
+
+    /// The breakdowns that are configured to be printed. The owners are left in place.
+    #[cfg(feature = "cli")]
+    fn take_breakdowns(&mut self) -> Vec<Breakdown> {
+        let mut breakdowns = vec![];
+        if let Some(rows) = self.config.extensions {
+            breakdowns.push(Breakdown::new(
+                BreakdownKind::Extension,
+                std::mem::take(&mut self.extensions),
+                rows,
+            ));
+        }
+        if self.config.owners {
+            let mut by_name: HashMap<String, u64> = HashMap::new();
+            for (&uid, &size) in &self.owners {
+                *by_name.entry(user_name(uid)).or_default() += size;
+            }
+            breakdowns.push(Breakdown::new(BreakdownKind::Owner, by_name, usize::MAX));
+        }
+        if self.config.devices {
+            let names = device_names(self.devices.keys().copied());
+            let by_name = self
+                .devices
+                .drain()
+                .map(|(device, size)| (names[&device].clone(), size))
+                .collect();
+            breakdowns.push(Breakdown::new(BreakdownKind::Device, by_name, usize::MAX));
+        }
+        breakdowns
+    }
+}
+
+/// What `Receiver::receive` passes on from the messages of the walker threads.
+enum Received {
+    Event(WalkEvent),
+    /// A root that could not be read at all, reported when it is finished
+    Unreadable(PathBuf),
+    /// A root is finished, which is reported separately once its sums are final
+    Finished,
+    /// No message arrived within the refresh interval of `Receiver::receive`
+    Idle,
+    SkippedMountPoint(PathBuf),
+    UndatedFile(PathBuf),
+    CacheDir(PathBuf),
+    UnreadableXattrs(PathBuf),
+    DeviceCapacity(PathBuf),
+}
+
+/// Sums up the messages of the walker threads in the receiver thread. Every way of running a
+/// walk receives them through `Receiver::receive`, and only differs in what it does with the
+/// results.
+struct Receiver {
+    totals: RootTotals,
+    /// The roots in the order they were given in
+    order: Vec<PathBuf>,
+    finished_roots: HashSet<PathBuf>,
+    /// The position of the first root in `order` that is not finished yet
+    next_root: usize,
+    ignore_errors: GlobSet,
+    on_error: Option<ErrorHandler>,
+}
+
+impl Receiver {
+    fn new(walk: &Walk, config: ExtrasConfig, order: Vec<PathBuf>) -> Receiver {
+        Receiver {
+            totals: RootTotals::new(config, walk.dedup, &order),
+            order,
+            finished_roots: HashSet::new(),
+            next_root: 0,
+            ignore_errors: walk.ignore_errors.clone(),
+            on_error: walk.on_error.clone(),
+        }
+    }
+
+    /// Receives the messages until the walker threads are finished, or until `emit` returns
+    /// `false`. The channel is dropped then, which makes the walker threads stop. With a
+    /// `refresh` interval, `Received::Idle` is emitted whenever no message arrived within it.
+    fn receive(
+        &mut self,
+        rx: channel::Receiver<Message>,
+        refresh: Option<Duration>,
+        mut emit: impl FnMut(&mut Receiver, Received) -> bool,
+    ) {
+        let mut last_refresh = Instant::now();
+        loop {
+            let msg = match refresh {
+                Some(refresh) => {
+                    match rx.recv_timeout(refresh.saturating_sub(last_refresh.elapsed())) {
+                        Ok(msg) => msg,
+                        Err(channel::RecvTimeoutError::Timeout) => {
+                            last_refresh = Instant::now();
+                            if !emit(self, Received::Idle) {
+                                return;
+                            }
+                            continue;
+                        }
+                        Err(channel::RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                None => match rx.recv() {
+                    Ok(msg) => msg,
+                    Err(_) => return,
+                },
+            };
+            if !self.dispatch(msg, &mut emit) {
+                return;
+            }
+        }
+    }
+
+    /// Adds a single message to the sums, and emits what it reports.
+    fn dispatch(
+        &mut self,
+        msg: Message,
+        emit: &mut impl FnMut(&mut Receiver, Received) -> bool,
+    ) -> bool {
+        let received = match msg {
+            Message::SizeEntry(entry) if self.totals.config.paths => {
+                let root = entry.root.clone();
+                let path = entry.path.clone();
+                let size = entry.size;
+                let counted = self.totals.add(entry);
+                let Some(path) = path.filter(|_| counted) else {
+                    return true;
+                };
+                Received::Event(WalkEvent::Entry { root, path, size })
+            }
+            Message::SizeEntry(entry) => {
+                self.totals.add(entry);
+                return true;
+            }
+            Message::Error { root, error } if self.ignore_errors.matches(error.path(), &root) => {
+                self.totals.stats.ignored_errors += 1;
+                return true;
+            }
+            Message::Error { root, error } => {
+                self.totals.count_error(&error);
+                match handle_error(self.on_error.as_ref(), error) {
+                    Some(error) => Received::Event(WalkEvent::Error { root, error }),
+                    None => return true,
+                }
+            }
+            Message::FinishedEntry { root, kind } => {
+                self.totals.finish(&root, kind);
+                self.finished_roots.insert(root.clone());
+                if !emit(self, Received::Finished) {
+                    return false;
+                }
+                // Roots that cannot share entries with the roots before them are final already
+                if !self.totals.may_share(&root) && !self.report(root, emit) {
+                    return false;
+                }
+                return self.release(emit);
+            }
+            Message::SkippedMountPoint(path) => Received::SkippedMountPoint(path),
+            Message::UndatedFile(path) => Received::UndatedFile(path),
+            Message::CacheDir(path) => Received::CacheDir(path),
+            Message::UnreadableXattrs(path) => Received::UnreadableXattrs(path),
+            Message::DeviceCapacity(path) => Received::DeviceCapacity(path),
+            msg => {
+                self.totals.count(&msg);
+                return true;
+            }
+        };
+        emit(self, received)
+    }
+
+    /// Reports the finished roots that may share entries with the roots before them, once all of
+    /// these are finished as well. This way, the entries they share are attributed
+    /// deterministically (see `RootTotals::resolve`).
+    fn release(&mut self, emit: &mut impl FnMut(&mut Receiver, Received) -> bool) -> bool {
+        while let Some(root) = self
+            .order
+            .get(self.next_root)
+            .filter(|root| self.finished_roots.contains(*root))
+            .cloned()
+        {
+            self.next_root += 1;
+            if self.totals.may_share(&root) && !self.report(root, emit) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn report(
+        &mut self,
+        root: PathBuf,
+        emit: &mut impl FnMut(&mut Receiver, Received) -> bool,
+    ) -> bool {
+        self.totals.resolve(&root);
+        let received = match self.totals.entries.get(&root) {
+            Some(entry) => Received::Event(WalkEvent::RootFinished(entry.clone())),
+            None => Received::Unreadable(root),
+        };
+        emit(self, received)
+    }
+}
+
+/// The largest regular files of a walk. Only a fixed number of them is kept, so the memory use
+/// does not depend on the number of files.
+#[derive(Default)]
+struct LargestFiles {
+    limit: usize,
+    /// A min-heap, so that the smallest of the kept files can be replaced
+    heap: BinaryHeap<Reverse<(u64, PathBuf)>>,
+}
+
+impl LargestFiles {
+    fn new(limit: usize) -> LargestFiles {
+        LargestFiles {
+            limit,
+            heap: BinaryHeap::with_capacity(limit + 1),
+        }
+    }
+
+    fn add(&mut self, size: u64, path: PathBuf) {
+        if self.heap.len() < self.limit {
+            self.heap.push(Reverse((size, path)));
+        } else if self
+            .heap
+            .peek()
+            .is_some_and(|Reverse((min, _))| size > *min)
+        {
+            self.heap.pop();
+            self.heap.push(Reverse((size, path)));
+        }
+    }
+
+    /// The kept files, largest first. Files of equal size are ordered by path.
+    fn into_entries(self) -> Vec<RootEntry> {
+        let mut entries: Vec<RootEntry> = self
+            .heap
+            .into_iter()
+            .map(|Reverse((size, path))| RootEntry {
+                size,
+                files: 1,
+                kind: Some(EntryKind::File),
+                ..RootEntry::new(path)
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            b.size
+                .cmp(&a.size)
+                .then_with(|| compare_paths(&a.path, &b.path))
+        });
+        entries
+    }
+}
+
+/// Orders paths lexicographically by their bytes.
+pub(crate) fn compare_paths(path1: &Path, path2: &Path) -> std::cmp::Ordering {
+    path1
+        .as_os_str()
+        .as_encoded_bytes()
+        .cmp(path2.as_os_str().as_encoded_bytes())
+}
+
+fn sum_entries<'a>(entries: impl Iterator<Item = &'a RootEntry>) -> RootEntry {
+    let mut total = RootEntry::new(PathBuf::new());
+    for entry in entries {
+        total.size += entry.size;
+        total.files += entry.files;
+        total.dirs += entry.dirs;
+        total.shared += entry.shared;
+        total.add_disk_usage(entry.disk_usage);
+    }
+    total
+}
+
+/// Sums up the given entries, skipping subdirectories that are contained in one of the others.
+fn sum_outermost(entries: &[RootEntry]) -> RootEntry {
+    let paths: HashSet<&Path> = entries.iter().map(|entry| entry.path.as_path()).collect();
+    sum_entries(entries.iter().filter(|entry| {
+        !entry
+            .path
+            .ancestors()
+            .skip(1)
+            .take(entry.depth as usize)
+            .any(|ancestor| paths.contains(ancestor))
+    }))
+}
+
+/// The parts of a `Walk` that every walker thread needs.
+struct Settings<'a> {
+    filesize_type: FilesizeType,
+    report_depth: Option<u64>,
+    /// The depth below which nothing is walked
+    max_depth: Option<u64>,
+    counters: Arc<Progress>,
+    /// Errors that are dropped, see `Walk::ignore_errors`
+    ignore_errors: GlobSet,
+    /// Compute the apparent size and the disk usage of every entry
+    both: bool,
+    /// Decide which entries below the roots are counted, skipped or pruned, see `Walk::filters`
+    filters: Vec<EntryFilter>,
+    /// If not empty, only the files that match one of these patterns are counted
+    include: GlobSet,
+    /// Only the files with one of these lowercase extensions are counted, if given
+    extensions: Option<HashSet<String>>,
+    /// If not empty, only the files whose name matches one of these are counted
+    regex: Vec<Regex>,
+    /// The files whose name matches one of these are not counted
+    regex_exclude: Vec<Regex>,
+    /// Match the regexes against the path relative to the root instead of the name
+    regex_full_path: bool,
+    /// Regular files larger than this are skipped
+    max_file_size: Option<u64>,
+    /// Regular files modified before this are skipped
+    newer_than: Option<SystemTime>,
+    /// Regular files modified at or after this are skipped
+    older_than: Option<SystemTime>,
+    /// The time the walk started at
+    now: SystemTime,
+    /// The number of entries after which the walk stops
+    limit: Option<u64>,
+    /// The number of entries visited so far, only counted with a limit
+    visited: AtomicU64,
+    cancel: CancelToken,
+    /// Read the `.gitignore` files, whose entries are skipped
+    gitignore: bool,
+    /// Skip the contents of directories with a `CACHEDIR.TAG` file
+    skip_cache_dirs: bool,
+    /// Skip the contents of directories with a `.diskusignore` file
+    respect_ignore_markers: bool,
+    /// Skip directories that are stored on another device than their root
+    one_file_system: bool,
+    /// Devices of pseudo-filesystems, whose mount points are skipped
+    special_devices: HashSet<u64>,
+    /// Bind mounts below the roots whose contents are also walked elsewhere, and are skipped
+    bind_mounts: HashMap<PathBuf, PathBuf>,
+    /// Skip everything that is not a regular file, a directory or a symlink
+    skip_special_files: bool,
+    /// Skip the entries (and directories) that are not owned by this user ID
+    owner: Option<u32>,
+    /// Walk the targets of symlinks instead of the symlinks themselves
+    follow_symlinks: bool,
+    /// Count every hardlink, instead of every file only once
+    count_hardlinks: bool,
+    /// Open every file to find its hardlinks on Windows
+    dedup_hardlinks: bool,
+    /// Do not count the size of directories themselves
+    files_only: bool,
+    /// Add the size of the extended attributes to the size of files
+    include_xattrs: bool,
+    /// Walk the targets of roots that are symlinks
+    dereference_roots: bool,
+    /// Send the path of every entry to the receiver thread, not just the path of its root
+    entry_paths: bool,
+    /// Send the user ID of the owner of every entry to the receiver thread
+    owners: bool,
+    /// Send the device of every entry to the receiver thread
+    devices: bool,
+    trace: Option<Trace>,
+    /// Report the entries to this visitor instead of the receiver thread
+    visit: Option<&'a Visit<'a>>,
+}
+
+/// Walks every root, given as pairs of the path to walk and the path it is reported as.
+fn root_walk(tx: channel::Sender<Message>, roots: Vec<(PathBuf, PathBuf)>, settings: &Settings) {
+    let walk_root = |tx_ref: &mut channel::Sender<Message>, (entry, root): (PathBuf, PathBuf)| {
+        let entry = if settings.dereference_roots {
+            match dereference(&entry) {
+                Ok(target) => target,
+                Err(err) => {
+                    settings.error(tx_ref, &root, Error::NoMetadataForRoot(entry, err));
+                    settings.finish_root(tx_ref, &root, None);
+                    return;
+                }
+            }
+        } else {
+            entry
+        };
+        let root = Root {
+            walked: entry.clone(),
+            reported: root,
+            device: None,
+        };
+        walk(
+            tx_ref.clone(),
+            std::slice::from_ref(&entry),
+            &root,
+            None,
+            0,
+            &Parent::default(),
+            settings,
+        );
+    };
+    // A visitor counts a file for the first root that reaches it, so with `DedupScope::Global`
+    // the roots are walked one after another. That is the first root (in the order the roots
+    // were given in) that contains the file, like in `RootTotals`.
+    if settings
+        .visit
+        .is_some_and(|visit| visit.dedup == DedupScope::Global)
+    {
+        let mut tx = tx;
+        for root in roots {
+            walk_root(&mut tx, root);
+        }
+    } else {
+        roots.into_par_iter().for_each_with(tx, walk_root);
+    }
+}
+
+/// The elapsed time of a walk, whether it was cancelled and whether that was because of
+/// `Walk::timeout`.
+type Walked = (Duration, bool, bool);
+
+/// Walks the roots on the given pool. Fails if the timer thread panicked.
+fn walk_in(
+    pool: &rayon::ThreadPool,
+    tx: channel::Sender<Message>,
+    roots: Vec<(PathBuf, PathBuf)>,
+    settings: &Settings,
+    timeout: Option<Duration>,
+) -> Result<Walked, WalkError> {
+    pool.install(|| {
+        let timer = timeout.map(|timeout| spawn_timer(timeout, settings.cancel.clone()));
+        let start = Instant::now();
+        root_walk(tx, roots, settings);
+        let elapsed = start.elapsed();
+        let cancelled = settings.cancel.is_cancelled();
+        let timed_out = match timer {
+            Some((done, timer_thread)) => {
+                drop(done);
+                timer_thread.join().map_err(|_| WalkError::Panicked)?
+            }
+            None => false,
+        };
+        Ok((elapsed, cancelled, cancelled && timed_out))
+    })
+}
+
+/// Cancels the given token after the timeout, unless the returned sender is dropped before. The
+/// thread returns whether it cancelled the token.
+fn spawn_timer(timeout: Duration, cancel: CancelToken) -> (channel::Sender<()>, JoinHandle<bool>) {
+    let (done_tx, done_rx) = channel::bounded::<()>(0);
+    let timer_thread = thread::spawn(move || {
+        let timed_out = done_rx.recv_timeout(timeout) == Err(channel::RecvTimeoutError::Timeout);
+        if timed_out {
+            cancel.cancel();
+        }
+        timed_out
+    });
+    (done_tx, timer_thread)
+}
+
+/// Resolves the given path if it is a symlink. Fails for broken symlinks.
+fn dereference(path: &Path) -> io::Result<PathBuf> {
+    if path.symlink_metadata()?.file_type().is_symlink() {
+        fs::canonicalize(path)
+    } else {
+        Ok(path.to_path_buf())
+    }
+}
+
+/// What the entries passed to `walk` inherit from the directory they are contained in.
+#[derive(Clone, Default)]
+struct Parent {
+    /// The `.gitignore` patterns that apply to the entries
+    ignore: Ignore,
+    /// The IDs of all directories from the root down to the parent
+    ancestors: Vec<UniqueID>,
+    /// The device the parent is stored on, only kept with `Walk::skip_special`
+    device: Option<u64>,
+}
+
+/// A root that is not walked, because it is contained in another one.
+#[derive(Debug, Clone)]
+struct Overlap {
+    /// The position of the root in `Walk::root_directories`
+    index: usize,
+    path: PathBuf,
+    /// The first root that contains it
+    outer: PathBuf,
+    /// Whether the root is the same as `outer`, just spelled differently
+    duplicate: bool,
+}
+
+/// One of the roots of a walk.
+#[derive(Clone)]
+struct Root {
+    /// The path the root is walked at
+    walked: PathBuf,
+    /// The path the root is reported as, see `Walk::absolute`
+    reported: PathBuf,
+    /// The device the root is stored on, once it is known (only with `Walk::one_file_system`)
+    device: Option<u64>,
+}
+
+/// Removes `.` components, trailing separators and `..` components that follow a normal component
+/// from a path, without accessing the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Canonicalizes the given path. If that fails (e.g. due to missing permissions), the path is
+/// only made absolute by joining it with the current directory.
+fn absolute_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| {
+        let path: PathBuf = path
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+        match env::current_dir() {
+            Ok(current_dir) => current_dir.join(path),
+            Err(_) => path,
+        }
+    })
+}
+
+/// Decides what happens with an entry below the roots, by asking the filters in turn (see
+/// `FilterDecision::and_then`).
+fn filter(
+    entry: &Path,
+    metadata: &fs::Metadata,
+    context: &FilterContext,
+    settings: &Settings,
+) -> FilterDecision {
+    settings
+        .filters
+        .iter()
+        .fold(FilterDecision::Count, |decision, filter| {
+            decision.and_then(|| (filter.0)(entry, metadata, context))
+        })
+}
+
+/// Prunes the entries for which the given closure returns `true`.
+fn prune_if<F>(prune: F) -> EntryFilter
+where
+    F: Fn(&Path, &fs::Metadata, &FilterContext) -> bool + Send + Sync + 'static,
+{
+    EntryFilter(Arc::new(move |entry, metadata, context| {
+        if prune(entry, metadata, context) {
+            FilterDecision::Prune
+        } else {
+            FilterDecision::Count
+        }
+    }))
+}
+
+/// Whether the name of an entry starts with a dot, or (on Windows) it has the hidden attribute.
+fn is_hidden(entry: &Path, metadata: &fs::Metadata) -> bool {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0 {
+            return true;
+        }
+    }
+    #[cfg(not(windows))]
+    let _ = metadata;
+
+    entry
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+impl Settings<'_> {
+    /// Reports an error to the receiver thread, and to the visitor of `Walk::run_with_visitor`.
+    fn error(&self, tx: &channel::Sender<Message>, root: &Path, error: Error) {
+        if !self.ignore_errors.matches(error.path(), root) {
+            self.counters.add_error();
+            if let Some(visit) = self.visit {
+                visit.visitor.error(&error);
+            }
+        }
+        let _ = tx.send(Message::Error {
+            root: root.to_path_buf(),
+            error,
+        });
+    }
+
+    /// Reports that all entries below a root have been sent.
+    fn finish_root(&self, tx: &channel::Sender<Message>, root: &Path, kind: Option<EntryKind>) {
+        match self.visit {
+            Some(visit) => visit.finish_root(root),
+            None => {
+                let _ = tx.send(Message::FinishedEntry {
+                    root: root.to_path_buf(),
+                    kind,
+                });
+            }
+        }
+    }
+
+    /// Counts a visited entry, and returns whether it is beyond `Settings::limit` or the walk was
+    /// cancelled, so that it has to be skipped.
+    fn stopped(&self, tx: &channel::Sender<Message>) -> bool {
+        if self.cancel.is_cancelled() {
+            return true;
+        }
+        let Some(limit) = self.limit else {
+            return false;
+        };
+        let visited = self.visited.fetch_add(1, Ordering::Relaxed);
+        if visited == limit {
+            let _ = tx.send(Message::LimitReached);
+        }
+        visited >= limit
+    }
+}
+
+/// Whether a file below a root matches `Walk::include`, `Walk::extensions`, `Walk::regex` and
+/// `Walk::regex_exclude`.
+fn included(entry: &Path, root: &Path, settings: &Settings) -> bool {
+    let extension_matches = |extensions: &HashSet<String>| {
+        let extension = entry.extension().map_or_else(String::new, |extension| {
+            extension.to_string_lossy().to_lowercase()
+        });
+        extensions.contains(&extension)
+    };
+    let regex_text = || {
+        let text = if settings.regex_full_path {
+            entry.strip_prefix(root).ok().map(Path::as_os_str)
+        } else {
+            entry.file_name()
+        };
+        text.unwrap_or_default().to_string_lossy()
+    };
+    let regex_matches = settings.regex.is_empty() && settings.regex_exclude.is_empty() || {
+        let text = regex_text();
+        (settings.regex.is_empty() || settings.regex.iter().any(|regex| regex.is_match(&text)))
+            && !settings
+                .regex_exclude
+                .iter()
+                .any(|regex| regex.is_match(&text))
+    };
+    (settings.include.is_empty() || settings.include.matches(entry, root))
+        && settings.extensions.as_ref().map_or(true, extension_matches)
+        && regex_matches
+}
+
+/// The name of the files that mark directories whose contents are skipped, see
+/// `Walk::respect_ignore_markers`.
+const IGNORE_MARKER: &str = ".diskusignore";
+
+/// Whether the given path is a `CACHEDIR.TAG` file that starts with the signature of the Cache
+/// Directory Tagging Specification (https://bford.info/cachedir/).
+fn is_cache_dir_tag(path: &Path) -> bool {
+    const SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+    if path.file_name() != Some("CACHEDIR.TAG".as_ref()) {
+        return false;
+    }
+    let mut start = [0; SIGNATURE.len()];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut start))
+        .is_ok_and(|()| start == SIGNATURE)
+}
+
+/// Whether a file is skipped because of `Walk::newer_than` or `Walk::older_than`. Files whose
+/// modification time is unknown or in the future are always counted.
+fn outside_time_window(
+    entry: &Path,
+    metadata: &fs::Metadata,
+    tx: &channel::Sender<Message>,
+    settings: &Settings,
+) -> bool {
+    if settings.newer_than.is_none() && settings.older_than.is_none() {
+        return false;
+    }
+    match metadata.modified() {
+        Ok(modified) if modified <= settings.now => {
+            settings
+                .newer_than
+                .is_some_and(|newer_than| modified < newer_than)
+                || settings
+                    .older_than
+                    .is_some_and(|older_than| modified >= older_than)
+        }
+        _ => {
+            let _ = tx.send(Message::UndatedFile(entry.to_path_buf()));
+            false
+        }
+    }
+}
+
+/// Walks the given entries at `depth` below the root. `dir` is the deepest reported subdirectory
+/// of the root the entries are contained in, if any.
+fn walk(
+    tx: channel::Sender<Message>,
+    entries: &[PathBuf],
+    root: &Root,
+    dir: Option<PathBuf>,
+    depth: u64,
+    parent: &Parent,
+    settings: &Settings,
+) {
+    entries.into_par_iter().for_each_with(tx, |tx_ref, entry| {
+        // Once the limit is reached or the walk is cancelled, the remaining entries are skipped,
+        // so that the walk ends quickly. The roots are still reported, their contents are not
+        // walked.
+        let stopped = settings.stopped(tx_ref);
+        if depth > 0 && stopped {
+            return;
+        }
+
+        // Broken symlinks are counted as symlinks, even if they are followed
+        let metadata = if settings.follow_symlinks {
+            entry.metadata().or_else(|_| entry.symlink_metadata())
+        } else {
+            entry.symlink_metadata()
+        };
+
+        if let Ok(metadata) = metadata {
+            // The roots themselves are always walked
+            let decision = if depth > 0 {
+                let context = FilterContext {
+                    tx: tx_ref,
+                    root,
+                    parent,
+                };
+                filter(entry, &metadata, &context, settings)
+            } else {
+                FilterDecision::Count
+            };
+            if decision == FilterDecision::Prune {
+                return;
+            }
+            let file_type = metadata.file_type();
+            let special_file =
+                !file_type.is_file() && !file_type.is_dir() && !file_type.is_symlink();
+            if depth > 0 && settings.skip_special_files && special_file {
+                let _ = tx_ref.send(Message::SkippedSpecialFile);
+                return;
+            }
+            let other_owner = settings
+                .owner
+                .is_some_and(|owner| owner_of(&metadata).is_some_and(|uid| uid != owner));
+            if depth > 0 && other_owner {
+                let _ = tx_ref.send(Message::SkippedOtherOwner);
+                return;
+            }
+
+            let root_on_device;
+            let root = if depth == 0 && settings.one_file_system {
+                root_on_device = Root {
+                    device: device_of(&metadata),
+                    ..root.clone()
+                };
+                &root_on_device
+            } else {
+                root
+            };
+            if settings.one_file_system && metadata.is_dir() && device_of(&metadata) != root.device
+            {
+                let _ = tx_ref.send(Message::SkippedMountPoint(entry.clone()));
+                return;
+            }
+            // Only mount points are skipped, not pseudo-filesystems that are walked on purpose
+            let special = device_of(&metadata)
+                .is_some_and(|device| settings.special_devices.contains(&device));
+            if depth > 0 && metadata.is_dir() && special && device_of(&metadata) != parent.device {
+                let _ = tx_ref.send(Message::SkippedMountPoint(entry.clone()));
+                return;
+            }
+            if depth > 0 && metadata.is_dir() && settings.bind_mounts.contains_key(entry) {
+                let _ = tx_ref.send(Message::SkippedMountPoint(entry.clone()));
+                return;
+            }
+
+            // Followed symlinks can lead to the same entry on many paths, so every entry has to
+            // be counted only once
+            let unique_id = if settings.follow_symlinks {
+                entry_id(&metadata)
+            } else {
+                generate_unique_id(entry, &metadata, settings.dedup_hardlinks)
+            };
+            // Directories that contain themselves would be walked forever
+            let dir_id = if metadata.is_dir() {
+                entry_id(&metadata)
+            } else {
+                None
+            };
+            if dir_id.is_some_and(|id| parent.ancestors.contains(&id)) {
+                settings.error(tx_ref, &root.reported, Error::FilesystemLoop(entry.clone()));
+                return;
+            }
+
+            // With include patterns, only the matching files are counted. Directories are still
+            // walked to find them, but their own size is not counted.
+            let filtered = !settings.include.is_empty()
+                || settings.extensions.is_some()
+                || !settings.regex.is_empty()
+                || !settings.regex_exclude.is_empty();
+            let counted = decision == FilterDecision::Count
+                && !(settings.files_only && metadata.is_dir())
+                && (!filtered
+                    || (!metadata.is_dir()
+                        && (depth == 0 || included(entry, &root.walked, settings))));
+            if !counted && !metadata.is_dir() {
+                return;
+            }
+            // Computed at most once, as `FilesizeType::DataSize` has to open the file
+            let mut entry_size = None;
+            let mut size_of_entry = || {
+                *entry_size
+                    .get_or_insert_with(|| settings.filesize_type.size_and_shared(entry, &metadata))
+            };
+            // Only files below the roots are limited, so only their size is computed for it
+            let too_large = depth > 0
+                && metadata.is_file()
+                && settings
+                    .max_file_size
+                    .is_some_and(|max_size| size_of_entry().0 > max_size);
+            if too_large {
+                let _ = tx_ref.send(Message::SkippedLargeFile);
+                return;
+            }
+            if depth > 0
+                && metadata.is_file()
+                && outside_time_window(entry, &metadata, tx_ref, settings)
+            {
+                return;
+            }
+
+            // Both sizes are computed from the same metadata
+            let (size, disk_usage) = if !counted {
+                (0, settings.both.then_some(0))
+            } else if settings.both {
+                (
+                    FilesizeType::ApparentSize.size(&metadata),
+                    Some(FilesizeType::DiskUsage.size(&metadata)),
+                )
+            } else {
+                (size_of_entry().0, None)
+            };
+            if counted && !settings.both && metadata.is_file() {
+                let _ = match size_of_entry().1 {
+                    Some(0) => Ok(()),
+                    Some(shared) => tx_ref.send(Message::CloneShared(shared)),
+                    None => tx_ref.send(Message::UnknownCloneShare),
+                };
+            }
+            let xattrs = counted
+                && settings.include_xattrs
+                && metadata.is_file()
+                && !matches!(
+                    settings.filesize_type,
+                    FilesizeType::Inodes | FilesizeType::Blocks
+                );
+            let (size, disk_usage) = if xattrs {
+                let xattr_size = xattr_size(entry).unwrap_or_else(|_| {
+                    let _ = tx_ref.send(Message::UnreadableXattrs(entry.clone()));
+                    0
+                });
+                (
+                    size + xattr_size,
+                    disk_usage.map(|disk_usage| disk_usage + xattr_size),
+                )
+            } else {
+                (size, disk_usage)
+            };
+
+            // The metadata of a device node says nothing about the device, so block devices that
+            // are given as roots are reported with their capacity
+            let device_root = depth == 0
+                && counted
+                && is_block_device(&metadata)
+                && !matches!(
+                    settings.filesize_type,
+                    FilesizeType::Inodes | FilesizeType::Blocks
+                );
+            let (size, disk_usage) = if device_root {
+                match device_capacity(entry) {
+                    Ok(capacity) => {
+                        let _ = tx_ref.send(Message::DeviceCapacity(entry.clone()));
+                        (capacity, disk_usage.map(|_| capacity))
+                    }
+                    Err(_) => {
+                        let error = Error::CouldNotReadDevice(entry.clone());
+                        settings.error(tx_ref, &root.reported, error);
+                        settings.finish_root(tx_ref, &root.reported, None);
+                        return;
+                    }
+                }
+            } else {
+                (size, disk_usage)
+            };
+
+            // Directories within the maximum depth are reported on their own
+            let dir = match settings.report_depth {
+                Some(report_depth) if metadata.is_dir() && depth >= 1 && depth <= report_depth => {
+                    Some(entry.clone())
+                }
+                _ => dir.clone(),
+            };
+
+            let unique_id = unique_id.filter(|_| !settings.count_hardlinks);
+            let sent = match settings.visit {
+                Some(visit) => {
+                    visit.entry(&root.reported, entry, unique_id, size, &metadata);
+                    true
+                }
+                None => tx_ref
+                    .send(Message::SizeEntry(SizeEntry {
+                        unique_id,
+                        root: root.reported.clone(),
+                        dir: dir.clone(),
+                        path: settings.entry_paths.then(|| entry.clone()),
+                        owner: owner_of(&metadata).filter(|_| settings.owners),
+                        device: device_of(&metadata).filter(|_| settings.devices),
+                        size,
+                        disk_usage,
+                        is_dir: metadata.is_dir(),
+                        is_symlink: metadata.file_type().is_symlink(),
+                    }))
+                    .is_ok(),
+            };
+            // The receiver is only gone if nobody waits for the results anymore, e.g. because the
+            // receiver of `run_streaming` was dropped. The walk just ends then, without walking
+            // the contents of the entry.
+            if !sent {
+                return;
+            }
+            settings.counters.add(size);
+
+            let truncated = settings
+                .max_depth
+                .is_some_and(|max_depth| depth >= max_depth);
+            if metadata.is_dir() && truncated {
+                let _ = tx_ref.send(Message::TruncatedDir);
+            } else if metadata.is_dir() && !stopped && !settings.cancel.is_cancelled() {
+                if let Some(ref trace) = settings.trace {
+                    trace.enter(entry);
+                }
+                let mut children = vec![];
+                match fs::read_dir(entry) {
+                    Ok(child_entries) => {
+                        for child_entry in child_entries.flatten() {
+                            children.push(child_entry.path());
+                        }
+                    }
+                    Err(err) => {
+                        let error = Error::CouldNotReadDir(entry.clone(), err);
+                        settings.error(tx_ref, &root.reported, error);
+                    }
+                }
+                // The directory itself is still counted, just like the roots
+                let cache_dir = depth > 0
+                    && settings.skip_cache_dirs
+                    && children.iter().any(|child| is_cache_dir_tag(child));
+                if cache_dir {
+                    let _ = tx_ref.send(Message::CacheDir(entry.clone()));
+                    children.clear();
+                }
+                let marked = depth > 0
+                    && settings.respect_ignore_markers
+                    && children
+                        .iter()
+                        .any(|child| child.file_name() == Some(IGNORE_MARKER.as_ref()));
+                if marked {
+                    let _ = tx_ref.send(Message::MarkedDir);
+                    children.clear();
+                }
+
+                let mut child_parent = Parent::default();
+                if settings.gitignore {
+                    child_parent.ignore = parent.ignore.enter(entry);
+                }
+                child_parent.ancestors = parent.ancestors.clone();
+                child_parent.ancestors.extend(dir_id);
+                if !settings.special_devices.is_empty() {
+                    child_parent.device = device_of(&metadata);
+                }
+                walk(
+                    tx_ref.clone(),
+                    &children[..],
+                    root,
+                    dir,
+                    depth + 1,
+                    &child_parent,
+                    settings,
+                );
+            };
+
+            if depth == 0 {
+                let kind = Some(EntryKind::of(&metadata));
+                settings.finish_root(tx_ref, &root.reported, kind);
+            }
+        } else if let Err(err) = metadata {
+            let error = if depth == 0 {
+                Error::NoMetadataForRoot(entry.clone(), err)
+            } else {
+                Error::NoMetadataForPath(entry.clone(), err)
+            };
+            settings.error(tx_ref, &root.reported, error);
+
+            if depth == 0 {
+                settings.finish_root(tx_ref, &root.reported, None);
+            }
+        };
+    });
+}
+
+/// Configures a `Walk` and checks the configuration. Everything that is not set here can still be
+/// configured on the built `Walk`.
+#[derive(Debug, Clone)]
+pub struct WalkBuilder {
+    root_directories: Vec<PathBuf>,
+    num_threads: usize,
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
+    filesize_type: FilesizeType,
+    exclude: GlobSet,
+    follow_symlinks: bool,
+    max_depth: Option<u64>,
+    report_depth: Option<u64>,
+    dedup: DedupScope,
+}
+
+impl WalkBuilder {
+    /// Starts a walk of the given paths, with three threads per CPU and `FilesizeType::DiskUsage`.
+    pub fn new(root_directories: Vec<PathBuf>) -> WalkBuilder {
+        WalkBuilder {
+            root_directories,
+            num_threads: 3 * num_cpus::get(),
+            thread_pool: None,
+            filesize_type: FilesizeType::DiskUsage,
+            exclude: GlobSet::default(),
+            follow_symlinks: false,
+            max_depth: None,
+            report_depth: None,
+            dedup: DedupScope::default(),
+        }
+    }
+
+    /// The number of threads that read the filesystem, unless a thread pool is given.
+    pub fn threads(mut self, num_threads: usize) -> WalkBuilder {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// See `Walk::thread_pool`.
+    pub fn thread_pool(mut self, thread_pool: Arc<rayon::ThreadPool>) -> WalkBuilder {
+        self.thread_pool = Some(thread_pool);
+        self
+    }
+
+    pub fn filesize_type(mut self, filesize_type: FilesizeType) -> WalkBuilder {
+        self.filesize_type = filesize_type;
+        self
+    }
+
+    /// See `Walk::exclude`.
+    pub fn exclude(mut self, exclude: GlobSet) -> WalkBuilder {
+        self.exclude = exclude;
+        self
+    }
+
+    /// See `Walk::follow_symlinks`.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> WalkBuilder {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// See `Walk::max_depth`.
+    pub fn max_depth(mut self, max_depth: Option<u64>) -> WalkBuilder {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// See `Walk::report_depth`.
+    pub fn report_depth(mut self, report_depth: Option<u64>) -> WalkBuilder {
+        self.report_depth = report_depth;
+        self
+    }
+
+    /// See `Walk::dedup`.
+    pub fn dedup(mut self, dedup: DedupScope) -> WalkBuilder {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Checks the configuration and creates the `Walk`.
+    pub fn build(self) -> Result<Walk, ConfigError> {
+        if self.root_directories.is_empty() {
+            return Err(ConfigError::NoPaths);
+        }
+        if self.num_threads == 0 && self.thread_pool.is_none() {
+            return Err(ConfigError::NoThreads);
+        }
+        let walk = Walk::new(self.root_directories, self.num_threads, self.filesize_type)
+            .exclude(self.exclude)
+            .follow_symlinks(self.follow_symlinks)
+            .max_depth(self.max_depth)
+            .report_depth(self.report_depth)
+            .dedup(self.dedup);
+        Ok(match self.thread_pool {
+            Some(thread_pool) => walk.thread_pool(thread_pool),
+            None => walk,
+        })
+    }
+}
+
+/// A configured walk, which can be run any number of times. Clones share the `CancelToken` and
+/// the counters (`Walk::progress`) of the original.
+#[derive(Debug, Clone)]
+pub struct Walk {
+    root_directories: Arc<[PathBuf]>,
+    num_threads: usize,
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
+    filesize_type: FilesizeType,
+    absolute: bool,
+    report_depth: Option<u64>,
+    max_depth: Option<u64>,
+    status_line: bool,
+    counters: Arc<Progress>,
+    trace: bool,
+    ignore_errors: GlobSet,
+    exclude: GlobSet,
+    filter: Option<EntryFilter>,
+    on_error: Option<ErrorHandler>,
+    include: GlobSet,
+    extensions: Option<HashSet<String>>,
+    regex: Vec<Regex>,
+    regex_exclude: Vec<Regex>,
+    regex_full_path: bool,
+    max_file_size: Option<u64>,
+    limit: Option<u64>,
+    timeout: Option<Duration>,
+    cancel: CancelToken,
+    newer_than: Option<SystemTime>,
+    older_than: Option<SystemTime>,
+    gitignore: bool,
+    skip_git_dirs: bool,
+    skip_hidden: bool,
+    prune: HashSet<OsString>,
+    skip_cache_dirs: bool,
+    respect_ignore_markers: bool,
+    one_file_system: bool,
+    skip_special: bool,
+    skip_bind_mounts: bool,
+    skip_special_files: bool,
+    owner: Option<u32>,
+    follow_symlinks: bool,
+    count_hardlinks: bool,
+    dedup_hardlinks: bool,
+    files_only: bool,
+    include_xattrs: bool,
+    dedup: DedupScope,
+    allow_overlap: bool,
+    dereference_roots: bool,
+}
+
+impl Walk {
+    /// Creates a walk without checking its configuration, `WalkBuilder` is the preferred way to
+    /// create one.
+    pub fn new<I, P>(root_directories: I, num_threads: usize, filesize_type: FilesizeType) -> Walk
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        Walk {
+            root_directories: root_directories.into_iter().map(Into::into).collect(),
+            num_threads,
+            thread_pool: None,
+            filesize_type,
+            absolute: false,
+            report_depth: None,
+            max_depth: None,
+            status_line: false,
+            counters: Arc::new(Progress::default()),
+            trace: false,
+            ignore_errors: GlobSet::default(),
+            exclude: GlobSet::default(),
+            filter: None,
+            on_error: None,
+            include: GlobSet::default(),
+            extensions: None,
+            regex: vec![],
+            regex_exclude: vec![],
+            regex_full_path: false,
+            max_file_size: None,
+            limit: None,
+            timeout: None,
+            cancel: CancelToken::new(),
+            newer_than: None,
+            older_than: None,
+            gitignore: false,
+            skip_git_dirs: false,
+            skip_hidden: false,
+            prune: HashSet::new(),
+            skip_cache_dirs: false,
+            respect_ignore_markers: false,
+            one_file_system: false,
+            skip_special: false,
+            skip_bind_mounts: false,
+            skip_special_files: false,
+            owner: None,
+            follow_symlinks: false,
+            count_hardlinks: false,
+            dedup_hardlinks: false,
+            files_only: false,
+            include_xattrs: false,
+            dedup: DedupScope::Global,
+            allow_overlap: false,
+            dereference_roots: false,
+        }
+    }
+
+    /// Walk on the given thread pool instead of a new one with the number of threads of the walk,
+    /// e.g. to share one pool between many walks and the rest of an application.
+    pub fn thread_pool(mut self, thread_pool: Arc<rayon::ThreadPool>) -> Walk {
+        self.thread_pool = Some(thread_pool);
+        self
+    }
+
+    /// The thread pool given by `Walk::thread_pool`, or a new one.
+    fn pool(&self) -> Result<Arc<rayon::ThreadPool>, WalkError> {
+        match &self.thread_pool {
+            Some(thread_pool) => Ok(Arc::clone(thread_pool)),
+            None => rayon::ThreadPoolBuilder::new()
+                .num_threads(self.num_threads)
+                .build()
+                .map(Arc::new)
+                .map_err(WalkError::ThreadPool),
+        }
+    }
+
+    /// Show a status line with the number of entries and bytes scanned so far on stderr, if it
+    /// is a TTY. This needs the `cli` feature.
+    pub fn status_line(mut self, status_line: bool) -> Walk {
+        self.status_line = status_line;
+        self
+    }
+
+    /// The live counters of the walk, which are reset whenever it is run. They can be read from
+    /// another thread while the walk is running, e.g. to show its progress.
+    pub fn progress(&self) -> Arc<Progress> {
+        Arc::clone(&self.counters)
+    }
+
+    /// Log every directory on stderr as it is entered, to find out where a walk got stuck.
+    pub fn trace(mut self, trace: bool) -> Walk {
+        self.trace = trace;
+        self
+    }
+
+    /// Pass every error to the given closure as soon as it is received, instead of collecting
+    /// the errors (in `WalkResult::errors`), reporting them as `WalkEvent::Error` or printing
+    /// them. It is called from the thread that receives the results of the walk, and only for
+    /// the errors that are not dropped by `Walk::ignore_errors`.
+    pub fn on_error<F>(mut self, on_error: F) -> Walk
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(ErrorHandler(Arc::new(on_error)));
+        self
+    }
+
+    /// Drop the errors for paths that match one of the given patterns, e.g. for directories that
+    /// are known to be unreadable. They are neither reported nor included in `Stats::errors`,
+    /// only in `Stats::ignored_errors`.
+    pub fn ignore_errors(mut self, ignore_errors: GlobSet) -> Walk {
+        self.ignore_errors = ignore_errors;
+        self
+    }
+
+    /// Skip all entries below the roots that match one of the given patterns. Excluded
+    /// directories are not walked at all.
+    pub fn exclude(mut self, exclude: GlobSet) -> Walk {
+        self.exclude = exclude;
+        self
+    }
+
+    /// Decide for every entry below the roots whether it is counted, skipped or pruned (skipped
+    /// with everything below it), e.g. from a lookup in a database. The closure is called from
+    /// the walker threads, concurrently for many entries. Entries that are excluded in any other
+    /// way, like with `Walk::exclude` or `Walk::prune`, are not passed to it.
+    pub fn filter<F>(mut self, filter: F) -> Walk
+    where
+        F: Fn(&Path, &fs::Metadata) -> FilterDecision + Send + Sync + 'static,
+    {
+        self.filter = Some(EntryFilter(Arc::new(move |entry, metadata, _| {
+            filter(entry, metadata)
+        })));
+        self
+    }
+
+    /// Only count the files below the roots that match one of the given patterns, unless no
+    /// patterns are given. Excluded entries are skipped even if they match.
+    pub fn include(mut self, include: GlobSet) -> Walk {
+        self.include = include;
+        self
+    }
+
+    /// Only count the files below the roots with one of the given extensions, if any are given.
+    /// Extensions are compared case-insensitively and may start with a dot. The empty extension
+    /// matches files without an extension.
+    pub fn extensions(mut self, extensions: Option<Vec<String>>) -> Walk {
+        self.extensions = extensions.map(|extensions| {
+            extensions
+                .iter()
+                .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                .collect()
+        });
+        self
+    }
+
+    /// Only count the files below the roots whose name matches one of the given regexes, unless
+    /// none are given.
+    pub fn regex(mut self, regex: Vec<Regex>) -> Walk {
+        self.regex = regex;
+        self
+    }
+
+    /// Do not count the files below the roots whose name matches one of the given regexes.
+    /// Directories are still walked.
+    pub fn regex_exclude(mut self, regex_exclude: Vec<Regex>) -> Walk {
+        self.regex_exclude = regex_exclude;
+        self
+    }
+
+    /// Match `Walk::regex` and `Walk::regex_exclude` against the path of every file relative to
+    /// its root, like `src/main.rs`, instead of its name.
+    pub fn regex_full_path(mut self, regex_full_path: bool) -> Walk {
+        self.regex_full_path = regex_full_path;
+        self
+    }
+
+    /// Skip the regular files that are larger than the given size, as measured by the size type of
+    /// the walk. They are not counted anywhere, but their number is reported in `Stats`. Roots
+    /// that are files are always counted.
+    pub fn max_file_size(mut self, max_file_size: Option<u64>) -> Walk {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Stop the walk after visiting the given number of entries (of any kind, including the ones
+    /// that are not counted). The sizes are incomplete then, which is reported as
+    /// `Stats::limit_reached`.
+    pub fn limit(mut self, limit: Option<u64>) -> Walk {
+        self.limit = limit;
+        self
+    }
+
+    /// Cancel the walk if it takes longer than the given time. The sizes are incomplete then,
+    /// which is reported as `Stats::timed_out`. Only that run is cancelled: the token of
+    /// `Walk::cancel_token` is not, so that the walk can be run again.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Walk {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Stop the walk early once the given token is cancelled, e.g. from another thread. The
+    /// roots are still reported with the sizes up to then, and `Stats::cancelled` is set.
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Walk {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Skip the regular files below the roots that were last modified before the given time.
+    /// Files whose modification time is unknown or in the future are always counted.
+    pub fn newer_than(mut self, newer_than: Option<SystemTime>) -> Walk {
+        self.newer_than = newer_than;
+        self
+    }
+
+    /// Skip the regular files below the roots that were last modified at or after the given time.
+    /// Together with `newer_than`, only the files modified in between are counted.
+    pub fn older_than(mut self, older_than: Option<SystemTime>) -> Walk {
+        self.older_than = older_than;
+        self
+    }
+
+    /// Skip the entries that are ignored by `.gitignore` (and `.git/info/exclude`) files, like
+    /// git does. Only the ignore files in the roots and below them are read.
+    pub fn gitignore(mut self, gitignore: bool) -> Walk {
+        self.gitignore = gitignore;
+        self
+    }
+
+    /// Skip all `.git` directories.
+    pub fn skip_git_dirs(mut self, skip_git_dirs: bool) -> Walk {
+        self.skip_git_dirs = skip_git_dirs;
+        self
+    }
+
+    /// Skip all entries below the roots whose name starts with a dot (and, on Windows, the
+    /// entries with the hidden attribute). Hidden directories are not walked at all.
+    pub fn skip_hidden(mut self, skip_hidden: bool) -> Walk {
+        self.skip_hidden = skip_hidden;
+        self
+    }
+
+    /// Skip the directories below the roots with one of the given names, like `node_modules`,
+    /// including everything below them.
+    pub fn prune(mut self, names: Vec<OsString>) -> Walk {
+        self.prune = names.into_iter().collect();
+        self
+    }
+
+    /// Do not walk the contents of the directories below the roots that contain a valid
+    /// `CACHEDIR.TAG` file, like the caches of cargo or pip. The directories themselves are still
+    /// counted.
+    pub fn skip_cache_dirs(mut self, skip_cache_dirs: bool) -> Walk {
+        self.skip_cache_dirs = skip_cache_dirs;
+        self
+    }
+
+    /// Do not walk the contents of the directories below the roots that contain a file named
+    /// `.diskusignore`, which marks scratch or cache directories for good. The directories
+    /// themselves are still counted, their number is reported in `Stats`.
+    pub fn respect_ignore_markers(mut self, respect_ignore_markers: bool) -> Walk {
+        self.respect_ignore_markers = respect_ignore_markers;
+        self
+    }
+
+    /// Do not walk directories that are stored on another filesystem than their root, like
+    /// `du -x`.
+    pub fn one_file_system(mut self, one_file_system: bool) -> Walk {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// Skip the mount points of pseudo-filesystems like `/proc`, `/sys` or `/dev`, and of the
+    /// tmpfs mounts below `/run` (only on Linux). Roots on such filesystems are still walked.
+    pub fn skip_special(mut self, skip_special: bool) -> Walk {
+        self.skip_special = skip_special;
+        self
+    }
+
+    /// Skip the bind mounts below the roots that show a directory which is also walked at another
+    /// place, so that it is not read twice (and, with `Walk::count_hardlinks`, not counted twice).
+    /// Only on Linux, where the mounts are read from `/proc/self/mountinfo`.
+    pub fn skip_bind_mounts(mut self, skip_bind_mounts: bool) -> Walk {
+        self.skip_bind_mounts = skip_bind_mounts;
+        self
+    }
+
+    /// Skip the sockets, FIFOs and device nodes below the roots, whose sizes are meaningless.
+    /// Their number is reported in `Stats`.
+    pub fn skip_special_files(mut self, skip_special_files: bool) -> Walk {
+        self.skip_special_files = skip_special_files;
+        self
+    }
+
+    /// Only count the entries below the roots that are owned by the user with the given ID, and
+    /// do not walk the directories of other users at all. The roots themselves are always walked.
+    /// The number of skipped entries is reported in `Stats`. Has no effect on Windows.
+    pub fn owner(mut self, owner: Option<u32>) -> Walk {
+        self.owner = owner;
+        self
+    }
+
+    /// Walk the targets of symlinks, including symlinked directories, instead of the symlinks
+    /// themselves. Every target is only counted once, and symlinks that lead to one of their
+    /// own parent directories are reported as `Error::FilesystemLoop`.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Walk {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Count every hardlink of a file, like `du -l`, instead of counting each file only once.
+    /// This also saves the memory to keep track of all files that were seen.
+    pub fn count_hardlinks(mut self, count_hardlinks: bool) -> Walk {
+        self.count_hardlinks = count_hardlinks;
+        self
+    }
+
+    /// Count every file with more than one hardlink only once on Windows as well. This opens
+    /// every file to read its file index, which is why Windows tools (and diskus by default)
+    /// count every hardlink there. Elsewhere, hardlinks are always detected.
+    pub fn dedup_hardlinks(mut self, dedup_hardlinks: bool) -> Walk {
+        self.dedup_hardlinks = dedup_hardlinks;
+        self
+    }
+
+    /// Only count the contents of directories, not the space that the directories themselves
+    /// take up (often 4 KiB each). The sizes differ slightly from `du` then. Directories are
+    /// still walked and counted in `Stats`.
+    pub fn files_only(mut self, files_only: bool) -> Walk {
+        self.files_only = files_only;
+        self
+    }
+
+    /// Add the size of the extended attributes of files (like resource forks and Finder info on
+    /// macOS) to their size, which costs additional system calls for every file. Files whose
+    /// attributes cannot be read are counted without them. Only on Linux and macOS, and not with
+    /// `FilesizeType::Inodes` or `FilesizeType::Blocks`.
+    pub fn include_xattrs(mut self, include_xattrs: bool) -> Walk {
+        self.include_xattrs = include_xattrs;
+        self
+    }
+
+    /// Choose which roots the entries that can be reached from several roots are counted for.
+    pub fn dedup(mut self, dedup: DedupScope) -> Walk {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Walk (and report) roots that are contained in another root. By default, these are skipped
+    /// so that nothing is counted twice, and reported as warnings by `run_and_print` and
+    /// `run_and_print_sorted`. Roots that are given more than once (like `dir`, `dir/` and
+    /// `./dir`) are always only walked once, and reported by their first spelling.
+    pub fn allow_overlap(mut self, allow_overlap: bool) -> Walk {
+        self.allow_overlap = allow_overlap;
+        self
+    }
+
+    /// Walk the targets of the roots that are symlinks, like `du -H`. Symlinks below the roots
+    /// are not followed, and the roots are still reported by the paths they were given as.
+    pub fn dereference_roots(mut self, dereference_roots: bool) -> Walk {
+        self.dereference_roots = dereference_roots;
+        self
+    }
+
+    /// The filters that decide about every entry below the roots: the options that exclude
+    /// entries, in this order, and then the closure of `Walk::filter`. Excluded entries are
+    /// pruned, so that the closure is not called for them.
+    fn filters(&self) -> Vec<EntryFilter> {
+        let mut filters = vec![];
+        if !self.exclude.is_empty() {
+            let exclude = self.exclude.clone();
+            filters.push(prune_if(move |entry, _, context| {
+                exclude.matches(entry, &context.root.walked)
+            }));
+        }
+        if self.skip_git_dirs {
+            filters.push(prune_if(|entry, metadata, _| {
+                metadata.is_dir() && entry.file_name() == Some(".git".as_ref())
+            }));
+        }
+        if self.skip_hidden {
+            filters.push(prune_if(|entry, metadata, _| is_hidden(entry, metadata)));
+        }
+        if self.gitignore {
+            filters.push(prune_if(|entry, metadata, context| {
+                context.parent.ignore.is_ignored(entry, metadata.is_dir())
+            }));
         }
+        if !self.prune.is_empty() {
+            let prune = self.prune.clone();
+            filters.push(prune_if(move |entry, metadata, context| {
+                let pruned =
+                    metadata.is_dir() && entry.file_name().is_some_and(|name| prune.contains(name));
+                if pruned {
+                    let _ = context.tx.send(Message::PrunedDir);
+                }
+                pruned
+            }));
+        }
+        filters.extend(self.filter.clone());
+        filters
     }
-}
 
-fn print_result<P: AsRef<Path>>(
-    path: P,
-    size: Option<u64>,
-    error: Option<Error>,
-    size_format: Option<&FileSizeOpts>,
-) {
-    if let Some(err) = error {
-        match err {
-            Error::NoMetadataForPath(path) => {
-                eprintln!(
-                    "{} could not retrieve metadata for path '{}'",
-                    "diskus:".red().bold(),
-                    path.to_string_lossy()
-                );
-            }
-            Error::CouldNotReadDir(path) => {
-                eprintln!(
-                    "{} could not read contents of directory '{}'",
-                    "diskus:".red().bold(),
-                    path.to_string_lossy()
-                );
-            }
+    fn settings(
+        &self,
+        progress: Option<Arc<StatusLine>>,
+        config: ExtrasConfig,
+    ) -> Settings<'static> {
+        self.counters.reset();
+        Settings {
+            filesize_type: self.filesize_type,
+            report_depth: self.report_depth,
+            max_depth: self.max_depth,
+            trace: self.trace.then(|| Trace::new(progress)),
+            counters: Arc::clone(&self.counters),
+            ignore_errors: self.ignore_errors.clone(),
+            both: config.both,
+            filters: self.filters(),
+            include: self.include.clone(),
+            extensions: self.extensions.clone(),
+            regex: self.regex.clone(),
+            regex_exclude: self.regex_exclude.clone(),
+            regex_full_path: self.regex_full_path,
+            max_file_size: self.max_file_size,
+            newer_than: self.newer_than,
+            older_than: self.older_than,
+            now: SystemTime::now(),
+            limit: self.limit,
+            visited: AtomicU64::new(0),
+            cancel: self.cancel.child(),
+            gitignore: self.gitignore,
+            skip_cache_dirs: self.skip_cache_dirs,
+            respect_ignore_markers: self.respect_ignore_markers,
+            one_file_system: self.one_file_system,
+            special_devices: if self.skip_special {
+                special_devices()
+            } else {
+                HashSet::new()
+            },
+            bind_mounts: if self.skip_bind_mounts {
+                let roots: Vec<PathBuf> = self
+                    .root_directories
+                    .iter()
+                    .map(|path| {
+                        if self.dereference_roots {
+                            dereference(path).unwrap_or_else(|_| path.clone())
+                        } else {
+                            path.clone()
+                        }
+                    })
+                    .collect();
+                bind_mounts(&roots)
+            } else {
+                HashMap::new()
+            },
+            skip_special_files: self.skip_special_files,
+            owner: self.owner,
+            follow_symlinks: self.follow_symlinks,
+            count_hardlinks: self.count_hardlinks,
+            dedup_hardlinks: self.dedup_hardlinks,
+            files_only: self.files_only,
+            include_xattrs: self.include_xattrs,
+            dereference_roots: self.dereference_roots,
+            entry_paths: config.entry_paths(),
+            owners: config.owners,
+            devices: config.devices,
+            visit: None,
         }
     }
 
-    if let Some(size) = size {
-        // if atty::is(atty::Stream::Stdout) {
-        //     println!(
-        //         "{: >10}    {}",
-        //         size.file_size(size_format).unwrap(),
-        //         path.as_ref().to_str().unwrap()
-        //     );
-        // } else {
-        //     println!("{}\t{:?}", size, path.as_ref().to_str().unwrap());
-        // }
+    /// Starts walking the roots on the thread pool of the walk, and returns the messages of the
+    /// walker threads. The receiver of the returned handle is left to the caller.
+    fn start(
+        &self,
+        roots: Vec<(PathBuf, PathBuf)>,
+        config: ExtrasConfig,
+    ) -> Result<(WalkHandle, channel::Receiver<Message>), WalkError> {
+        let (tx, rx) = channel::unbounded();
+
+        let pool = self.pool()?;
+        let progress = self.spawn_progress();
+        let settings = self.settings(
+            progress.as_ref().map(|(progress, _)| Arc::clone(progress)),
+            config,
+        );
+        let timeout = self.timeout;
+        #[cfg(feature = "async")]
+        let cancel = settings.cancel.clone();
+        let walker = thread::spawn(move || walk_in(&pool, tx, roots, &settings, timeout));
 
-        if let Some(size_format) = size_format {
-            safe_write(format!(
-                "{: >10}\t{}\n",
-                size.file_size(size_format).unwrap(),
-                path.as_ref().to_str().unwrap()
-            ));
-            // println!(
-            //     "{: >10}    {}",
-            //     size.file_size(size_format).unwrap(),
-            //     path.as_ref().to_str().unwrap()
-            // );
+        let handle = WalkHandle {
+            walker: Some(walker),
+            progress,
+            receiver: None,
+            #[cfg(feature = "async")]
+            cancel,
+        };
+        Ok((handle, rx))
+    }
+
+    fn spawn_progress(&self) -> Option<(Arc<StatusLine>, JoinHandle<()>)> {
+        if self.status_line {
+            StatusLine::spawn(Arc::clone(&self.counters))
         } else {
-            safe_write(format!("{}\t{}\n", size, path.as_ref().to_str().unwrap()));
-            // println!("{}\t{:?}", size, path.as_ref().to_str().unwrap());
+            None
         }
     }
-}
 
-pub enum Error {
-    NoMetadataForPath(PathBuf),
-    CouldNotReadDir(PathBuf),
-}
+    /// Report every root by its canonicalized, absolute path. Roots that resolve to the same
+    /// path are only walked (and reported) once.
+    pub fn absolute(mut self, absolute: bool) -> Walk {
+        self.absolute = absolute;
+        self
+    }
 
-enum Message {
-    SizeEntry(Option<UniqueID>, PathBuf, u64),
-    FinishedEntry(PathBuf),
-    Error { error: Error },
-}
+    /// Also report every directory up to the given depth below the roots (like `du -d`). This
+    /// does not limit how deep the walk goes, see `Walk::max_depth` for that.
+    pub fn report_depth(mut self, report_depth: Option<u64>) -> Walk {
+        self.report_depth = report_depth;
+        self
+    }
 
-fn root_walk(tx: channel::Sender<Message>, entries: Vec<PathBuf>, filesize_type: FilesizeType) {
-    entries.into_par_iter().for_each_with(tx, |tx_ref, entry| {
-        walk(
-            tx_ref.clone(),
-            &[entry.clone()],
-            entry.clone(),
-            0,
-            filesize_type,
-        );
-    })
-}
+    /// Do not walk the contents of directories at the given depth below the roots, which makes
+    /// all sizes an underestimate. The directories themselves are still counted, and so are all
+    /// entries above them (0 only counts the roots themselves).
+    pub fn max_depth(mut self, max_depth: Option<u64>) -> Walk {
+        self.max_depth = max_depth;
+        self
+    }
 
-fn walk(
-    tx: channel::Sender<Message>,
-    entries: &[PathBuf],
-    root: PathBuf,
-    depth: u64,
-    filesize_type: FilesizeType,
-) {
-    entries
-        .into_par_iter()
-        .for_each_with(tx.clone(), |tx_ref, entry| {
-            if let Ok(metadata) = entry.symlink_metadata() {
-                let unique_id = generate_unique_id(&metadata);
-
-                let size = filesize_type.size(&metadata);
-
-                tx_ref
-                    .send(Message::SizeEntry(unique_id, root.clone(), size))
-                    .unwrap();
-
-                if metadata.is_dir() {
-                    let mut children = vec![];
-                    match fs::read_dir(entry) {
-                        Ok(child_entries) => {
-                            for child_entry in child_entries.flatten() {
-                                children.push(child_entry.path());
-                            }
-                        }
-                        Err(_) => {
-                            tx_ref
-                                .send(Message::Error {
-                                    error: Error::CouldNotReadDir(entry.clone()),
-                                })
-                                .unwrap();
-                        }
-                    }
+    /// The roots to walk, as pairs of the path to walk and the path it is reported as, and the
+    /// roots that are skipped because they are contained in another one (see
+    /// `Walk::allow_overlap`). Roots that are given more than once are only walked (and reported)
+    /// once, by their first spelling.
+    fn roots(&self) -> (Vec<(PathBuf, PathBuf)>, Vec<Overlap>) {
+        let (duplicates, overlaps): (Vec<Overlap>, Vec<Overlap>) = self
+            .overlaps()
+            .into_iter()
+            .partition(|overlap| overlap.duplicate);
+        let skipped: HashSet<usize> = duplicates
+            .iter()
+            .chain(&overlaps)
+            .map(|overlap| overlap.index)
+            .collect();
+        let root_directories = self
+            .root_directories
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !skipped.contains(index))
+            .map(|(_, path)| path);
 
-                    walk(
-                        tx_ref.clone(),
-                        &children[..],
-                        root.clone(),
-                        depth + 1,
-                        filesize_type,
-                    );
-                };
-            } else {
-                tx_ref
-                    .send(Message::Error {
-                        error: Error::NoMetadataForPath(entry.clone()),
-                    })
-                    .unwrap();
-            };
-        });
-    if depth == 0 {
-        tx.send(Message::FinishedEntry(root.clone())).unwrap();
+        if !self.absolute {
+            let roots = root_directories
+                .map(|path| (path.clone(), path.clone()))
+                .collect();
+            return (roots, overlaps);
+        }
+
+        let mut seen = HashSet::new();
+        let roots = root_directories
+            .filter_map(|path| {
+                let root = absolute_path(path);
+                seen.insert(root.clone()).then(|| (path.clone(), root))
+            })
+            .collect();
+        (roots, overlaps)
     }
-}
 
-pub struct Walk {
-    root_directories: Vec<PathBuf>,
-    num_threads: usize,
-    filesize_type: FilesizeType,
-}
+    /// Finds the roots that are given more than once, and (unless `Walk::allow_overlap` is set)
+    /// the roots that are contained in another root.
+    fn overlaps(&self) -> Vec<Overlap> {
+        let normalized: Vec<PathBuf> = self
+            .root_directories
+            .iter()
+            .map(|path| normalize(path))
+            .collect();
+        // Paths that cannot be resolved are only compared by their normalized spelling
+        let resolved: Vec<Option<PathBuf>> = self
+            .root_directories
+            .iter()
+            .map(|path| self.resolved_root(path))
+            .collect();
 
-impl Walk {
-    pub fn new(
-        root_directories: Vec<PathBuf>,
-        num_threads: usize,
-        filesize_type: FilesizeType,
-    ) -> Walk {
-        Walk {
-            root_directories,
-            num_threads,
-            filesize_type,
+        let mut overlaps = vec![];
+        for index in 0..self.root_directories.len() {
+            let path = &resolved[index];
+            let duplicate_of = (0..index).find(|&other| {
+                normalized[other] == normalized[index]
+                    || (path.is_some() && resolved[other] == *path)
+            });
+            let contained_in = || {
+                let path = path.as_ref()?;
+                resolved.iter().position(|other| {
+                    other
+                        .as_ref()
+                        .is_some_and(|other| path != other && path.starts_with(other))
+                })
+            };
+            let outer = match duplicate_of {
+                Some(outer) => Some(outer),
+                None if self.allow_overlap => None,
+                None => contained_in(),
+            };
+            if let Some(outer) = outer {
+                overlaps.push(Overlap {
+                    index,
+                    path: self.root_directories[index].clone(),
+                    outer: self.root_directories[outer].clone(),
+                    duplicate: duplicate_of.is_some(),
+                });
+            }
+        }
+        overlaps
+    }
+
+    /// The canonical path of everything that is walked for the given root, or `None` if it cannot
+    /// be resolved. Symlinks are only resolved if they are walked as their target.
+    fn resolved_root(&self, path: &Path) -> Option<PathBuf> {
+        let metadata = path.symlink_metadata().ok()?;
+        let dereferenced = self.dereference_roots || self.follow_symlinks;
+        if metadata.file_type().is_symlink() && !dereferenced {
+            let name = path.file_name()?;
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            Some(fs::canonicalize(parent).ok()?.join(name))
+        } else {
+            fs::canonicalize(path).ok()
         }
     }
 
-    pub fn run(&self) -> (Vec<(PathBuf, u64)>, Vec<Error>) {
+    /// Walks all roots and returns their sizes once they are all finished. This only fails if
+    /// the walk itself could not be run, errors while reading entries are part of the result.
+    pub fn run(&self) -> Result<WalkResult, WalkError> {
+        Ok(self.run_with_files(ExtrasConfig::default())?.0)
+    }
+
+    /// Like `run`, but also returns every entry that was counted, with files that are hardlinked
+    /// into several places only listed once.
+    ///
+    /// All entries are kept in memory until the walk is finished, which takes roughly as much
+    /// memory as the paths themselves for large trees. Use `run_streaming` to process the entries
+    /// as they are found instead.
+    pub fn run_detailed(&self) -> Result<(WalkResult, Vec<FileEntry>), WalkError> {
+        self.run_with_files(ExtrasConfig {
+            files: true,
+            ..ExtrasConfig::default()
+        })
+    }
+
+    fn run_with_files(
+        &self,
+        config: ExtrasConfig,
+    ) -> Result<(WalkResult, Vec<FileEntry>), WalkError> {
+        let collected = self.run_with_roots(config)?;
+        let result = WalkResult {
+            entries: collected.entries,
+            errors: collected
+                .errors
+                .into_iter()
+                .map(|(_root, err)| err)
+                .collect(),
+            stats: collected.stats,
+        };
+        Ok((result, collected.files))
+    }
+
+    /// The sizes of the roots as pairs, like `run` returned them before it returned a
+    /// `WalkResult`.
+    ///
+    /// # Panics
+    ///
+    /// Unlike `run`, this panics if the walk cannot be run (see `WalkError`).
+    #[deprecated(note = "use `run`, which returns a `WalkResult`")]
+    pub fn run_legacy(&self) -> (Vec<(PathBuf, u64)>, Vec<Error>, Stats) {
+        let result = self.run().expect("the walk could not be run");
+        let sizes = result
+            .entries
+            .into_iter()
+            .map(|entry| (entry.path, entry.size))
+            .collect();
+        (sizes, result.errors, result.stats)
+    }
+
+    /// Like `run`, but returns the results as a tuple.
+    ///
+    /// # Panics
+    ///
+    /// Unlike `run`, this panics if the walk cannot be run (see `WalkError`).
+    #[deprecated(note = "use `run`, which reports the counts as well")]
+    pub fn run_with_counts(&self) -> (Vec<RootEntry>, Vec<Error>, Stats) {
+        let result = self.run().expect("the walk could not be run");
+        (result.entries, result.errors, result.stats)
+    }
+
+    /// Like `run`, but reports both the apparent size and the disk usage of each root, which
+    /// tells sparse or compressed files apart. The size type of the walk is ignored.
+    pub fn run_both(&self) -> Result<(Vec<BothSizes>, Vec<Error>, Stats), WalkError> {
+        let collected = self.run_with_roots(ExtrasConfig {
+            both: true,
+            ..ExtrasConfig::default()
+        })?;
+        let sizes = collected
+            .entries
+            .into_iter()
+            .map(|entry| BothSizes {
+                apparent_size: entry.size,
+                disk_usage: entry.disk_usage.unwrap_or(0),
+                path: entry.path,
+            })
+            .collect();
+        Ok((
+            sizes,
+            collected
+                .errors
+                .into_iter()
+                .map(|(_root, err)| err)
+                .collect(),
+            collected.stats,
+        ))
+    }
+
+    /// Like `run`, but breaks the size of all roots down by the user ID of the owner of each
+    /// entry. Hardlinks are only attributed to the owner once.
+    #[cfg(unix)]
+    pub fn run_by_owner(&self) -> Result<(OwnerSizes, Vec<Error>, Stats), WalkError> {
+        let collected = self.run_with_roots(ExtrasConfig {
+            owners: true,
+            ..ExtrasConfig::default()
+        })?;
+        Ok((
+            collected.extras.owners,
+            collected
+                .errors
+                .into_iter()
+                .map(|(_root, err)| err)
+                .collect(),
+            collected.stats,
+        ))
+    }
+
+    /// Walks all roots and reports every entry to the given visitor, from the walker threads
+    /// themselves. This avoids the overhead of sending every entry to another thread, but
+    /// nothing is summed up except the total of every root.
+    ///
+    /// With `DedupScope::Global`, the roots are walked one after another (each of them still on
+    /// all threads), so that a file that is hardlinked into several roots is counted for the
+    /// first of them, like with the other methods.
+    pub fn run_with_visitor<V: WalkVisitor + Sync>(&self, visitor: &V) -> Result<Stats, WalkError> {
+        let (roots, _overlaps) = self.roots();
+        let visit = Visit {
+            visitor,
+            dedup: self.dedup,
+            seen: Mutex::new(HashSet::new()),
+            totals: roots
+                .iter()
+                .map(|(_, root)| (root.clone(), AtomicU64::new(0)))
+                .collect(),
+            files: AtomicU64::new(0),
+            dirs: AtomicU64::new(0),
+            symlinks: AtomicU64::new(0),
+            shared: AtomicU64::new(0),
+        };
+
         let (tx, rx) = channel::unbounded();
+        let pool = self.pool()?;
+        let progress = self.spawn_progress();
+        let settings = Settings {
+            visit: Some(&visit),
+            ..self.settings(
+                progress.as_ref().map(|(progress, _)| Arc::clone(progress)),
+                ExtrasConfig::default(),
+            )
+        };
 
-        let receiver_thread = thread::spawn(move || {
-            let mut ids = HashSet::new();
-            let mut sizes = HashMap::new();
-            let mut error_messages = vec![];
-            for msg in rx {
-                match msg {
-                    Message::SizeEntry(unique_id, root, size) => {
-                        if let Some(unique_id) = unique_id {
-                            // Only count this entry if the ID has not been seen
-                            if ids.insert(unique_id) {
-                                sizes
-                                    .entry(root)
-                                    .and_modify(|tot| *tot += size)
-                                    .or_insert(size);
-                            }
-                        } else {
-                            sizes
-                                .entry(root)
-                                .and_modify(|tot| *tot += size)
-                                .or_insert(size);
-                        }
-                    }
-                    Message::Error { error } => {
-                        error_messages.push(error);
-                    }
-                    Message::FinishedEntry(_path) => {}
+        // Only the statistics are left to the receiver thread
+        let stats = thread::scope(|scope| {
+            let receiver_thread = scope.spawn(|| {
+                let mut receiver = Receiver::new(self, ExtrasConfig::default(), vec![]);
+                receiver.receive(rx, None, |_, _| true);
+                receiver.totals.stats
+            });
+            // The visitor runs on this thread's pool, so a panic in it ends up here
+            let walked = panic::catch_unwind(AssertUnwindSafe(|| {
+                walk_in(&pool, tx, roots, &settings, self.timeout)
+            }));
+            let received = receiver_thread.join();
+            match (walked, received) {
+                (Ok(Ok((elapsed, cancelled, timed_out))), Ok(mut stats)) => {
+                    stats.elapsed = elapsed;
+                    stats.cancelled = cancelled;
+                    stats.timed_out = timed_out;
+                    Ok(stats)
                 }
+                _ => Err(WalkError::Panicked),
             }
-            let mut sizes_vec = vec![];
-            for (path, size) in sizes.into_iter() {
-                sizes_vec.push((path, size));
+        });
+
+        let mut panicked = false;
+        if let Some((progress, status_thread)) = progress {
+            progress.finish();
+            panicked = status_thread.join().is_err();
+        }
+        let mut stats = stats?;
+        if panicked {
+            return Err(WalkError::Panicked);
+        }
+        visit.add_stats(&mut stats);
+        Ok(stats)
+    }
+
+    /// Like `run`, but keeps track of the root each error belongs to. The roots are
+    /// returned in the order they were given in, each followed by its reported subdirectories.
+    fn run_with_roots(&self, config: ExtrasConfig) -> Result<Collected, WalkError> {
+        let (roots, overlaps) = self.roots();
+        let order: Vec<PathBuf> = roots.iter().map(|(_, root)| root.clone()).collect();
+
+        let (mut handle, rx) = self.start(roots, config)?;
+
+        let mut receiver = Receiver::new(self, config, order);
+        // The roots are finished in any order, each one with its subdirectories
+        let mut finished = vec![];
+        let mut error_messages = vec![];
+        let mut skipped_mount_points = vec![];
+        let mut undated_files = vec![];
+        let mut cache_dirs = vec![];
+        let mut unreadable_xattrs = vec![];
+        let mut devices = vec![];
+        receiver.receive(rx, None, |receiver, received| {
+            match received {
+                Received::Event(WalkEvent::RootFinished(entry)) => {
+                    let position = receiver.totals.position(&entry.path);
+                    let subdirs = receiver.totals.take_subdirs(&entry.path);
+                    finished.push((position, std::iter::once(entry).chain(subdirs)));
+                }
+                Received::Event(WalkEvent::Error { root, error }) => {
+                    error_messages.push((root, error));
+                }
+                Received::SkippedMountPoint(path) => skipped_mount_points.push(path),
+                Received::UndatedFile(path) => undated_files.push(path),
+                Received::CacheDir(path) => cache_dirs.push(path),
+                Received::UnreadableXattrs(path) => unreadable_xattrs.push(path),
+                Received::DeviceCapacity(path) => devices.push(path),
+                Received::Event(WalkEvent::Entry { .. })
+                | Received::Unreadable(_)
+                | Received::Finished
+                | Received::Idle => {}
             }
-            (sizes_vec, error_messages)
+            true
         });
+        finished.sort_by_key(|(position, _)| *position);
+        let entries = finished
+            .into_iter()
+            .flat_map(|(_, entries)| entries)
+            .collect();
+        let mut totals = receiver.totals;
+        let extras = totals.take_extras();
+        Ok(Collected {
+            entries,
+            errors: error_messages,
+            extras,
+            files: totals.files,
+            stats: handle.join(totals.stats)?,
+            skipped_mount_points,
+            undated_files,
+            cache_dirs,
+            unreadable_xattrs,
+            devices,
+            overlaps,
+        })
+    }
+
+    /// Starts the walk and returns right away. The events of the walk can be received while it
+    /// is running, and the channel is closed once it is finished.
+    ///
+    /// The roots are walked in parallel, so the events of different roots interleave. The
+    /// `WalkEvent::RootFinished` event of a root is sent as soon as it is finished, unless it
+    /// counted hardlinked files (or any entries with `Walk::follow_symlinks`) that one of the
+    /// roots before it may contain as well. Such entries are attributed in the order the roots
+    /// were given in (see `Walk::dedup`), so the root is held back until the roots before it are
+    /// finished. Roots that could not be read at all are only reported by their error.
+    pub fn run_streaming(&self) -> Result<(WalkHandle, channel::Receiver<WalkEvent>), WalkError> {
+        let (roots, _overlaps) = self.roots();
+        let order: Vec<PathBuf> = roots.iter().map(|(_, root)| root.clone()).collect();
+
+        let config = ExtrasConfig {
+            paths: true,
+            ..ExtrasConfig::default()
+        };
+        let (mut handle, rx) = self.start(roots, config)?;
+        let (tx, events) = channel::unbounded();
+
+        let mut receiver = Receiver::new(self, config, order);
+        handle.receiver = Some(thread::spawn(move || {
+            // Sending fails once the receiver is dropped, which stops the walk
+            receiver.receive(rx, None, |_, received| match received {
+                Received::Event(event) => tx.send(event).is_ok(),
+                _ => true,
+            });
+            receiver.totals.stats
+        }));
+
+        Ok((handle, events))
+    }
+}
 
-        let pool = rayon::ThreadPoolBuilder::new()
-            .num_threads(self.num_threads)
-            .build()
-            .unwrap();
-        pool.install(|| root_walk(tx, self.root_directories.clone(), self.filesize_type));
+#[cfg(feature = "async")]
+impl Walk {
+    /// Like `run_streaming`, but for async code: the events are received from a `Stream`, and
+    /// the result of the walk (which is the same as that of `run`) from a `Future`. The walk
+    /// still runs on the thread pool of the walk, so that it never blocks the async runtime.
+    pub fn run_async(&self) -> Result<(WalkStream, WalkFuture), WalkError> {
+        let (roots, _overlaps) = self.roots();
+        let order: Vec<PathBuf> = roots.iter().map(|(_, root)| root.clone()).collect();
+
+        let config = ExtrasConfig {
+            paths: true,
+            ..ExtrasConfig::default()
+        };
+        let (mut handle, rx) = self.start(roots, config)?;
+        let cancel = handle.cancel.clone();
+        let (tx, events) = mpsc::unbounded();
+        let (result_tx, result) = oneshot::channel();
+
+        let mut receiver = Receiver::new(self, config, order);
+        thread::spawn(move || {
+            // The roots are finished in any order, each one with its subdirectories
+            let mut finished = vec![];
+            let mut errors = vec![];
+            receiver.receive(rx, None, |receiver, received| {
+                let Received::Event(event) = received else {
+                    return true;
+                };
+                match &event {
+                    WalkEvent::RootFinished(entry) => {
+                        let position = receiver.totals.position(&entry.path);
+                        let subdirs = receiver.totals.take_subdirs(&entry.path);
+                        finished.push((position, std::iter::once(entry.clone()).chain(subdirs)));
+                    }
+                    WalkEvent::Error { error, .. } => errors.push(error.clone()),
+                    WalkEvent::Entry { .. } => {}
+                }
+                // The result is still collected once the stream is dropped, which cancels the
+                // walk instead
+                let _ = tx.unbounded_send(event);
+                true
+            });
+            drop(tx);
+            finished.sort_by_key(|(position, _)| *position);
+            let result = handle.join(receiver.totals.stats).map(|stats| WalkResult {
+                entries: finished
+                    .into_iter()
+                    .flat_map(|(_, entries)| entries)
+                    .collect(),
+                errors,
+                stats,
+            });
+            let _ = result_tx.send(result);
+        });
 
-        receiver_thread.join().unwrap()
+        let stream = WalkStream {
+            events,
+            cancel,
+            ended: false,
+        };
+        Ok((stream, WalkFuture { result }))
     }
+}
 
+#[cfg(feature = "cli")]
+impl Walk {
+    /// Walks all roots and prints the results once they are all finished.
     pub fn run_and_print_sorted(
         &self,
-        size_format: Option<FileSizeOpts>,
-        print_total: bool,
-        verbose: bool,
-    ) {
-        let (mut sizes, error_messages) = self.run();
-        sizes.sort_by(|(_p1, s1), (_p2, s2)| s1.cmp(s2));
+        options: PrintOptions,
+        sort_order: SortOrder,
+        reverse: bool,
+    ) -> Result<Stats, WalkError> {
+        let print_total = options.total;
+        let percent = options.percent || options.bar;
+        let output_format = options.output_format;
+        let top = options.top;
+        let min_size = options.min_size;
+        let config = ExtrasConfig::of(&options);
+        let mut error_reporter = ErrorReporter::new(&options);
+        // Before the walk, so that it is not run in vain
+        let mut formatter = formatter(options).map_err(WalkError::Output)?;
+
+        let Collected {
+            mut entries,
+            errors: error_messages,
+            stats,
+            extras,
+            skipped_mount_points,
+            undated_files,
+            cache_dirs,
+            unreadable_xattrs,
+            devices,
+            overlaps,
+            ..
+        } = self.run_with_roots(config)?;
+
+        for overlap in &overlaps {
+            error_reporter.overlapping_root(&overlap.path, &overlap.outer);
+        }
+        for path in &skipped_mount_points {
+            error_reporter.skipped_mount_point(path);
+        }
+        for path in &undated_files {
+            error_reporter.undated_file(path);
+        }
+        for path in &cache_dirs {
+            error_reporter.cache_dir(path);
+        }
+        for path in &unreadable_xattrs {
+            error_reporter.unreadable_xattrs(path);
+        }
+        for path in &devices {
+            error_reporter.device_capacity(path);
+        }
+        for (_root, err) in &error_messages {
+            error_reporter.error(err);
+        }
+        error_reporter.finish();
+
+        formatter.begin();
+
+        let mut root_errors: HashMap<PathBuf, Vec<Error>> = HashMap::new();
+        for (root, err) in error_messages {
+            formatter.error(&err);
+            root_errors.entry(root).or_default().push(err);
+        }
+
+        // Roots without any metadata are only reported by formats that include errors
+        if output_format == OutputFormat::Json {
+            for root in root_errors.keys() {
+                if !entries.iter().any(|entry| &entry.path == root) {
+                    entries.push(RootEntry::new(root.clone()));
+                }
+            }
+        }
 
-        if verbose {
-            for err in error_messages {
-                print_result("", None, Some(err), None);
+        // The total always includes everything, even the entries that are not shown
+        let total = sum_entries(entries.iter().filter(|entry| entry.depth == 0));
+
+        let mut omitted = vec![];
+        if let Some(min_size) = min_size {
+            let (shown, hidden) = entries
+                .into_iter()
+                .partition(|entry| entry.size >= min_size);
+            entries = shown;
+            omitted = hidden;
+        }
+        match top {
+            Some(top) if entries.len() > top => {
+                entries.sort_by(|a, b| SortOrder::Size.compare(b, a));
+                omitted.extend(entries.split_off(top));
             }
-        } else if !error_messages.is_empty() {
-            eprintln!(
-                "{} the results may be tainted. Re-run with -v/--verbose to print all errors.",
-                "[diskus warning]".red().bold()
-            );
+            _ => {}
+        }
+
+        if reverse {
+            entries.sort_by(|a, b| sort_order.compare(b, a));
+        } else {
+            entries.sort_by(|a, b| sort_order.compare(a, b));
+        }
+
+        for entry in &entries {
+            let errors = root_errors.remove(&entry.path).unwrap_or_default();
+            formatter.entry(&Row {
+                entry,
+                errors: &errors,
+                percent: percent.then(|| Percent::of(entry.size, total.size)),
+            });
         }
 
-        let mut total_size = 0;
-        for (path, size) in sizes {
-            total_size += size;
-            print_result(path, Some(size), None, size_format.as_ref());
+        if !omitted.is_empty() {
+            formatter.omitted(omitted.len(), &sum_outermost(&omitted));
         }
 
         if print_total {
-            println!("\n{}", "Total:".cyan().bold());
-            print_result("", Some(total_size), None, size_format.as_ref());
+            formatter.total(&total);
         }
+        extras.print(&mut *formatter);
+        formatter.finish().map_err(WalkError::Output)?;
+
+        Ok(stats)
     }
 
-    pub fn run_and_print(&self, size_format: Option<FileSizeOpts>, total: bool, verbose: bool) {
-        let (tx, rx) = channel::unbounded();
+    /// Walks all roots and prints every root as soon as it is finished.
+    pub fn run_and_print(&self, options: PrintOptions) -> Result<Stats, WalkError> {
+        let print_total = options.total;
+        let percent = options.percent || options.bar;
+        let output_format = options.output_format;
+        let config = ExtrasConfig::of(&options);
+        let mut error_reporter = ErrorReporter::new(&options);
+        let live = options.live
+            && output_format == OutputFormat::Text
+            && options.output_file.is_none()
+            && atty::is(atty::Stream::Stdout);
 
-        let receiver_thread = thread::spawn(move || {
-            let mut ids = HashSet::new();
-            let mut sizes = HashMap::new();
-            let mut tainted_results = false;
-            for msg in rx {
-                match msg {
-                    Message::SizeEntry(unique_id, root, size) => {
-                        if let Some(unique_id) = unique_id {
-                            // Only count this entry if the ID has not been seen
-                            if ids.insert(unique_id) {
-                                sizes
-                                    .entry(root)
-                                    .and_modify(|tot| *tot += size)
-                                    .or_insert(size);
-                            }
-                        } else {
-                            sizes
-                                .entry(root)
-                                .and_modify(|tot| *tot += size)
-                                .or_insert(size);
+        let (roots, overlaps) = self.roots();
+        for overlap in &overlaps {
+            error_reporter.overlapping_root(&overlap.path, &overlap.outer);
+        }
+
+        let order: Vec<PathBuf> = roots.iter().map(|(_, root)| root.clone()).collect();
+
+        let mut formatter = formatter(options).map_err(WalkError::Output)?;
+        let (mut handle, rx) = self.start(roots, config)?;
+        let progress = handle
+            .progress
+            .as_ref()
+            .map(|(progress, _)| Arc::clone(progress));
+
+        let mut receiver = Receiver::new(self, config, order);
+        let mut root_errors: HashMap<PathBuf, Vec<Error>> = HashMap::new();
+        // Percentages are only known after all roots are finished, so the results have to be
+        // held back until then. The same holds for the status line and the live view, which
+        // would garble the output on a terminal. Held back roots are printed in the order they
+        // were given in.
+        let defer = percent || progress.is_some() || live;
+        let mut deferred = vec![];
+
+        if progress.is_none() {
+            formatter.begin();
+        }
+
+        // Once the results cannot be written anymore, the walk is stopped by no longer receiving
+        // its messages
+        let refresh = live.then_some(LIVE_REFRESH_INTERVAL);
+        receiver.receive(rx, refresh, |receiver, received| {
+            let finished = match received {
+                Received::Event(WalkEvent::RootFinished(entry)) => {
+                    let subdirs = receiver.totals.take_subdirs(&entry.path);
+                    Some((entry, subdirs))
+                }
+                // Roots without any metadata are only reported by formats that include errors
+                Received::Unreadable(path) if output_format == OutputFormat::Json => {
+                    Some((RootEntry::new(path), vec![]))
+                }
+                Received::Event(WalkEvent::Error { root, error }) => {
+                    suspend_progress(&progress, || {
+                        error_reporter.error(&error);
+                        formatter.error(&error);
+                        // Only the JSON output lists the errors of each root
+                        if output_format == OutputFormat::Json {
+                            root_errors.entry(root).or_default().push(error);
                         }
+                    });
+                    None
+                }
+                Received::SkippedMountPoint(path) => {
+                    suspend_progress(&progress, || error_reporter.skipped_mount_point(&path));
+                    None
+                }
+                Received::UndatedFile(path) => {
+                    suspend_progress(&progress, || error_reporter.undated_file(&path));
+                    None
+                }
+                Received::CacheDir(path) => {
+                    suspend_progress(&progress, || error_reporter.cache_dir(&path));
+                    None
+                }
+                Received::UnreadableXattrs(path) => {
+                    suspend_progress(&progress, || error_reporter.unreadable_xattrs(&path));
+                    None
+                }
+                Received::DeviceCapacity(path) => {
+                    suspend_progress(&progress, || error_reporter.device_capacity(&path));
+                    None
+                }
+                Received::Finished | Received::Idle => {
+                    if live {
+                        draw_live(&mut *formatter, receiver);
                     }
-                    Message::Error { error } => {
-                        if verbose {
-                            print_result("", None, Some(error), size_format.as_ref())
-                        } else {
-                            tainted_results = true;
-                        }
+                    None
+                }
+                Received::Event(WalkEvent::Entry { .. }) | Received::Unreadable(_) => None,
+            };
+
+            if let Some((entry, subdirs)) = finished {
+                let errors = root_errors.remove(&entry.path).unwrap_or_default();
+                let position = receiver.totals.position(&entry.path);
+                let rows = std::iter::once((entry, errors))
+                    .chain(subdirs.into_iter().map(|subdir| (subdir, vec![])));
+                if defer {
+                    deferred.extend(rows.map(|row| (position, row)));
+                } else {
+                    for (entry, errors) in rows {
+                        formatter.entry(&Row {
+                            entry: &entry,
+                            errors: &errors,
+                            percent: None,
+                        });
                     }
-                    Message::FinishedEntry(path) => print_result(
-                        &path,
-                        sizes.get(&path).map(|s| s.to_owned()),
-                        None,
-                        size_format.as_ref(),
-                    ),
                 }
             }
+            !formatter.sink().failed()
+        });
+        let mut totals = receiver.totals;
 
-            if tainted_results {
-                eprintln!(
-                    "{} the results may be tainted. Re-run with -v/--verbose to print all errors.",
-                    "[diskus warning]".red().bold()
-                );
-            }
+        if live {
+            formatter.clear_live();
+        }
+        if let Some(progress) = progress {
+            progress.finish();
+            formatter.begin();
+        }
 
-            if total {
-                let total_size = sizes.values().sum();
-                println!("\n{}", "Total:".cyan().bold());
-                print_result("", Some(total_size), None, size_format.as_ref());
-            }
-        });
+        let total = totals.total();
+        deferred.sort_by_key(|(position, _)| *position);
+        for (_, (entry, errors)) in deferred {
+            formatter.entry(&Row {
+                entry: &entry,
+                errors: &errors,
+                percent: percent.then(|| Percent::of(entry.size, total.size)),
+            });
+        }
+
+        error_reporter.finish();
+
+        if print_total {
+            formatter.total(&total);
+        }
+        totals.take_extras().print(&mut *formatter);
+        let written = formatter.finish();
+        let stats = handle.join(totals.stats)?;
+        written.map_err(WalkError::Output)?;
+        Ok(stats)
+    }
+}
 
-        let pool = rayon::ThreadPoolBuilder::new()
-            .num_threads(self.num_threads)
-            .build()
-            .unwrap();
-        pool.install(|| root_walk(tx, self.root_directories.clone(), self.filesize_type));
+#[cfg(feature = "cli")]
+fn draw_live(formatter: &mut dyn Formatter, receiver: &Receiver) {
+    let rows: Vec<LiveRow> = receiver
+        .order
+        .iter()
+        .map(|root| LiveRow {
+            path: root,
+            size: receiver
+                .totals
+                .entries
+                .get(root)
+                .map_or(0, |entry| entry.size),
+            finished: receiver.finished_roots.contains(root),
+        })
+        .collect();
+    formatter.live(&rows);
+}
 
-        receiver_thread.join().unwrap()
+/// Runs `f` while the status line (if any) is cleared.
+#[cfg(feature = "cli")]
+fn suspend_progress(progress: &Option<Arc<StatusLine>>, f: impl FnOnce()) {
+    match progress {
+        Some(progress) => progress.suspend(f),
+        None => f(),
     }
 }
@@ -0,0 +1,94 @@
+use std::io;
+use std::path::Path;
+
+/// The total size of the values of all extended attributes of a file (which includes resource
+/// forks and Finder info on macOS), without following symlinks. Files on filesystems without
+/// extended attributes have none.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn xattr_size(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+
+    // The list can grow between asking for its size and reading it
+    let mut names = vec![];
+    let len = loop {
+        match list(&path, &mut names) {
+            Ok(len) if names.is_empty() && len > 0 => names.resize(len, 0),
+            Ok(len) => break len,
+            Err(err) if err.raw_os_error() == Some(libc::ERANGE) => names.clear(),
+            Err(err) if err.raw_os_error() == Some(libc::ENOTSUP) => return Ok(0),
+            Err(err) => return Err(err),
+        }
+    };
+
+    let mut size = 0;
+    for name in names[..len].split(|&byte| byte == 0) {
+        if name.is_empty() {
+            continue;
+        }
+        let name = CString::new(name)?;
+        match value_size(&path, &name) {
+            Ok(len) => size += len as u64,
+            // The attribute was removed in the meantime
+            Err(err) if err.raw_os_error() == Some(NO_ATTRIBUTE) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn xattr_size(_path: &Path) -> io::Result<u64> {
+    Ok(0)
+}
+
+#[cfg(target_os = "linux")]
+const NO_ATTRIBUTE: i32 = libc::ENODATA;
+
+#[cfg(target_os = "macos")]
+const NO_ATTRIBUTE: i32 = libc::ENOATTR;
+
+/// Reads the NUL-separated names of the extended attributes into `buffer`, or returns the size of
+/// the list if `buffer` is empty.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn list(path: &std::ffi::CStr, buffer: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: `path` is NUL-terminated, and at most `buffer.len()` bytes are written to `buffer`
+    #[cfg(target_os = "linux")]
+    let len = unsafe { libc::llistxattr(path.as_ptr(), buffer.as_mut_ptr().cast(), buffer.len()) };
+    // SAFETY: as above
+    #[cfg(target_os = "macos")]
+    let len = unsafe {
+        libc::listxattr(
+            path.as_ptr(),
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+            libc::XATTR_NOFOLLOW,
+        )
+    };
+    usize::try_from(len).map_err(|_| io::Error::last_os_error())
+}
+
+/// The size of the value of the given extended attribute.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn value_size(path: &std::ffi::CStr, name: &std::ffi::CStr) -> io::Result<usize> {
+    use std::ptr;
+
+    // SAFETY: both strings are NUL-terminated, and nothing is written for a null buffer
+    #[cfg(target_os = "linux")]
+    let len = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), ptr::null_mut(), 0) };
+    // SAFETY: as above
+    #[cfg(target_os = "macos")]
+    let len = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            ptr::null_mut(),
+            0,
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    };
+    usize::try_from(len).map_err(|_| io::Error::last_os_error())
+}
@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use futures_core::Stream;
+use tempdir::TempDir;
+
+use diskus::{CancelToken, FilesizeType, FilterDecision, Walk, WalkEvent, WalkStream};
+
+async fn next(stream: &mut WalkStream) -> Option<WalkEvent> {
+    future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+}
+
+#[tokio::test]
+async fn events_are_streamed() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let roots = vec![tmp_dir.path().join("a"), tmp_dir.path().join("b")];
+    for (root, size) in roots.iter().zip([100, 200]) {
+        fs::create_dir(root)?;
+        File::create(root.join("file"))?.write_all(&vec![0u8; size])?;
+    }
+    let missing = tmp_dir.path().join("missing");
+
+    let mut paths = roots.clone();
+    paths.push(missing.clone());
+    let walk = Walk::new(paths, 2, FilesizeType::ApparentSize);
+    let (mut stream, result) = walk.run_async()?;
+    let mut files = vec![];
+    let mut finished = vec![];
+    let mut errors = vec![];
+    while let Some(event) = next(&mut stream).await {
+        match event {
+            WalkEvent::Entry { path, size, .. } if path.ends_with("file") => {
+                files.push((path, size));
+            }
+            WalkEvent::Entry { .. } => {}
+            WalkEvent::RootFinished(entry) => finished.push(entry.path),
+            WalkEvent::Error { root, .. } => errors.push(root),
+        }
+    }
+    let result = result.await?;
+
+    files.sort();
+    finished.sort();
+    assert_eq!(
+        files,
+        [(roots[0].join("file"), 100), (roots[1].join("file"), 200)]
+    );
+    assert_eq!(finished, roots);
+    assert_eq!(errors, [missing]);
+    // The result is the same as that of `Walk::run`
+    let entries: Vec<&PathBuf> = result.entries.iter().map(|entry| &entry.path).collect();
+    assert_eq!(entries, [&roots[0], &roots[1]]);
+    assert_eq!(result.errors.len(), 1);
+    assert_eq!(result.stats.files, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn dropping_the_stream_cancels_the_walk() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    for file in 0..20 {
+        File::create(tmp_dir.path().join(file.to_string()))?;
+    }
+
+    // The walk only goes on once the stream is dropped
+    let (dropped, wait_for_drop) = mpsc::channel::<()>();
+    let wait_for_drop = Mutex::new(Some(wait_for_drop));
+    let cancel = CancelToken::new();
+    let walk = Walk::new(vec![tmp_dir.path()], 1, FilesizeType::Inodes)
+        .cancel_token(cancel.clone())
+        .filter(move |_path: &Path, _metadata: &fs::Metadata| {
+            if let Some(wait_for_drop) = wait_for_drop.lock().unwrap().take() {
+                let _ = wait_for_drop.recv_timeout(Duration::from_secs(10));
+            }
+            FilterDecision::Count
+        });
+    let (stream, result) = walk.run_async()?;
+    drop(stream);
+    dropped.send(())?;
+    let result = result.await?;
+
+    assert!(result.stats.cancelled);
+    // Only that run was cancelled
+    assert!(!cancel.is_cancelled());
+    assert!(result.stats.files < 20);
+
+    Ok(())
+}
@@ -0,0 +1,966 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+use tempdir::TempDir;
+
+fn diskus(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .expect("Failed to run diskus");
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).expect("Output should be valid UTF-8")
+}
+
+fn create_file(path: &Path, size: usize) -> Result<(), Box<dyn Error>> {
+    File::create(path)?.write_all(&vec![0u8; size])?;
+    Ok(())
+}
+
+/// Creates three directories 'small', 'medium' and 'large' containing one file each.
+fn create_sized_tree(root: &Path) -> Result<(), Box<dyn Error>> {
+    for (name, size) in [("medium", 200), ("large", 300), ("small", 100)] {
+        fs::create_dir(root.join(name))?;
+        create_file(&root.join(name).join("file"), size)?;
+    }
+    Ok(())
+}
+
+fn printed_paths(output: &str) -> Vec<&str> {
+    output
+        .lines()
+        .map(|line| line.split('\t').nth(1).expect("Lines should contain a tab"))
+        .collect()
+}
+
+/// The lines of the output in sorted order, as the roots are printed in the order they finish
+/// in (unless they share hardlinks with the roots before them).
+fn sorted_lines(output: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = output.lines().collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn sort_by_size_reverse() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let args = [
+        "--apparent-size",
+        "--sort",
+        "size",
+        "small",
+        "large",
+        "medium",
+    ];
+    let output = diskus(tmp_dir.path(), &args);
+    assert_eq!(printed_paths(&output), ["small", "medium", "large"]);
+
+    let output = diskus(tmp_dir.path(), &[&args[..], &["--reverse"]].concat());
+    assert_eq!(printed_paths(&output), ["large", "medium", "small"]);
+
+    Ok(())
+}
+
+#[test]
+fn sort_by_name_reverse() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let args = ["--sort", "name", "small", "large", "medium"];
+    let output = diskus(tmp_dir.path(), &args);
+    assert_eq!(printed_paths(&output), ["large", "medium", "small"]);
+
+    let output = diskus(tmp_dir.path(), &[&args[..], &["-r"]].concat());
+    assert_eq!(printed_paths(&output), ["small", "medium", "large"]);
+
+    Ok(())
+}
+
+#[test]
+fn absolute_paths_are_deduplicated() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let output = diskus(tmp_dir.path(), &["--absolute", "./small", "small"]);
+    let small = fs::canonicalize(tmp_dir.path().join("small"))?;
+    assert_eq!(printed_paths(&output), [small.to_str().unwrap()]);
+
+    Ok(())
+}
+
+#[test]
+fn top_entries() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let args = ["--bytes", "--apparent-size", "--top", "2", "--total"];
+    let output = diskus(
+        tmp_dir.path(),
+        &[&args[..], &["small", "large", "medium"]].concat(),
+    );
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0].split('\t').nth(1), Some("large"));
+    assert_eq!(lines[1].split('\t').nth(1), Some("medium"));
+    assert!(lines[2].contains("1 smaller entry"));
+    // The total includes the omitted entries
+    assert!(lines[3].ends_with("\ttotal"));
+
+    Ok(())
+}
+
+#[test]
+fn output_file() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let output = diskus(
+        tmp_dir.path(),
+        &["--output", "csv", "--output-file", "report.csv", "small"],
+    );
+    assert!(output.is_empty());
+
+    let report = fs::read_to_string(tmp_dir.path().join("report.csv"))?;
+    assert!(report.starts_with("path,size_bytes,size_human\nsmall,"));
+    // The temporary file has been renamed
+    assert_eq!(fs::read_dir(tmp_dir.path())?.count(), 4);
+
+    Ok(())
+}
+
+#[test]
+fn quiet_mode_sets_exit_status() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["--quiet", "does-not-exist"])
+        .output()?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stderr.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn apparent_size() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_file(&tmp_dir.path().join("file"), 100)?;
+
+    assert_eq!(diskus(tmp_dir.path(), &["-b", "file"]), "100\tfile\n");
+
+    Ok(())
+}
+
+#[test]
+fn plain_output_if_not_a_terminal() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let output = diskus(tmp_dir.path(), &["--apparent-size", "small/file"]);
+    assert_eq!(output, "100\tsmall/file\n");
+
+    let output = diskus(
+        tmp_dir.path(),
+        &["--apparent-size", "--pretty", "small/file"],
+    );
+    assert_eq!(output, "     100 B\tsmall/file\n");
+
+    // An explicit size format is still humanized, but not padded
+    let args = ["--apparent-size", "--size-format", "decimal", "small/file"];
+    assert_eq!(diskus(tmp_dir.path(), &args), "100 B\tsmall/file\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .args(["--pretty", "--plain"])
+        .output()?;
+    assert!(!output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn summarized_errors() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let missing: Vec<String> = (0..5).map(|i| format!("missing-{}", i)).collect();
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .arg("--summarize-errors")
+        .args(&missing)
+        .output()?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert_eq!(stderr.lines().count(), 4);
+    assert!(stderr.ends_with("… and 2 more paths without metadata\n"));
+
+    Ok(())
+}
+
+#[test]
+fn equal_sizes_are_sorted_deterministically() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    for name in ["d", "b", "a", "c"] {
+        fs::create_dir(tmp_dir.path().join(name))?;
+        create_file(&tmp_dir.path().join(name).join("file"), 100)?;
+    }
+
+    let args = ["--apparent-size", "--sort", "size", "d", "b", "a", "c"];
+    for _ in 0..10 {
+        let output = diskus(tmp_dir.path(), &args);
+        assert_eq!(printed_paths(&output), ["a", "b", "c", "d"]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn fixed_unit() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_file(&tmp_dir.path().join("file"), 2500)?;
+
+    let args = ["--apparent-size", "--sort", "size", "--total", "file"];
+    let output = diskus(tmp_dir.path(), &[&args[..], &["--unit", "KB"]].concat());
+    assert_eq!(output, "2.50 KB\tfile\n2.50 KB\ttotal\n");
+
+    let args = [&args[..], &["--unit", "gb", "--precision", "1"]].concat();
+    let output = diskus(tmp_dir.path(), &args);
+    assert_eq!(output, "0.0 GB\tfile\n0.0 GB\ttotal\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn classify() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+    std::os::unix::fs::symlink("small", tmp_dir.path().join("link"))?;
+
+    let args = [
+        "--classify",
+        "--sort",
+        "name",
+        "small",
+        "large/file",
+        "link",
+    ];
+    let output = diskus(tmp_dir.path(), &args);
+    assert_eq!(printed_paths(&output), ["large/file", "link@", "small/"]);
+
+    // Without --sort, every root is printed as soon as it is finished
+    let output = diskus(tmp_dir.path(), &["-F", "small", "large/file", "link"]);
+    let mut paths = printed_paths(&output);
+    paths.sort();
+    assert_eq!(paths, ["large/file", "link@", "small/"]);
+
+    Ok(())
+}
+
+#[test]
+fn errors_as_json() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["--errors-json", "missing"])
+        .output()?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert_eq!(
+        stderr,
+        "{\"kind\": \"no_metadata_for_root\", \"path\": \"missing\"}\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn biggest_files() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+    // Hardlinks of the same file are only listed once
+    fs::hard_link(
+        tmp_dir.path().join("large").join("file"),
+        tmp_dir.path().join("small").join("link"),
+    )?;
+
+    let args = [
+        "--apparent-size",
+        "--biggest-files",
+        "2",
+        "large",
+        "medium",
+        "small",
+    ];
+    let output = diskus(tmp_dir.path(), &args);
+    let files: Vec<&str> = output
+        .split("\nBiggest files:\n")
+        .nth(1)
+        .expect("Output should list the biggest files")
+        .lines()
+        .collect();
+    assert_eq!(files.len(), 2);
+    assert!(files[0].starts_with("300\t"));
+    assert_eq!(files[1], "200\tmedium/file");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn exit_status_if_errors_occurred() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+    let status = |args: &[&str]| -> Result<Option<i32>, Box<dyn Error>> {
+        let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+            .current_dir(tmp_dir.path())
+            .args(args)
+            .output()?;
+        Ok(output.status.code())
+    };
+
+    assert_eq!(status(&["small"])?, Some(0));
+
+    let locked = tmp_dir.path().join("locked");
+    fs::create_dir(&locked)?;
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000))?;
+    // Permissions are not enforced for root
+    if fs::read_dir(&locked).is_err() {
+        assert_eq!(status(&["locked"])?, Some(1));
+        assert_eq!(status(&["--no-fail-on-error", "locked"])?, Some(0));
+    }
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))?;
+
+    // Paths that do not exist at all are a hard failure
+    assert_eq!(status(&["small", "missing"])?, Some(2));
+    assert_eq!(
+        status(&["--no-fail-on-error", "small", "missing"])?,
+        Some(2)
+    );
+
+    assert_eq!(status(&["--depth", "none"])?, Some(64));
+    assert_eq!(status(&["--help"])?, Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn trace_directories() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["-vv", "small", "large"])
+        .output()?;
+    let stderr = String::from_utf8(output.stderr)?;
+    let mut entered: Vec<&str> = stderr
+        .lines()
+        .map(|line| line.rsplit(" entering ").next().unwrap())
+        .collect();
+    entered.sort();
+    assert_eq!(entered, ["'large'", "'small'"]);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn both_sizes() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    File::create(tmp_dir.path().join("sparse"))?.set_len(10_000_000)?;
+
+    let output = diskus(tmp_dir.path(), &["--both", "--total", "sparse"]);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 2);
+
+    let columns: Vec<&str> = lines[0].split('\t').collect();
+    assert_eq!(columns.len(), 3);
+    assert_eq!(columns[0], "10000000");
+    assert!(columns[1].parse::<u64>()? < 10_000_000);
+    assert_eq!(columns[2], "sparse");
+    assert_eq!(lines[1], format!("10000000\t{}\ttotal", columns[1]));
+
+    Ok(())
+}
+
+#[test]
+fn header_row() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let output = diskus(
+        tmp_dir.path(),
+        &["--header", "--count", "--sort", "name", "large"],
+    );
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "SIZE\tFILES\tDIRS\tPATH");
+    assert!(lines[1].ends_with("\tlarge"));
+
+    // Machine formats have no header row
+    let output = diskus(tmp_dir.path(), &["--header", "--print0", "large"]);
+    assert!(!output.contains("PATH"));
+
+    Ok(())
+}
+
+#[test]
+fn exclude_patterns() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let args = [
+        "--inodes",
+        "--exclude",
+        "large",
+        "--exclude",
+        "medium/fi?e",
+        ".",
+    ];
+    let output = diskus(tmp_dir.path(), &args);
+    // Only '.', 'small', 'small/file' and 'medium' are left
+    assert_eq!(output, "4\t.\n");
+
+    Ok(())
+}
+
+#[test]
+fn exclude_patterns_from_file() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+    fs::write(
+        tmp_dir.path().join("excludes"),
+        "# build output\nlarge\n\nmedium/fi?e\n",
+    )?;
+
+    let args = [
+        "--inodes",
+        "--exclude-from",
+        "excludes",
+        "--exclude",
+        "excludes",
+        ".",
+    ];
+    let output = diskus(tmp_dir.path(), &args);
+    assert_eq!(output, "4\t.\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["--exclude-from", "does-not-exist"])
+        .output()?;
+    assert_eq!(output.status.code(), Some(64));
+    assert!(String::from_utf8(output.stderr)?.contains("could not read exclude file"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn one_file_system() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::MetadataExt;
+
+    // /dev/shm is a separate filesystem on most Linux systems
+    match (fs::metadata("/dev"), fs::metadata("/dev/shm")) {
+        (Ok(dev), Ok(shm)) if dev.dev() != shm.dev() => {}
+        _ => return Ok(()),
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .args(["-x", "-v", "--no-fail-on-error", "/dev"])
+        .output()?;
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("skipped mount point '/dev/shm'"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn block_counts() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_file(&tmp_dir.path().join("file"), 10_000)?;
+
+    let size = |args: &[&str]| -> u64 {
+        let output = diskus(tmp_dir.path(), args);
+        output.split('\t').next().unwrap().parse().unwrap()
+    };
+    let blocks = size(&["--blocks", "file"]);
+    assert!(blocks >= 20);
+    assert_eq!(blocks * 512, size(&["file"]));
+    assert_eq!(size(&["--size-type", "blocks", "file"]), blocks);
+    assert_eq!(size(&["--size-type", "apparent", "file"]), 10_000);
+    assert_eq!(size(&["--size-type", "inodes", "file"]), 1);
+
+    let output = diskus(tmp_dir.path(), &["--blocks", "--output", "json", "file"]);
+    assert!(output.contains(&format!("\"blocks\": {}", blocks)));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn dereference_roots() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::symlink;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+    symlink("large", tmp_dir.path().join("current"))?;
+    symlink("does-not-exist", tmp_dir.path().join("broken"))?;
+
+    assert_eq!(
+        diskus(tmp_dir.path(), &["--inodes", "current"]),
+        "1\tcurrent\n"
+    );
+    assert_eq!(
+        diskus(tmp_dir.path(), &["--inodes", "-H", "current"]),
+        "2\tcurrent\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["-H", "-v", "broken"])
+        .output()?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)?.contains("could not retrieve metadata"));
+
+    Ok(())
+}
+
+#[test]
+fn min_size() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let args = [
+        "--apparent-size",
+        "--min-size",
+        "200",
+        "--total",
+        "small/file",
+        "medium/file",
+        "large/file",
+    ];
+    let output = diskus(tmp_dir.path(), &args);
+    assert_eq!(
+        output,
+        "300\tlarge/file\n200\tmedium/file\n… and 1 smaller entry (100)\n600\ttotal\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn max_file_size() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args([
+            "--apparent-size",
+            "--max-file-size",
+            "250",
+            "--biggest-files",
+            "3",
+            ".",
+        ])
+        .output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("medium/file"));
+    assert!(!stdout.contains("large/file"));
+    assert!(String::from_utf8(output.stderr)?.contains("skipped 1 file larger than"));
+
+    Ok(())
+}
+
+#[test]
+fn limit() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["--inodes", "--limit", "2", "."])
+        .output()?;
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8(output.stdout)?, "2\t.\n");
+    assert!(String::from_utf8(output.stderr)?.contains("scan truncated after 2 entries"));
+
+    Ok(())
+}
+
+#[test]
+fn timeout() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let run = |timeout: &str| {
+        Command::new(env!("CARGO_BIN_EXE_diskus"))
+            .current_dir(tmp_dir.path())
+            .args(["--inodes", "--timeout", timeout, "."])
+            .output()
+    };
+
+    let output = run("1h")?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, "7\t.\n");
+
+    let output = run("1")?;
+    assert_eq!(output.status.code(), Some(64));
+
+    Ok(())
+}
+
+#[test]
+fn empty_time_window() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["--newer-than", "30d", "--older-than", "1y"])
+        .output()?;
+    assert_eq!(output.status.code(), Some(64));
+    assert!(output.stdout.is_empty());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn special_files() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let status = Command::new("mkfifo")
+        .arg(tmp_dir.path().join("fifo"))
+        .status()?;
+    assert!(status.success());
+
+    assert_eq!(diskus(tmp_dir.path(), &["--inodes", "."]), "1\t.\n");
+    let output = diskus(tmp_dir.path(), &["--inodes", "--include-special", "."]);
+    assert_eq!(output, "2\t.\n");
+
+    Ok(())
+}
+
+#[test]
+fn extensions() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_file(&tmp_dir.path().join("movie.mp4"), 100)?;
+    create_file(&tmp_dir.path().join("movie.MKV"), 20)?;
+    create_file(&tmp_dir.path().join("notes.txt"), 3)?;
+
+    let output = diskus(
+        tmp_dir.path(),
+        &["--apparent-size", "--ext", "mp4,.mkv", "."],
+    );
+    assert_eq!(output, "120\t.\n");
+
+    Ok(())
+}
+
+#[test]
+fn regex() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_file(&tmp_dir.path().join("core.1234"), 100)?;
+    create_file(&tmp_dir.path().join("core.1234.gz"), 20)?;
+
+    let output = diskus(
+        tmp_dir.path(),
+        &["--apparent-size", "--regex", r"^core\.\d+$", "."],
+    );
+    assert_eq!(output, "100\t.\n");
+
+    // Invalid patterns are reported before anything is walked
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["--regex", "core(", "."])
+        .output()?;
+    assert_eq!(output.status.code(), Some(64));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)?.contains("invalid regex 'core('"));
+
+    Ok(())
+}
+
+#[test]
+fn prune() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    fs::create_dir_all(tmp_dir.path().join("a").join("target"))?;
+    fs::create_dir_all(tmp_dir.path().join("b").join("target"))?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["--inodes", "--prune", "target", "."])
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, "3\t.\n");
+    assert!(String::from_utf8(output.stderr)?.contains("pruned 2 directories"));
+
+    Ok(())
+}
+
+#[test]
+fn ignore_markers() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+    File::create(tmp_dir.path().join("large").join(".diskusignore"))?;
+
+    assert_eq!(diskus(tmp_dir.path(), &["--inodes", "."]), "8\t.\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["--inodes", "--respect-ignore-markers", "."])
+        .output()?;
+    assert!(output.status.success());
+    // 'large' itself is still counted
+    assert_eq!(String::from_utf8(output.stdout)?, "6\t.\n");
+    assert!(String::from_utf8(output.stderr)?.contains("skipped the contents of 1 directory"));
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn special_filesystems_are_skipped() -> Result<(), Box<dyn Error>> {
+    // /dev/pts is a separate devpts filesystem on most Linux systems
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    if !mountinfo.contains(" /dev/pts rw") {
+        return Ok(());
+    }
+
+    let run = |args: &[&str]| -> Result<String, Box<dyn Error>> {
+        let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+            .args([&["-v", "--no-fail-on-error"], args, &["/dev"]].concat())
+            .output()?;
+        Ok(String::from_utf8(output.stderr)?)
+    };
+    // The root itself is walked although it is a devtmpfs
+    assert!(run(&[])?.contains("skipped mount point '/dev/pts'"));
+    assert!(!run(&["--no-skip-special"])?.contains("skipped mount point"));
+    assert!(!run(&["--no-skip-special-fs"])?.contains("skipped mount point"));
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn block_device_roots() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let device = fs::read_dir("/dev")?
+        .flatten()
+        .find(|entry| entry.file_type().is_ok_and(|kind| kind.is_block_device()));
+    let Some(device) = device else {
+        return Ok(());
+    };
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .args(["-v".as_ref(), device.path().as_os_str()])
+        .output()?;
+    let stderr = String::from_utf8(output.stderr)?;
+    // Either the capacity is reported as such, or the device cannot be opened
+    if output.status.success() {
+        assert!(stderr.contains("is the capacity of the device"));
+    } else {
+        assert_eq!(output.status.code(), Some(2));
+        assert!(stderr.contains("could not open block device"));
+        assert!(output.stdout.is_empty());
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn dedup_scope() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    fs::create_dir(tmp_dir.path().join("a"))?;
+    fs::create_dir(tmp_dir.path().join("b"))?;
+    create_file(&tmp_dir.path().join("a").join("file"), 100)?;
+    fs::hard_link(
+        tmp_dir.path().join("a").join("file"),
+        tmp_dir.path().join("b").join("file"),
+    )?;
+
+    let output = diskus(tmp_dir.path(), &["--inodes", "b", "a"]);
+    assert_eq!(output, "2\tb\n1\ta\n");
+    let output = diskus(
+        tmp_dir.path(),
+        &["--inodes", "--dedup", "per-root", "b", "a"],
+    );
+    assert_eq!(sorted_lines(&output), ["2\ta", "2\tb"]);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn overlapping_roots() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    fs::create_dir_all(tmp_dir.path().join("data").join("projects"))?;
+    std::os::unix::fs::symlink("data/projects", tmp_dir.path().join("link"))?;
+
+    let run = |args: &[&str]| -> Result<(String, String), Box<dyn Error>> {
+        let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+            .current_dir(tmp_dir.path())
+            .args(args)
+            .output()?;
+        assert!(output.status.success());
+        Ok((
+            String::from_utf8(output.stdout)?,
+            String::from_utf8(output.stderr)?,
+        ))
+    };
+
+    let (stdout, stderr) = run(&["--inodes", "data/", "data/projects"])?;
+    assert_eq!(stdout, "2\tdata/\n");
+    assert!(stderr.contains("'data/projects' is already contained in 'data/'"));
+
+    let (stdout, _stderr) = run(&["--inodes", "--allow-overlap", "data", "data/projects"])?;
+    assert_eq!(sorted_lines(&stdout), ["1\tdata/projects", "2\tdata"]);
+
+    // Symlinks only overlap with their target if they are dereferenced
+    let (stdout, _stderr) = run(&["--inodes", "data", "link"])?;
+    assert_eq!(sorted_lines(&stdout), ["1\tlink", "2\tdata"]);
+    let (stdout, _stderr) = run(&["--inodes", "-H", "data", "link"])?;
+    assert_eq!(stdout, "2\tdata\n");
+
+    // Paths that are given twice are always collapsed, without a warning
+    let (stdout, stderr) = run(&["--inodes", "--allow-overlap", "data", "data/", "./data"])?;
+    assert_eq!(stdout, "2\tdata\n");
+    assert_eq!(stderr, "");
+
+    Ok(())
+}
+
+#[test]
+fn files_from() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+    fs::create_dir(tmp_dir.path().join(" padded "))?;
+    fs::write(tmp_dir.path().join("paths"), "small\n\n padded \nmedium\n")?;
+
+    let output = diskus(tmp_dir.path(), &["--inodes", "--files-from", "paths"]);
+    assert_eq!(
+        sorted_lines(&output),
+        ["1\t padded ", "2\tmedium", "2\tsmall"]
+    );
+
+    // The listed paths are added to the ones on the command line
+    let output = diskus(
+        tmp_dir.path(),
+        &["--inodes", "--files-from", "paths", "large"],
+    );
+    let mut paths = printed_paths(&output);
+    paths.sort();
+    assert_eq!(paths, [" padded ", "large", "medium", "small"]);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["--inodes", "--files-from", "-"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(b"large\n")?;
+    let output = child.wait_with_output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, "2\tlarge\n");
+
+    fs::write(tmp_dir.path().join("empty"), "\n\n")?;
+    for list in ["empty", "does-not-exist"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+            .current_dir(tmp_dir.path())
+            .args(["--files-from", list])
+            .output()?;
+        assert_eq!(output.status.code(), Some(64));
+        assert!(output.stdout.is_empty());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn files0_from() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+    fs::create_dir(tmp_dir.path().join("two\nlines"))?;
+    fs::write(tmp_dir.path().join("paths"), "small\0two\nlines\0\0")?;
+
+    let output = diskus(
+        tmp_dir.path(),
+        &[
+            "--inodes",
+            "--sort",
+            "name",
+            "--files0-from",
+            "paths",
+            "large",
+        ],
+    );
+    assert_eq!(output, "2\tlarge\n2\tsmall\n1\ttwo\nlines\n");
+
+    fs::write(tmp_dir.path().join("empty"), "")?;
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["--files0-from", "empty"])
+        .output()?;
+    assert_eq!(output.status.code(), Some(64));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)?.contains("does not contain any paths"));
+
+    Ok(())
+}
+
+#[cfg(windows)]
+#[test]
+fn wildcards_are_expanded() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    create_sized_tree(tmp_dir.path())?;
+
+    let output = diskus(
+        tmp_dir.path(),
+        &["--inodes", "--sort", "name", "*e*", "S?ALL\\file"],
+    );
+    assert_eq!(printed_paths(&output), ["large", "medium", "small\\file"]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_diskus"))
+        .current_dir(tmp_dir.path())
+        .args(["nothing*"])
+        .output()?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)?.contains("no paths match the pattern 'nothing*'"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn ignore_errors() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::symlink;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    fs::create_dir(tmp_dir.path().join("dir"))?;
+    symlink(".", tmp_dir.path().join("dir").join("loop"))?;
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_diskus"))
+            .current_dir(tmp_dir.path())
+            .args(args)
+            .output()
+    };
+
+    let output = run(&["-L", "--ignore-errors", "*/other", "dir"])?;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!output.stderr.is_empty());
+
+    let output = run(&["-L", "--ignore-errors", "*/loop", "dir"])?;
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stderr.is_empty());
+
+    Ok(())
+}
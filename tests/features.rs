@@ -0,0 +1,26 @@
+use std::path::Path;
+use std::process::Command;
+
+/// The library has to build without the printing dependencies of the `cli` feature.
+#[test]
+fn builds_without_default_features() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(env!("CARGO"))
+        .args(["check", "--lib", "--no-default-features", "--quiet"])
+        .arg("--manifest-path")
+        .arg(manifest_dir.join("Cargo.toml"))
+        // A separate target directory, so that the build does not wait for the lock of the
+        // one that is running the tests
+        .env(
+            "CARGO_TARGET_DIR",
+            manifest_dir.join("target/no-default-features"),
+        )
+        .env("RUSTFLAGS", "-D warnings")
+        .output()
+        .expect("cargo should run");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
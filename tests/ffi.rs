@@ -0,0 +1,87 @@
+use std::error::Error;
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::ptr;
+use std::slice;
+
+use tempdir::TempDir;
+
+use diskus::ffi::{
+    diskus_walk_free, diskus_walk_new, diskus_walk_run, DiskusCallback, DiskusOptions, DiskusWalk,
+    DISKUS_APPARENT_SIZE, DISKUS_ERRORS, DISKUS_INVALID_ARGUMENT, DISKUS_OK,
+};
+
+type New =
+    unsafe extern "C" fn(*const *const c_char, usize, *const DiskusOptions) -> *mut DiskusWalk;
+type Run = unsafe extern "C" fn(*const DiskusWalk, Option<DiskusCallback>, *mut c_void) -> c_int;
+type Free = unsafe extern "C" fn(*mut DiskusWalk);
+
+/// Collects the calls into the `Vec` that `userdata` points to.
+extern "C" fn collect(
+    path: *const u8,
+    path_len: usize,
+    size: u64,
+    is_error: bool,
+    userdata: *mut c_void,
+) {
+    let reported = unsafe { &mut *(userdata as *mut Vec<(PathBuf, u64, bool)>) };
+    let path = unsafe { slice::from_raw_parts(path, path_len) };
+    let path = PathBuf::from(String::from_utf8(path.to_vec()).unwrap());
+    reported.push((path, size, is_error));
+}
+
+#[test]
+fn walks_through_the_c_interface() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    File::create(tmp_dir.path().join("file"))?.write_all(&[0u8; 100])?;
+    let missing = tmp_dir.path().join("missing");
+
+    // Called through the function pointers, like from another language
+    let new: New = diskus_walk_new;
+    let run: Run = diskus_walk_run;
+    let free: Free = diskus_walk_free;
+
+    let paths = [
+        CString::new(tmp_dir.path().join("file").to_str().unwrap())?,
+        CString::new(missing.to_str().unwrap())?,
+    ];
+    let path_ptrs: Vec<*const c_char> = paths.iter().map(|path| path.as_ptr()).collect();
+    let options = DiskusOptions {
+        threads: 1,
+        size_type: DISKUS_APPARENT_SIZE,
+        count_hardlinks: false,
+    };
+
+    let mut reported: Vec<(PathBuf, u64, bool)> = vec![];
+    unsafe {
+        let walk = new(path_ptrs.as_ptr(), 1, &options);
+        assert!(!walk.is_null());
+        let userdata = &mut reported as *mut _ as *mut c_void;
+        assert_eq!(run(walk, Some(collect), userdata), DISKUS_OK);
+        free(walk);
+
+        let walk = new(path_ptrs[1..].as_ptr(), 1, ptr::null());
+        assert!(!walk.is_null());
+        assert_eq!(run(walk, Some(collect), userdata), DISKUS_ERRORS);
+        assert_eq!(run(walk, None, userdata), DISKUS_INVALID_ARGUMENT);
+        free(walk);
+
+        assert!(new(ptr::null(), 0, ptr::null()).is_null());
+        assert_eq!(
+            run(ptr::null(), Some(collect), userdata),
+            DISKUS_INVALID_ARGUMENT
+        );
+        free(ptr::null_mut());
+    }
+
+    assert_eq!(
+        reported,
+        vec![
+            (tmp_dir.path().join("file"), 100, false),
+            (missing, 0, true),
+        ]
+    );
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+use tempdir::TempDir;
+
+use diskus::{FilesizeType, Walk, WalkResult};
+
+#[test]
+fn results_round_trip_through_json() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let root = tmp_dir.path().join("root");
+    fs::create_dir(&root)?;
+    File::create(root.join("file"))?.write_all(&[0u8; 100])?;
+    let missing = tmp_dir.path().join("missing");
+
+    let walk = Walk::new(vec![root, missing], 1, FilesizeType::ApparentSize);
+    let result = walk.run()?;
+    assert_eq!(result.errors.len(), 1);
+
+    let json = serde_json::to_string(&result)?;
+    let restored: WalkResult = serde_json::from_str(&json)?;
+    assert_eq!(serde_json::to_string(&restored)?, json);
+    assert_eq!(restored.entries[0].path, result.entries[0].path);
+    assert_eq!(restored.stats.elapsed, result.stats.elapsed);
+    // The `io::Error` is restored from its OS error code
+    match &restored.errors[0] {
+        diskus::Error::NoMetadataForRoot(_, err) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+        error => panic!("unexpected error: {:?}", error),
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn paths_that_are_not_utf8_round_trip() -> Result<(), Box<dyn Error>> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let root = tmp_dir.path().join(OsStr::from_bytes(b"not-\xffutf8"));
+    fs::create_dir(&root)?;
+
+    let result = Walk::new(vec![root.clone()], 1, FilesizeType::ApparentSize).run()?;
+    let json = serde_json::to_string(&result)?;
+    let restored: WalkResult = serde_json::from_str(&json)?;
+    assert_eq!(restored.entries[0].path, root);
+
+    Ok(())
+}
@@ -1,10 +1,73 @@
+use std::cell::{Cell, RefCell};
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use tempdir::TempDir;
 
-use diskus::{FilesizeType, Walk};
+use diskus::{
+    CancelToken, ConfigError, DedupScope, FileEntry, FilesizeType, FilterDecision, GlobSet,
+    LockedVisitor, Walk, WalkBuilder, WalkError, WalkEvent, WalkVisitor,
+};
+
+#[test]
+fn invalid_configurations_are_rejected() {
+    let build =
+        |paths: Vec<PathBuf>, threads| WalkBuilder::new(paths).threads(threads).build().err();
+    assert_eq!(build(vec![], 1), Some(ConfigError::NoPaths));
+    assert_eq!(
+        build(vec![PathBuf::from(".")], 0),
+        Some(ConfigError::NoThreads)
+    );
+    assert_eq!(build(vec![PathBuf::from(".")], 1), None);
+}
+
+#[test]
+fn roots_of_any_path_type() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let root = tmp_dir.path();
+    File::create(root.join("file"))?.write_all(&[0u8; 100])?;
+
+    let name = root.join("file").to_str().unwrap().to_string();
+    let result = Walk::new([name.as_str()], 1, FilesizeType::ApparentSize).run()?;
+    assert_eq!(result.entries[0].path, root.join("file"));
+    let result = Walk::new(vec![name], 1, FilesizeType::ApparentSize).run()?;
+    assert_eq!(result.entries[0].size, 100);
+    let other = TempDir::new("diskus-tests")?;
+    let result = Walk::new([root, other.path()], 1, FilesizeType::Inodes).run()?;
+    assert_eq!(result.entries.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn walks_share_a_thread_pool() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let first = tmp_dir.path().join("first");
+    let second = tmp_dir.path().join("second");
+    fs::create_dir(&first)?;
+    fs::create_dir(&second)?;
+    File::create(first.join("file"))?.write_all(&[0u8; 100])?;
+    File::create(second.join("file"))?.write_all(&[0u8; 50])?;
+
+    let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(2).build()?);
+    let size = |path: PathBuf| {
+        WalkBuilder::new(vec![path])
+            .threads(0)
+            .thread_pool(Arc::clone(&pool))
+            .filesize_type(FilesizeType::ApparentSize)
+            .build()
+            .map(|walk| walk.run().unwrap().entries[0].size)
+    };
+    let dir_size = |path: &Path| path.metadata().unwrap().len();
+    assert_eq!(size(first.clone()), Ok(dir_size(&first) + 100));
+    assert_eq!(size(second.clone()), Ok(dir_size(&second) + 50));
+
+    Ok(())
+}
 
 #[test]
 fn size_of_single_file() -> Result<(), Box<dyn Error>> {
@@ -15,12 +78,1536 @@ fn size_of_single_file() -> Result<(), Box<dyn Error>> {
 
     let num_threads = 1;
     let root_directories = &[file_path];
-    let walk = Walk::new(root_directories.to_vec(), num_threads, FilesizeType::ApparentSize);
-    let (sizes_in_bytes, errors) = walk.run();
-    let (_dir, size_in_bytes) = sizes_in_bytes.first().expect("Should not be empty");
+    let walk = Walk::new(
+        root_directories.to_vec(),
+        num_threads,
+        FilesizeType::ApparentSize,
+    );
+    let result = walk.run()?;
+    let entry = result.entries.first().expect("Should not be empty");
+
+    assert!(result.errors.is_empty());
+    assert_eq!(entry.size, 100);
+
+    Ok(())
+}
+
+#[test]
+fn results_are_streamed() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let roots = vec![tmp_dir.path().join("a"), tmp_dir.path().join("b")];
+    for (root, size) in roots.iter().zip([100, 200]) {
+        fs::create_dir(root)?;
+        File::create(root.join("file"))?.write_all(&vec![0u8; size])?;
+    }
+
+    let walk = Walk::new(roots.clone(), 2, FilesizeType::ApparentSize);
+    let (handle, events) = walk.run_streaming()?;
+    let mut sums = vec![0; roots.len()];
+    let mut finished = vec![];
+    for event in events {
+        match event {
+            WalkEvent::Entry { root, path, size } => {
+                assert!(path.starts_with(&root));
+                sums[roots.iter().position(|r| *r == root).unwrap()] += size;
+                if path.ends_with("file") {
+                    assert_eq!(size, if root == roots[0] { 100 } else { 200 });
+                }
+            }
+            WalkEvent::RootFinished(entry) => finished.push((entry.path, entry.size)),
+            WalkEvent::Error { error, .. } => panic!("unexpected error: {:?}", error),
+        }
+    }
+    let stats = handle.wait()?;
+
+    // The roots share no entries, so they are reported in the order they are finished in
+    finished.sort();
+    let expected: Vec<_> = roots.iter().cloned().zip(sums).collect();
+    assert_eq!(finished, expected);
+    assert_eq!(stats.files, 2);
+    assert_eq!(stats.dirs, 2);
+
+    Ok(())
+}
+
+#[test]
+fn roots_are_not_held_back_by_slow_roots() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let roots: Vec<PathBuf> = ["slow", "fast", "shared"]
+        .iter()
+        .map(|name| tmp_dir.path().join(name))
+        .collect();
+    for root in &roots {
+        fs::create_dir(root)?;
+    }
+    File::create(roots[0].join("file"))?.write_all(&[0u8; 100])?;
+    File::create(roots[1].join("file"))?.write_all(&[0u8; 200])?;
+    fs::hard_link(roots[0].join("file"), roots[2].join("link"))?;
+
+    // The slow root is only walked once the fast root is reported
+    let (fast_reported, wait_for_fast) = mpsc::channel::<()>();
+    let wait_for_fast = Mutex::new(wait_for_fast);
+    let slow = roots[0].clone();
+    let walk = Walk::new(roots.clone(), 3, FilesizeType::ApparentSize)
+        .dedup_hardlinks(true)
+        .filter(move |path: &Path, _metadata: &fs::Metadata| {
+            if path.starts_with(&slow) {
+                let _ = wait_for_fast
+                    .lock()
+                    .unwrap()
+                    .recv_timeout(Duration::from_secs(10));
+            }
+            FilterDecision::Count
+        });
+    let (handle, events) = walk.run_streaming()?;
+    let mut finished = vec![];
+    for event in events {
+        if let WalkEvent::RootFinished(entry) = event {
+            if entry.path == roots[1] {
+                fast_reported.send(())?;
+            }
+            finished.push(entry);
+        }
+    }
+    handle.wait()?;
+
+    let paths: Vec<&PathBuf> = finished.iter().map(|entry| &entry.path).collect();
+    // The root that shares a file with the slow root waits for it, so that the file is
+    // attributed to the slow root
+    assert_eq!(paths, [&roots[1], &roots[0], &roots[2]]);
+    assert_eq!(finished[1].files, 1);
+    assert_eq!(finished[2].files, 0);
+    assert_eq!(finished[2].shared, 100);
+
+    Ok(())
+}
+
+#[test]
+fn streams_can_be_dropped_early() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    for dir in 0..20 {
+        let dir = tmp_dir.path().join(dir.to_string());
+        fs::create_dir(&dir)?;
+        for file in 0..50 {
+            File::create(dir.join(file.to_string()))?;
+        }
+    }
+
+    let walk = Walk::new(vec![tmp_dir.path().to_path_buf()], 4, FilesizeType::Inodes);
+    let (handle, events) = walk.run_streaming()?;
+    assert_eq!(events.iter().take(10).count(), 10);
+    drop(events);
+    // The walk ends quietly instead of panicking in the walker threads
+    let stats = handle.wait()?;
+    assert!(stats.files <= 1000);
+
+    Ok(())
+}
+
+/// Not `Sync`, so it has to be wrapped in a `LockedVisitor`
+#[derive(Default)]
+struct Visited {
+    entries: RefCell<Vec<(PathBuf, u64)>>,
+    finished: RefCell<Vec<(PathBuf, u64)>>,
+}
+
+impl WalkVisitor for Visited {
+    fn entry(&self, _root: &Path, path: &Path, size: u64) {
+        self.entries.borrow_mut().push((path.to_path_buf(), size));
+    }
+
+    fn root_finished(&self, root: &Path, total: u64) {
+        self.finished.borrow_mut().push((root.to_path_buf(), total));
+    }
+}
+
+#[test]
+fn entries_are_visited() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    File::create(tmp_dir.path().join("file"))?.write_all(&[0u8; 100])?;
+    fs::hard_link(tmp_dir.path().join("file"), tmp_dir.path().join("link"))?;
+
+    let walk = Walk::new(vec![tmp_dir.path().to_path_buf()], 2, FilesizeType::Inodes);
+    let visitor = LockedVisitor::new(Visited::default());
+    let stats = walk.run_with_visitor(&visitor)?;
+    let visited = visitor.into_inner();
+
+    // The root and one of the hardlinks
+    assert_eq!(visited.entries.borrow().len(), 2);
+    assert_eq!(
+        *visited.finished.borrow(),
+        vec![(tmp_dir.path().to_path_buf(), 2)]
+    );
+    assert_eq!(stats.files, 1);
+    assert_eq!(stats.dirs, 1);
+    assert_eq!(stats.shared, 1);
+
+    Ok(())
+}
+
+#[test]
+fn visited_hardlinks_are_counted_for_the_first_root() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let roots = vec![tmp_dir.path().join("a"), tmp_dir.path().join("b")];
+    for root in &roots {
+        fs::create_dir(root)?;
+    }
+    File::create(roots[0].join("file"))?;
+    fs::hard_link(roots[0].join("file"), roots[1].join("link"))?;
+
+    // The second root would reach the file first if the roots were walked at the same time
+    let slow = roots[0].clone();
+    let walk = Walk::new(roots.clone(), 2, FilesizeType::Inodes).filter(
+        move |path: &Path, _metadata: &fs::Metadata| {
+            if path.starts_with(&slow) {
+                std::thread::sleep(Duration::from_millis(200));
+            }
+            FilterDecision::Count
+        },
+    );
+    let visitor = LockedVisitor::new(Visited::default());
+    let stats = walk.run_with_visitor(&visitor)?;
+    let visited = visitor.into_inner();
+
+    assert_eq!(
+        *visited.finished.borrow(),
+        [(roots[0].clone(), 2), (roots[1].clone(), 1)]
+    );
+    assert_eq!(stats.shared, 1);
+
+    Ok(())
+}
+
+#[test]
+fn panics_are_returned_as_errors() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    File::create(tmp_dir.path().join("file"))?;
+
+    let walk = Walk::new(vec![tmp_dir.path().to_path_buf()], 2, FilesizeType::Inodes)
+        .filter(|_path, _metadata| panic!("the filter panicked"));
+    assert!(matches!(walk.run(), Err(WalkError::Panicked)));
+
+    struct Panicking;
+    impl WalkVisitor for Panicking {
+        fn entry(&self, _root: &Path, _path: &Path, _size: u64) {
+            panic!("the visitor panicked");
+        }
+    }
+    let walk = Walk::new(vec![tmp_dir.path().to_path_buf()], 2, FilesizeType::Inodes);
+    assert!(matches!(
+        walk.run_with_visitor(&Panicking),
+        Err(WalkError::Panicked)
+    ));
+
+    // The lock of a visitor that panicked can still be taken
+    #[derive(Default)]
+    struct PanicsOnce(Cell<bool>);
+    impl WalkVisitor for PanicsOnce {
+        fn entry(&self, _root: &Path, _path: &Path, _size: u64) {
+            if !self.0.replace(true) {
+                panic!("the visitor panicked");
+            }
+        }
+    }
+    let visitor = LockedVisitor::new(PanicsOnce::default());
+    assert!(walk.run_with_visitor(&visitor).is_err());
+    assert!(walk.run_with_visitor(&visitor).is_ok());
+    assert!(visitor.into_inner().0.get());
+
+    Ok(())
+}
+
+#[test]
+fn progress_follows_the_walk() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    for dir in 0..10 {
+        let dir = tmp_dir.path().join(dir.to_string());
+        fs::create_dir(&dir)?;
+        for file in 0..10 {
+            File::create(dir.join(file.to_string()))?.write_all(&[0u8; 10])?;
+        }
+    }
+
+    let walk = Walk::new(
+        vec![tmp_dir.path().to_path_buf(), tmp_dir.path().join("missing")],
+        2,
+        FilesizeType::ApparentSize,
+    );
+    let counters = walk.progress();
+    let done = CancelToken::new();
+    let sampler = std::thread::spawn({
+        let counters = counters.clone();
+        let done = done.clone();
+        move || {
+            let mut samples = vec![];
+            while !done.is_cancelled() && samples.len() < 1000 {
+                samples.push((counters.entries(), counters.bytes(), counters.errors()));
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            samples
+        }
+    });
+    let result = walk.run()?;
+    done.cancel();
+    let samples = sampler.join().unwrap();
+
+    assert!(samples
+        .windows(2)
+        .all(|pair| pair[0].0 <= pair[1].0 && pair[0].1 <= pair[1].1 && pair[0].2 <= pair[1].2));
+    assert_eq!(counters.entries(), result.stats.entries());
+    assert_eq!(
+        counters.bytes(),
+        result.entries.iter().map(|entry| entry.size).sum::<u64>()
+    );
+    assert_eq!(counters.errors(), result.errors.len() as u64);
+    assert_eq!(counters.errors(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn errors_carry_the_io_error() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let missing = tmp_dir.path().join("missing");
+
+    let walk = Walk::new(vec![missing.clone()], 1, FilesizeType::ApparentSize);
+    let errors = walk.run()?.errors;
+
+    assert_eq!(errors.len(), 1);
+    let error = &errors[0];
+    assert!(
+        matches!(error, diskus::Error::NoMetadataForRoot(path, err) if *path == missing
+            && err.kind() == std::io::ErrorKind::NotFound)
+    );
+    let source = error.source().expect("Should have a source");
+    assert!(error.to_string().ends_with(&format!(": {}", source)));
+
+    Ok(())
+}
+
+#[test]
+fn errors_are_passed_to_the_handler() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let missing = tmp_dir.path().join("missing");
+
+    let handled = Arc::new(std::sync::Mutex::new(vec![]));
+    let walk = Walk::new(
+        vec![tmp_dir.path().to_path_buf(), missing.clone()],
+        1,
+        FilesizeType::ApparentSize,
+    )
+    .on_error({
+        let handled = Arc::clone(&handled);
+        move |error: &diskus::Error| handled.lock().unwrap().push(error.to_string())
+    });
+    let result = walk.run()?;
+
+    assert!(result.errors.is_empty());
+    assert_eq!(result.stats.errors, 1);
+    let handled = handled.lock().unwrap();
+    assert_eq!(handled.len(), 1);
+    assert!(handled[0].contains(&missing.to_string_lossy().into_owned()));
+
+    Ok(())
+}
+
+#[test]
+fn public_types_implement_the_common_traits() {
+    fn debug_clone<T: std::fmt::Debug + Clone>() {}
+    fn eq<T: std::fmt::Debug + Clone + Eq>() {}
+    fn copy_hash<T: Copy + Eq + std::hash::Hash>() {}
+
+    debug_clone::<Walk>();
+    debug_clone::<WalkBuilder>();
+    copy_hash::<FilesizeType>();
+    eq::<diskus::Error>();
+    eq::<diskus::RootEntry>();
+    eq::<diskus::Stats>();
+    eq::<diskus::WalkResult>();
+    eq::<WalkEvent>();
+}
+
+#[test]
+fn errors_are_compared_by_kind_and_path() {
+    let io_error = |kind| std::io::Error::new(kind, "error");
+    let path = PathBuf::from("path");
+    assert_eq!(
+        diskus::Error::CouldNotReadDir(path.clone(), io_error(std::io::ErrorKind::NotFound)),
+        diskus::Error::CouldNotReadDir(path.clone(), io_error(std::io::ErrorKind::Other))
+    );
+    assert_ne!(
+        diskus::Error::CouldNotReadDir(path.clone(), io_error(std::io::ErrorKind::NotFound)),
+        diskus::Error::NoMetadataForPath(path.clone(), io_error(std::io::ErrorKind::NotFound))
+    );
+    assert_ne!(
+        diskus::Error::FilesystemLoop(path),
+        diskus::Error::FilesystemLoop(PathBuf::from("other"))
+    );
+}
+
+#[test]
+fn every_entry_is_listed() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let root = tmp_dir.path();
+    fs::create_dir(root.join("sub"))?;
+    File::create(root.join("file"))?.write_all(&[0u8; 100])?;
+    File::create(root.join("sub").join("other"))?.write_all(&[0u8; 10])?;
+    fs::hard_link(root.join("file"), root.join("sub").join("link"))?;
+
+    let walk = Walk::new(vec![root.to_path_buf()], 2, FilesizeType::ApparentSize);
+    let (result, mut files) = walk.run_detailed()?;
+    assert!(result.errors.is_empty());
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    // The hardlinked file is only listed under one of its paths
+    let link = files
+        .iter()
+        .find(|file| file.size == 100)
+        .map(|file| file.path.clone())
+        .expect("the hardlinked file should be listed");
+    assert!(link == root.join("file") || link == root.join("sub").join("link"));
+
+    let entry = |path: PathBuf, is_dir| FileEntry {
+        size: path.metadata().unwrap().len(),
+        path,
+        is_dir,
+    };
+    let mut expected = vec![
+        entry(root.to_path_buf(), true),
+        entry(link, false),
+        entry(root.join("sub"), true),
+        entry(root.join("sub").join("other"), false),
+    ];
+    expected.sort_by(|a, b| a.path.cmp(&b.path));
+    assert_eq!(files, expected);
+    assert_eq!(
+        files.iter().map(|file| file.size).sum::<u64>(),
+        result.entries[0].size
+    );
+
+    Ok(())
+}
+
+#[test]
+fn counts_of_files_and_directories() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let sub_dir = tmp_dir.path().join("sub");
+    fs::create_dir(&sub_dir)?;
+    File::create(sub_dir.join("file-1"))?.write_all(&[0u8; 10])?;
+    File::create(tmp_dir.path().join("file-2"))?.write_all(&[0u8; 10])?;
+    // Hardlinks are only counted once
+    fs::hard_link(sub_dir.join("file-1"), tmp_dir.path().join("file-1-link"))?;
+
+    let walk = Walk::new(
+        vec![tmp_dir.path().to_path_buf()],
+        1,
+        FilesizeType::ApparentSize,
+    );
+    let result = walk.run()?;
+    let entry = result.entries.first().expect("Should not be empty");
+
+    assert!(result.errors.is_empty());
+    assert_eq!(entry.files, 2);
+    assert_eq!(entry.dirs, 2);
+
+    Ok(())
+}
+
+#[test]
+fn inode_count() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    File::create(tmp_dir.path().join("file-1"))?.write_all(&[0u8; 10])?;
+    File::create(tmp_dir.path().join("file-2"))?.write_all(&[0u8; 10])?;
+    // Hardlinks share a single inode
+    fs::hard_link(
+        tmp_dir.path().join("file-1"),
+        tmp_dir.path().join("file-1-link"),
+    )?;
+
+    let walk = Walk::new(vec![tmp_dir.path().to_path_buf()], 1, FilesizeType::Inodes);
+    let result = walk.run()?;
+    let entry = result.entries.first().expect("Should not be empty");
+
+    assert!(result.errors.is_empty());
+    assert_eq!(entry.size, 3);
+
+    Ok(())
+}
+
+#[test]
+fn sizes_of_subdirectories_up_to_report_depth() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let sub_dir = tmp_dir.path().join("sub");
+    fs::create_dir_all(sub_dir.join("subsub"))?;
+    File::create(sub_dir.join("subsub").join("file-1"))?.write_all(&[0u8; 10])?;
+    File::create(sub_dir.join("file-2"))?.write_all(&[0u8; 20])?;
+    File::create(tmp_dir.path().join("file-3"))?.write_all(&[0u8; 30])?;
+
+    let walk = WalkBuilder::new(vec![tmp_dir.path().to_path_buf()])
+        .threads(1)
+        .filesize_type(FilesizeType::Inodes)
+        .report_depth(Some(1))
+        .build()?;
+    let result = walk.run()?;
+
+    assert!(result.errors.is_empty());
+    let summary: Vec<_> = result
+        .entries
+        .iter()
+        .map(|entry| (entry.path.clone(), entry.depth, entry.size))
+        .collect();
+    assert_eq!(
+        summary,
+        [(tmp_dir.path().to_path_buf(), 0, 6), (sub_dir, 1, 4)]
+    );
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn stats_of_visited_entries() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    fs::create_dir(tmp_dir.path().join("sub"))?;
+    File::create(tmp_dir.path().join("sub").join("file"))?.write_all(&[0u8; 10])?;
+    std::os::unix::fs::symlink("sub/file", tmp_dir.path().join("link"))?;
+
+    let walk = Walk::new(
+        vec![tmp_dir.path().to_path_buf(), tmp_dir.path().join("missing")],
+        1,
+        FilesizeType::ApparentSize,
+    );
+    let result = walk.run()?;
+
+    assert_eq!(result.errors.len(), 1);
+    assert_eq!(result.stats.files, 1);
+    assert_eq!(result.stats.dirs, 2);
+    assert_eq!(result.stats.symlinks, 1);
+    assert_eq!(result.stats.errors, 1);
+    assert_eq!(result.stats.entries(), 4);
+
+    Ok(())
+}
+
+#[cfg(windows)]
+#[test]
+fn hardlinks_are_only_deduplicated_on_request() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    File::create(tmp_dir.path().join("file"))?.write_all(&[0u8; 100])?;
+    fs::hard_link(tmp_dir.path().join("file"), tmp_dir.path().join("link"))?;
+
+    let size = |dedup_hardlinks| {
+        Walk::new(
+            vec![tmp_dir.path().join("file"), tmp_dir.path().join("link")],
+            1,
+            FilesizeType::ApparentSize,
+        )
+        .dedup_hardlinks(dedup_hardlinks)
+        .run()?
+        .entries
+        .iter()
+        .map(|entry| entry.size)
+        .sum::<u64>()
+    };
+    assert_eq!(size(false), 200);
+    assert_eq!(size(true), 100);
+
+    Ok(())
+}
+
+#[test]
+fn size_shared_via_hardlinks() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    File::create(tmp_dir.path().join("file"))?.write_all(&[0u8; 100])?;
+    fs::hard_link(tmp_dir.path().join("file"), tmp_dir.path().join("link-1"))?;
+    fs::hard_link(tmp_dir.path().join("file"), tmp_dir.path().join("link-2"))?;
+
+    let walk = Walk::new(
+        vec![tmp_dir.path().to_path_buf()],
+        1,
+        FilesizeType::ApparentSize,
+    );
+    let result = walk.run()?;
+    let entry = result.entries.first().expect("Should not be empty");
+
+    assert!(result.errors.is_empty());
+    assert_eq!(entry.shared, 200);
+    assert_eq!(result.stats.shared, 200);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn sizes_by_owner() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    File::create(tmp_dir.path().join("file"))?.write_all(&[0u8; 100])?;
+    fs::hard_link(tmp_dir.path().join("file"), tmp_dir.path().join("link"))?;
+    let uid = fs::metadata(tmp_dir.path().join("file"))?.uid();
+
+    let walk = Walk::new(
+        vec![tmp_dir.path().join("file"), tmp_dir.path().join("link")],
+        1,
+        FilesizeType::ApparentSize,
+    );
+    let (owners, errors, _stats) = walk.run_by_owner()?;
+
+    assert!(errors.is_empty());
+    assert_eq!(owners.len(), 1);
+    assert_eq!(owners[&uid], 100);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn apparent_size_and_disk_usage() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    // A sparse file takes up (almost) no space on disk
+    File::create(tmp_dir.path().join("sparse"))?.set_len(10_000_000)?;
+    File::create(tmp_dir.path().join("file"))?.write_all(&[1u8; 100])?;
+
+    let walk = Walk::new(
+        vec![tmp_dir.path().join("sparse"), tmp_dir.path().join("file")],
+        1,
+        FilesizeType::Inodes,
+    );
+    let (sizes, errors, _stats) = walk.run_both()?;
 
     assert!(errors.is_empty());
-    assert_eq!(*size_in_bytes, 100);
+    assert_eq!(sizes.len(), 2);
+    assert_eq!(sizes[0].apparent_size, 10_000_000);
+    assert!(sizes[0].disk_usage < sizes[0].apparent_size);
+    assert_eq!(sizes[1].apparent_size, 100);
+    assert!(sizes[1].disk_usage >= 512);
+
+    Ok(())
+}
+
+#[test]
+fn excluded_entries_are_skipped() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let modules = tmp_dir.path().join("app").join("node_modules");
+    fs::create_dir_all(modules.join("dep"))?;
+    File::create(modules.join("dep").join("index.js"))?.write_all(&[0u8; 100])?;
+    File::create(tmp_dir.path().join("app").join("main.js"))?.write_all(&[0u8; 10])?;
+    File::create(tmp_dir.path().join("debian.iso"))?.write_all(&[0u8; 1000])?;
+
+    let exclude = GlobSet::new(vec!["*/node_modules".parse()?, "*.iso".parse()?]);
+    let walk = WalkBuilder::new(vec![tmp_dir.path().to_path_buf()])
+        .threads(1)
+        .filesize_type(FilesizeType::ApparentSize)
+        .exclude(exclude)
+        .build()?;
+    let result = walk.run()?;
+    let entry = result.entries.first().expect("Should not be empty");
+
+    assert!(result.errors.is_empty());
+    assert_eq!(entry.files, 1);
+    // The excluded directory is not walked at all
+    assert_eq!(result.stats.dirs, 2);
+
+    Ok(())
+}
+
+#[test]
+fn entries_are_filtered_by_the_closure() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    fs::create_dir(tmp_dir.path().join("pruned"))?;
+    File::create(tmp_dir.path().join("pruned").join("file"))?.write_all(&[0u8; 100])?;
+    fs::create_dir(tmp_dir.path().join("skipped"))?;
+    File::create(tmp_dir.path().join("skipped").join("file"))?.write_all(&[0u8; 10])?;
+    File::create(tmp_dir.path().join("skipped.txt"))?.write_all(&[0u8; 1000])?;
+
+    let walk = Walk::new(vec![tmp_dir.path()], 2, FilesizeType::ApparentSize).filter(
+        |path: &Path, _metadata: &fs::Metadata| match path.file_name() {
+            Some(name) if name == "pruned" => FilterDecision::Prune,
+            Some(name) if name.to_string_lossy().starts_with("skipped") => FilterDecision::Skip,
+            _ => FilterDecision::Count,
+        },
+    );
+    let result = walk.run()?;
+    let entry = result.entries.first().expect("Should not be empty");
+
+    assert!(result.errors.is_empty());
+    // Only the file in the skipped directory is counted, along with the root
+    assert_eq!(entry.files, 1);
+    assert_eq!(entry.size, tmp_dir.path().metadata()?.len() + 10,);
+    // The pruned directory is not walked at all
+    assert_eq!(result.stats.dirs, 2);
+
+    Ok(())
+}
+
+#[test]
+fn excluded_entries_are_not_passed_to_the_closure() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    fs::create_dir(tmp_dir.path().join("node_modules"))?;
+    File::create(tmp_dir.path().join("node_modules").join("file"))?;
+    File::create(tmp_dir.path().join("image.iso"))?;
+    File::create(tmp_dir.path().join("skipped"))?.write_all(&[0u8; 100])?;
+
+    let seen = Arc::new(Mutex::new(vec![]));
+    let walk = Walk::new(vec![tmp_dir.path()], 2, FilesizeType::ApparentSize)
+        .exclude(GlobSet::new(vec!["*.iso".parse()?]))
+        .prune(vec!["node_modules".into()])
+        .filter({
+            let seen = Arc::clone(&seen);
+            move |path: &Path, _metadata: &fs::Metadata| {
+                seen.lock().unwrap().push(path.to_path_buf());
+                FilterDecision::Skip
+            }
+        });
+    let result = walk.run()?;
+
+    assert_eq!(*seen.lock().unwrap(), [tmp_dir.path().join("skipped")]);
+    assert_eq!(result.entries[0].files, 0);
+    assert_eq!(result.stats.pruned_dirs, 1);
+
+    Ok(())
+}
+
+#[test]
+fn only_included_files_are_counted() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    fs::create_dir_all(tmp_dir.path().join("log").join("old"))?;
+    File::create(tmp_dir.path().join("log").join("syslog.log"))?.write_all(&[0u8; 100])?;
+    File::create(tmp_dir.path().join("log").join("old").join("syslog.gz"))?
+        .write_all(&[0u8; 20])?;
+    File::create(tmp_dir.path().join("log").join("old").join("debug.log"))?.write_all(&[0u8; 7])?;
+    File::create(tmp_dir.path().join("log").join("README"))?.write_all(&[0u8; 1000])?;
+
+    let walk = |exclude: Vec<&str>| -> Result<u64, Box<dyn Error>> {
+        let exclude = exclude
+            .into_iter()
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        let walk = Walk::new(
+            vec![tmp_dir.path().to_path_buf()],
+            1,
+            FilesizeType::ApparentSize,
+        )
+        .include(GlobSet::new(vec!["*.log".parse()?, "*.gz".parse()?]))
+        .exclude(GlobSet::new(exclude));
+        let result = walk.run()?;
+        assert!(result.errors.is_empty());
+        Ok(result.entries[0].size)
+    };
+
+    // The sizes of the directories themselves are not counted either
+    assert_eq!(walk(vec![])?, 127);
+    // Excludes take precedence over includes
+    assert_eq!(walk(vec!["debug.log"])?, 120);
+    assert_eq!(walk(vec!["old"])?, 100);
+
+    Ok(())
+}
+
+#[test]
+fn gitignored_entries_are_skipped() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let repo = tmp_dir.path();
+
+    fs::create_dir_all(repo.join(".git").join("info"))?;
+    fs::write(repo.join(".git").join("info").join("exclude"), "*.swp\n")?;
+    fs::write(repo.join(".gitignore"), "/target/\n*.log\n")?;
+    fs::create_dir_all(repo.join("target").join("debug"))?;
+    File::create(repo.join("target").join("debug").join("diskus"))?.write_all(&[0u8; 1000])?;
+    fs::create_dir(repo.join("logs"))?;
+    fs::write(repo.join("logs").join(".gitignore"), "!keep.log\n")?;
+    File::create(repo.join("logs").join("keep.log"))?.write_all(&[0u8; 10])?;
+    File::create(repo.join("logs").join("debug.log"))?.write_all(&[0u8; 20])?;
+    File::create(repo.join("main.rs.swp"))?.write_all(&[0u8; 40])?;
+
+    let walk = Walk::new(vec![repo.to_path_buf()], 1, FilesizeType::Inodes);
+    let result = walk.gitignore(true).run()?;
+    assert!(result.errors.is_empty());
+    // The repository, .git, .git/info, .git/info/exclude, .gitignore, logs, logs/.gitignore and
+    // logs/keep.log
+    assert_eq!(result.entries[0].size, 8);
+
+    let walk = Walk::new(vec![repo.to_path_buf()], 1, FilesizeType::Inodes);
+    let entries = walk.gitignore(true).skip_git_dirs(true).run()?.entries;
+    assert_eq!(entries[0].size, 5);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn followed_symlinks_are_counted_once() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::symlink;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let root = tmp_dir.path();
+
+    fs::create_dir(root.join("data"))?;
+    File::create(root.join("data").join("file"))?.write_all(&[0u8; 100])?;
+    fs::create_dir(root.join("links"))?;
+    symlink("../data", root.join("links").join("a"))?;
+    symlink("../data/file", root.join("links").join("b"))?;
+    symlink("..", root.join("links").join("loop"))?;
+
+    let walk = Walk::new(vec![root.to_path_buf()], 1, FilesizeType::Inodes);
+    let result = walk.run()?;
+    assert!(result.errors.is_empty());
+    assert_eq!(result.entries[0].size, 7);
+
+    // The root, 'data', 'data/file' and 'links'
+    let walk = WalkBuilder::new(vec![root.to_path_buf()])
+        .threads(1)
+        .filesize_type(FilesizeType::Inodes)
+        .follow_symlinks(true)
+        .build()?;
+    let result = walk.run()?;
+    assert_eq!(result.entries[0].size, 4);
+    assert_eq!(result.errors.len(), 1);
+    assert!(
+        matches!(&result.errors[0], diskus::Error::FilesystemLoop(path) if path.ends_with("loop"))
+    );
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn filesystem_loops_are_not_descended_into() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::symlink;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let root = tmp_dir.path();
+
+    fs::create_dir_all(root.join("a").join("b"))?;
+    symlink("../..", root.join("a").join("b").join("up"))?;
+    // Reaching 'a' a second time next to it is not a loop
+    symlink("a", root.join("c"))?;
+
+    // The root, 'a' and 'b'
+    let walk = WalkBuilder::new(vec![root.to_path_buf()])
+        .threads(1)
+        .filesize_type(FilesizeType::Inodes)
+        .follow_symlinks(true)
+        .build()?;
+    let mut result = walk.run()?;
+    assert_eq!(result.entries[0].size, 3);
+    result
+        .errors
+        .sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+    assert_eq!(result.errors.len(), 2);
+    assert!(
+        matches!(&result.errors[0], diskus::Error::FilesystemLoop(path) if path.ends_with("a/b/up"))
+    );
+    assert!(
+        matches!(&result.errors[1], diskus::Error::FilesystemLoop(path) if path.ends_with("c/b/up"))
+    );
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn matching_errors_are_ignored() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::symlink;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let root = tmp_dir.path();
+    for dir in ["expected", "new"] {
+        fs::create_dir(root.join(dir))?;
+        symlink("..", root.join(dir).join("up"))?;
+    }
+
+    let walk = WalkBuilder::new(vec![root.to_path_buf()])
+        .threads(1)
+        .filesize_type(FilesizeType::Inodes)
+        .follow_symlinks(true)
+        .build()?
+        .ignore_errors(GlobSet::new(vec!["*/expected/up".parse()?]));
+    let result = walk.run()?;
+    assert_eq!(result.errors.len(), 1);
+    assert!(
+        matches!(&result.errors[0], diskus::Error::FilesystemLoop(path) if path.ends_with("new/up"))
+    );
+    assert_eq!((result.stats.errors, result.stats.ignored_errors), (1, 1));
+
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[test]
+fn data_size_leaves_out_holes() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let file = tmp_dir.path().join("sparse");
+    let mut sparse = File::create(&file)?;
+    sparse.write_all(&[1u8; 4096])?;
+    sparse.set_len(64 * 1024 * 1024)?;
+    drop(sparse);
+
+    let size = |filesize_type| {
+        let result = Walk::new(vec![file.clone()], 1, filesize_type)
+            .run()
+            .unwrap();
+        assert!(result.errors.is_empty());
+        result.entries[0].size
+    };
+    let data_size = size(FilesizeType::DataSize);
+    assert!(data_size >= 4096);
+    assert!(data_size < size(FilesizeType::ApparentSize));
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn xattrs_can_be_included() -> Result<(), Box<dyn Error>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    let file = tmp_dir.path().join("file");
+    File::create(&file)?.write_all(&[0u8; 100])?;
+
+    let path = CString::new(file.as_os_str().as_bytes())?;
+    let name = CString::new("user.diskus-test")?;
+    let value = [1u8; 1000];
+    // SAFETY: both strings are NUL-terminated, and `value` is valid for `value.len()` bytes
+    let status = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if status != 0 {
+        // Not every filesystem supports extended attributes
+        return Ok(());
+    }
+
+    let size = |include_xattrs| {
+        let result = Walk::new(vec![file.clone()], 1, FilesizeType::ApparentSize)
+            .include_xattrs(include_xattrs)
+            .run()
+            .unwrap();
+        assert!(result.errors.is_empty());
+        result.entries[0].size
+    };
+    assert_eq!(size(false), 100);
+    assert_eq!(size(true), 1100);
+
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+#[test]
+fn physical_size_leaves_out_clones() -> Result<(), Box<dyn Error>> {
+    use std::process::Command;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    File::create(tmp_dir.path().join("original"))?.write_all(&[1u8; 1024 * 1024])?;
+    let status = Command::new("cp")
+        .arg("-c")
+        .arg(tmp_dir.path().join("original"))
+        .arg(tmp_dir.path().join("clone"))
+        .status()?;
+    assert!(status.success());
+
+    let walk = |filesize_type| {
+        let result = Walk::new(vec![tmp_dir.path().to_path_buf()], 1, filesize_type).run()?;
+        assert!(result.errors.is_empty());
+        (result.entries[0].size, result.stats)
+    };
+    let (physical, stats) = walk(FilesizeType::Physical);
+    if stats.unknown_clone_shares > 0 {
+        // Not on APFS
+        return Ok(());
+    }
+    let (disk_usage, _stats) = walk(FilesizeType::DiskUsage);
+    assert!(stats.clone_shared >= 1024 * 1024);
+    assert_eq!(physical + stats.clone_shared, disk_usage);
+
+    Ok(())
+}
+
+#[test]
+fn walk_stops_at_limit() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    for i in 0..10 {
+        File::create(tmp_dir.path().join(format!("file-{}", i)))?;
+    }
+
+    let walk = |limit| {
+        Walk::new(vec![tmp_dir.path().to_path_buf()], 2, FilesizeType::Inodes)
+            .limit(limit)
+            .run()
+            .unwrap()
+    };
+
+    // The root and 4 of the files
+    let result = walk(Some(5));
+    assert!(result.errors.is_empty());
+    assert_eq!(result.entries[0].size, 5);
+    assert!(result.stats.limit_reached);
+
+    // The limit is only reached if there are more entries
+    let result = walk(Some(11));
+    assert_eq!(result.entries[0].size, 11);
+    assert!(!result.stats.limit_reached);
+
+    Ok(())
+}
+
+#[test]
+fn cancelled_walks_stop_early() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    File::create(tmp_dir.path().join("file"))?;
+
+    let walk = |cancel| {
+        Walk::new(vec![tmp_dir.path().to_path_buf()], 1, FilesizeType::Inodes)
+            .cancel_token(cancel)
+            .timeout(Some(Duration::from_secs(60)))
+            .run()
+            .unwrap()
+    };
+
+    let result = walk(CancelToken::new());
+    assert!(result.errors.is_empty());
+    assert_eq!(result.entries[0].size, 2);
+    assert!(!result.stats.cancelled && !result.stats.timed_out);
+
+    // The root is still reported, but not walked
+    let cancel = CancelToken::new();
+    cancel.cancel();
+    let result = walk(cancel);
+    assert_eq!(result.entries[0].size, 1);
+    assert!(result.stats.cancelled);
+    assert!(!result.stats.timed_out);
+
+    Ok(())
+}
+
+#[test]
+fn walks_can_be_run_again_after_a_timeout() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    for file in 0..3 {
+        File::create(tmp_dir.path().join(file.to_string()))?;
+    }
+
+    // Only the first run is slow enough to time out
+    let slow = Arc::new(Mutex::new(true));
+    let cancel = CancelToken::new();
+    let walk = Walk::new(vec![tmp_dir.path()], 1, FilesizeType::Inodes)
+        .cancel_token(cancel.clone())
+        .timeout(Some(Duration::from_millis(100)))
+        .filter(move |_path: &Path, _metadata: &fs::Metadata| {
+            if std::mem::take(&mut *slow.lock().unwrap()) {
+                std::thread::sleep(Duration::from_secs(1));
+            }
+            FilterDecision::Count
+        });
+
+    let result = walk.run()?;
+    assert!(result.stats.cancelled && result.stats.timed_out);
+    assert!(result.entries[0].size < 4);
+    // The timeout only cancelled that run, and not the token of the walk
+    assert!(!cancel.is_cancelled());
+
+    for result in [walk.run()?, walk.clone().run()?] {
+        assert!(!result.stats.cancelled && !result.stats.timed_out);
+        assert_eq!(result.entries[0].size, 4);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn traversal_stops_at_max_depth() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let sub_dir = tmp_dir.path().join("sub");
+    fs::create_dir_all(sub_dir.join("subsub"))?;
+    File::create(sub_dir.join("subsub").join("file-1"))?.write_all(&[0u8; 10])?;
+    File::create(sub_dir.join("file-2"))?.write_all(&[0u8; 20])?;
+    File::create(tmp_dir.path().join("file-3"))?.write_all(&[0u8; 30])?;
+
+    let walk = |max_depth| {
+        WalkBuilder::new(vec![tmp_dir.path().to_path_buf()])
+            .threads(1)
+            .filesize_type(FilesizeType::Inodes)
+            .max_depth(max_depth)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap()
+    };
+
+    // Only the root itself
+    let result = walk(Some(0));
+    assert!(result.errors.is_empty());
+    assert_eq!(result.entries[0].size, 1);
+    assert_eq!(result.stats.truncated_dirs, 1);
+
+    // The root, 'sub' and 'file-3'
+    let result = walk(Some(1));
+    assert_eq!(result.entries[0].size, 3);
+    assert_eq!(result.stats.truncated_dirs, 1);
+
+    let result = walk(Some(2));
+    assert_eq!(result.entries[0].size, 5);
+    assert_eq!(result.stats.truncated_dirs, 1);
+
+    // Nothing is truncated if the tree is not deeper than the limit
+    let result = walk(Some(3));
+    assert_eq!(result.entries[0].size, 6);
+    assert_eq!(result.stats.truncated_dirs, 0);
+
+    Ok(())
+}
+
+#[test]
+fn files_above_max_file_size_are_skipped() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let sub_dir = tmp_dir.path().join("sub");
+    fs::create_dir(&sub_dir)?;
+    File::create(sub_dir.join("small"))?.write_all(&[0u8; 10])?;
+    File::create(sub_dir.join("large"))?.write_all(&[0u8; 1000])?;
+
+    let root_directories = vec![sub_dir.join("small"), sub_dir.join("large"), sub_dir];
+    let result = Walk::new(root_directories, 1, FilesizeType::ApparentSize)
+        .max_file_size(Some(100))
+        .allow_overlap(true)
+        .run()?;
+    assert!(result.errors.is_empty());
+    assert_eq!(result.stats.skipped_large_files, 1);
+    assert_eq!(result.stats.files, 3);
+
+    // Roots are always counted
+    assert_eq!(result.entries[0].size, 10);
+    assert_eq!(result.entries[1].size, 1000);
+    assert_eq!(result.entries[2].files, 1);
+
+    Ok(())
+}
+
+#[test]
+fn files_outside_of_the_time_window_are_skipped() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let day = Duration::from_secs(24 * 60 * 60);
+    let now = SystemTime::now();
+    for (name, size, modified) in [
+        ("new", 10, now - day),
+        ("old", 100, now - 60 * day),
+        ("ancient", 1000, now - 120 * day),
+        ("future", 10000, now + day),
+    ] {
+        let mut file = File::create(tmp_dir.path().join(name))?;
+        file.write_all(&vec![0u8; size])?;
+        file.set_modified(modified)?;
+    }
+
+    // The size of the files, without the root directory itself
+    let dir_size = tmp_dir.path().metadata()?.len();
+    let walk = |newer_than, older_than| {
+        let result = Walk::new(
+            vec![tmp_dir.path().to_path_buf()],
+            1,
+            FilesizeType::ApparentSize,
+        )
+        .newer_than(newer_than)
+        .older_than(older_than)
+        .run()
+        .unwrap();
+        assert!(result.errors.is_empty());
+        result.entries[0].size - dir_size
+    };
+
+    assert_eq!(walk(None, None), 11110);
+    // Files modified in the future are always counted
+    assert_eq!(walk(Some(now - 30 * day), None), 10010);
+    assert_eq!(walk(None, Some(now - 30 * day)), 11100);
+    assert_eq!(walk(Some(now - 90 * day), Some(now - 2 * day)), 10100);
+
+    Ok(())
+}
+
+#[test]
+fn only_files_with_the_given_extensions_are_counted() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let videos = tmp_dir.path().join("videos");
+    fs::create_dir(&videos)?;
+    File::create(videos.join("a.mp4"))?.write_all(&[0u8; 1])?;
+    File::create(videos.join("b.MKV"))?.write_all(&[0u8; 10])?;
+    File::create(videos.join("c.srt"))?.write_all(&[0u8; 100])?;
+    File::create(tmp_dir.path().join("README"))?.write_all(&[0u8; 1000])?;
+
+    let walk = |extensions: &[&str]| {
+        let extensions = extensions.iter().map(|e| e.to_string()).collect();
+        let result = Walk::new(
+            vec![tmp_dir.path().to_path_buf()],
+            1,
+            FilesizeType::ApparentSize,
+        )
+        .extensions(Some(extensions))
+        .run()
+        .unwrap();
+        assert!(result.errors.is_empty());
+        result.entries[0].size
+    };
+
+    assert_eq!(walk(&["mp4", "mkv"]), 11);
+    assert_eq!(walk(&[".MP4"]), 1);
+    // The empty extension matches files without one
+    assert_eq!(walk(&["", "srt"]), 1100);
+    assert_eq!(walk(&["iso"]), 0);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn special_files_can_be_skipped() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::net::UnixListener;
+    use std::process::Command;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    File::create(tmp_dir.path().join("file"))?;
+    let _socket = UnixListener::bind(tmp_dir.path().join("socket"))?;
+    let status = Command::new("mkfifo")
+        .arg(tmp_dir.path().join("fifo"))
+        .status()?;
+    assert!(status.success());
+
+    let walk = |skip_special_files| {
+        let result = Walk::new(vec![tmp_dir.path().to_path_buf()], 1, FilesizeType::Inodes)
+            .skip_special_files(skip_special_files)
+            .run()
+            .unwrap();
+        assert!(result.errors.is_empty());
+        (result.entries[0].size, result.stats.skipped_special_files)
+    };
+
+    assert_eq!(walk(false), (4, 0));
+    assert_eq!(walk(true), (2, 2));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn entries_of_other_owners_are_skipped() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let tmp_dir = TempDir::new("diskus-tests")?;
+    fs::create_dir(tmp_dir.path().join("dir"))?;
+    File::create(tmp_dir.path().join("dir").join("file"))?;
+    File::create(tmp_dir.path().join("file"))?;
+    let uid = fs::metadata(tmp_dir.path())?.uid();
+
+    let walk = |owner| {
+        let result = Walk::new(vec![tmp_dir.path().to_path_buf()], 1, FilesizeType::Inodes)
+            .owner(owner)
+            .run()
+            .unwrap();
+        assert!(result.errors.is_empty());
+        (result.entries[0].size, result.stats.skipped_other_owners)
+    };
+
+    assert_eq!(walk(Some(uid)), (4, 0));
+    // The contents of directories of other users are not visited at all
+    assert_eq!(walk(Some(uid.wrapping_add(1))), (1, 2));
+
+    Ok(())
+}
+
+#[test]
+fn directories_are_not_counted_with_files_only() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let dir = tmp_dir.path().join("dir");
+    fs::create_dir(&dir)?;
+    File::create(dir.join("file"))?.write_all(&[0u8; 100])?;
+
+    let walk = |files_only| {
+        let result = Walk::new(vec![dir.clone()], 1, FilesizeType::ApparentSize)
+            .files_only(files_only)
+            .run()
+            .unwrap();
+        assert!(result.errors.is_empty());
+        assert_eq!(result.stats.dirs, 1);
+        result.entries[0].size
+    };
+
+    assert_eq!(walk(true), 100);
+    assert_eq!(walk(false) - walk(true), fs::metadata(&dir)?.len());
+
+    Ok(())
+}
+
+#[test]
+fn only_files_matching_the_regexes_are_counted() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let dumps = tmp_dir.path().join("dumps");
+    fs::create_dir(&dumps)?;
+    File::create(dumps.join("core.1234"))?.write_all(&[0u8; 1])?;
+    File::create(dumps.join("core.1234.gz"))?.write_all(&[0u8; 10])?;
+    File::create(tmp_dir.path().join("core.99"))?.write_all(&[0u8; 100])?;
+
+    let walk = |regex: &[&str], regex_exclude: &[&str], full_path| {
+        let parse = |patterns: &[&str]| patterns.iter().map(|p| p.parse().unwrap()).collect();
+        let result = Walk::new(
+            vec![tmp_dir.path().to_path_buf()],
+            1,
+            FilesizeType::ApparentSize,
+        )
+        .regex(parse(regex))
+        .regex_exclude(parse(regex_exclude))
+        .regex_full_path(full_path)
+        .run()
+        .unwrap();
+        assert!(result.errors.is_empty());
+        result.entries[0].size
+    };
+
+    assert_eq!(walk(&[r"^core\.\d+$"], &[], false), 101);
+    assert_eq!(walk(&[r"^core\.\d+$", r"\.gz$"], &[], false), 111);
+    assert_eq!(walk(&["^core"], &[r"\.gz$"], false), 101);
+    assert_eq!(walk(&[], &["99"], false), 11);
+    // The path relative to the root
+    assert_eq!(walk(&[r"^dumps/core\.\d+$"], &[], true), 1);
+
+    Ok(())
+}
+
+#[test]
+fn hidden_entries_are_skipped() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let cache = tmp_dir.path().join(".cache");
+    fs::create_dir(&cache)?;
+    File::create(cache.join("blob"))?.write_all(&[0u8; 100])?;
+    File::create(tmp_dir.path().join(".bashrc"))?.write_all(&[0u8; 10])?;
+    File::create(tmp_dir.path().join("visible"))?.write_all(&[0u8; 1])?;
+
+    let walk = |root| {
+        Walk::new(vec![root], 1, FilesizeType::Inodes)
+            .skip_hidden(true)
+            .run()
+            .unwrap()
+    };
+
+    let result = walk(tmp_dir.path().to_path_buf());
+    assert!(result.errors.is_empty());
+    assert_eq!(result.entries[0].size, 2);
+    assert_eq!(result.stats.files, 1);
+
+    // Hidden roots are still walked
+    let entries = walk(cache).entries;
+    assert_eq!(entries[0].size, 2);
+
+    Ok(())
+}
+
+#[test]
+fn pruned_directories_are_skipped() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let app = tmp_dir.path().join("app");
+    fs::create_dir_all(app.join("node_modules").join("left-pad"))?;
+    fs::create_dir_all(app.join("src"))?;
+    File::create(app.join("src").join("node_modules"))?.write_all(&[0u8; 1])?;
+    fs::create_dir(tmp_dir.path().join("target"))?;
+
+    let result = Walk::new(vec![app], 1, FilesizeType::Inodes)
+        .prune(vec!["node_modules".into(), "target".into()])
+        .run()?;
+    assert!(result.errors.is_empty());
+    // 'app', 'src' and the file named like a pruned directory
+    assert_eq!(result.entries[0].size, 3);
+    assert_eq!(result.stats.pruned_dirs, 1);
+
+    Ok(())
+}
+
+#[test]
+fn tagged_cache_directories_are_skipped() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let tagged = tmp_dir.path().join("tagged");
+    let untagged = tmp_dir.path().join("untagged");
+    fs::create_dir(&tagged)?;
+    fs::create_dir(&untagged)?;
+    File::create(tagged.join("CACHEDIR.TAG"))?
+        .write_all(b"Signature: 8a477f597d28d172789f06886806bc55\n# a cache\n")?;
+    File::create(tagged.join("blob"))?.write_all(&[0u8; 100])?;
+    // A tag without the signature does not count
+    File::create(untagged.join("CACHEDIR.TAG"))?.write_all(b"Signature: none\n")?;
+    File::create(untagged.join("blob"))?.write_all(&[0u8; 100])?;
+
+    let result = Walk::new(vec![tmp_dir.path().to_path_buf()], 1, FilesizeType::Inodes)
+        .skip_cache_dirs(true)
+        .run()?;
+    assert!(result.errors.is_empty());
+    // The root, both directories and the contents of 'untagged'
+    assert_eq!(result.entries[0].size, 5);
+
+    Ok(())
+}
+
+#[test]
+fn every_hardlink_is_counted_with_count_hardlinks() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    fs::create_dir(tmp_dir.path().join("sub"))?;
+    File::create(tmp_dir.path().join("file"))?.write_all(&[0u8; 100])?;
+    fs::hard_link(
+        tmp_dir.path().join("file"),
+        tmp_dir.path().join("sub").join("file-link"),
+    )?;
+
+    // The size of the files, without the directories themselves
+    let dir_sizes = tmp_dir.path().metadata()?.len() + tmp_dir.path().join("sub").metadata()?.len();
+    let walk = |count_hardlinks| {
+        let result = Walk::new(
+            vec![tmp_dir.path().to_path_buf()],
+            1,
+            FilesizeType::ApparentSize,
+        )
+        .count_hardlinks(count_hardlinks)
+        .run()
+        .unwrap();
+        assert!(result.errors.is_empty());
+        (
+            result.entries[0].size - dir_sizes,
+            result.entries[0].files,
+            result.stats.shared,
+        )
+    };
+
+    assert_eq!(walk(false), (100, 1, 100));
+    assert_eq!(walk(true), (200, 2, 0));
+
+    Ok(())
+}
+
+#[test]
+fn hardlinks_shared_between_roots() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let (a, b) = (tmp_dir.path().join("a"), tmp_dir.path().join("b"));
+    fs::create_dir(&a)?;
+    fs::create_dir(&b)?;
+    File::create(a.join("file"))?.write_all(&[0u8; 100])?;
+    fs::hard_link(a.join("file"), b.join("file-link"))?;
+
+    let walk = |roots: &[&Path], dedup| {
+        let result = WalkBuilder::new(roots.iter().map(|root| root.to_path_buf()).collect())
+            .threads(4)
+            .filesize_type(FilesizeType::Inodes)
+            .dedup(dedup)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+        assert!(result.errors.is_empty());
+        let sizes: Vec<u64> = result.entries.iter().map(|entry| entry.size).collect();
+        (sizes, result.stats.shared)
+    };
+
+    // The shared file is always counted for the root that was given first
+    for _ in 0..10 {
+        assert_eq!(walk(&[&a, &b], DedupScope::Global), (vec![2, 1], 1));
+        assert_eq!(walk(&[&b, &a], DedupScope::Global), (vec![2, 1], 1));
+    }
+    assert_eq!(walk(&[&a, &b], DedupScope::PerRoot), (vec![2, 2], 0));
+
+    // Hardlinks within a single root are still only counted once
+    fs::hard_link(a.join("file"), a.join("file-link"))?;
+    assert_eq!(walk(&[&a, &b], DedupScope::PerRoot), (vec![2, 2], 1));
+
+    Ok(())
+}
+
+#[test]
+fn overlapping_roots_are_skipped() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let (data, projects) = (
+        tmp_dir.path().join("data"),
+        tmp_dir.path().join("data-projects"),
+    );
+    fs::create_dir_all(data.join("projects"))?;
+    fs::create_dir(&projects)?;
+    File::create(data.join("projects").join("file"))?.write_all(&[0u8; 10])?;
+
+    let walk = |roots: Vec<PathBuf>, allow_overlap| {
+        let result = Walk::new(roots, 1, FilesizeType::Inodes)
+            .allow_overlap(allow_overlap)
+            .run()
+            .unwrap();
+        assert!(result.errors.is_empty());
+        result
+            .entries
+            .into_iter()
+            .map(|entry| (entry.path, entry.size))
+            .collect::<Vec<_>>()
+    };
+
+    // Nested roots are skipped, no matter in which order they are given
+    let nested = vec![data.join("projects"), data.clone()];
+    assert_eq!(walk(nested.clone(), false), [(data.clone(), 3)]);
+    assert_eq!(walk(nested, true).len(), 2);
+
+    // Identical roots are only walked once, even if they are spelled differently
+    let identical = vec![data.clone(), data.join("projects").join("..")];
+    assert_eq!(walk(identical, false), [(data.clone(), 3)]);
+
+    // Siblings whose names start the same way do not overlap
+    let siblings = vec![data.clone(), projects.clone()];
+    assert_eq!(walk(siblings, false), [(data, 3), (projects, 1)]);
+
+    Ok(())
+}
+
+#[test]
+fn duplicate_roots_are_walked_once() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("diskus-tests")?;
+
+    let dir = tmp_dir.path().join("dir");
+    fs::create_dir_all(dir.join("sub"))?;
+    File::create(dir.join("file"))?.write_all(&[0u8; 10])?;
+    File::create(dir.join("sub").join("file"))?.write_all(&[0u8; 5])?;
+
+    let spellings = vec![
+        tmp_dir.path().join(".").join("dir").join(""),
+        dir.clone(),
+        dir.join("."),
+        dir.join("sub").join(".."),
+    ];
+    // Duplicates are collapsed even if overlapping roots are allowed
+    let result = Walk::new(spellings.clone(), 1, FilesizeType::ApparentSize)
+        .allow_overlap(true)
+        .run()?;
+    assert!(result.errors.is_empty());
+    assert_eq!(result.entries.len(), 1);
+    assert_eq!(result.entries[0].path, spellings[0]);
+    assert!(result.entries[0].size >= 15);
 
     Ok(())
 }